@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz NULL;
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
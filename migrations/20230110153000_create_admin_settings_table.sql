@@ -0,0 +1,7 @@
+-- Add migration script here
+-- single row table, the CHECK constraint prevents a second row from being inserted
+CREATE TABLE admin_settings(
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    require_two_factor BOOLEAN NOT NULL DEFAULT FALSE
+);
+INSERT INTO admin_settings (id) VALUES (TRUE);
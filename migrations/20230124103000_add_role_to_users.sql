@@ -0,0 +1,13 @@
+-- Add migration script here
+-- every existing user could do everything so far, they all become owners
+BEGIN;
+    ALTER TABLE users ADD COLUMN role TEXT NULL;
+
+    UPDATE users
+    SET role = 'owner'
+    WHERE role IS NULL;
+
+    ALTER TABLE users ALTER COLUMN role SET NOT NULL;
+    ALTER TABLE users ADD CONSTRAINT users_role_check
+        CHECK (role IN ('owner', 'editor', 'analyst', 'support'));
+COMMIT;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
+ALTER TABLE users ADD COLUMN deactivated_at timestamptz NULL;
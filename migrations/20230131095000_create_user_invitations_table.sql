@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE user_invitations(
+    invitation_id uuid PRIMARY KEY,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL
+    CHECK (role IN ('owner', 'editor', 'analyst', 'support')),
+    invited_by uuid NOT NULL
+    REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    accepted_at timestamptz NULL,
+    revoked_at timestamptz NULL
+);
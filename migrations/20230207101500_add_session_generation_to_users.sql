@@ -0,0 +1,3 @@
+-- Add migration script here
+-- bumped to invalidate every session of a user at once, e.g. after a password reset
+ALTER TABLE users ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE password_reset_tokens(
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL
+    REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE audit_log(
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL,
+    -- NULL for anonymous actions, e.g. a failed login
+    actor_id uuid NULL
+    REFERENCES users (user_id),
+    action TEXT NOT NULL,
+    target_type TEXT NULL,
+    target_id TEXT NULL,
+    ip TEXT NULL,
+    user_agent TEXT NULL,
+    changes JSONB NOT NULL DEFAULT '{}'
+);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+CREATE INDEX audit_log_actor_id_idx ON audit_log (actor_id);
+CREATE INDEX audit_log_action_idx ON audit_log (action);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid PRIMARY KEY,
+    title TEXT NOT NULL,
+    published_by uuid NOT NULL
+    REFERENCES users (user_id),
+    published_at timestamptz NOT NULL
+);
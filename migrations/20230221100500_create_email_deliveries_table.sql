@@ -0,0 +1,22 @@
+-- Add migration script here
+-- one row per email sent to a subscriber
+CREATE TABLE email_deliveries(
+    id BIGSERIAL PRIMARY KEY,
+    subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id),
+    -- 'confirmation' or 'newsletter_issue'
+    kind TEXT NOT NULL,
+    newsletter_issue_id uuid NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+    -- 'sent' or 'failed'
+    status TEXT NOT NULL,
+    -- NULL if the provider did not accept the message
+    provider_message_id TEXT NULL,
+    submitted_at timestamptz NULL,
+    error_code BIGINT NULL,
+    error TEXT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE UNIQUE INDEX email_deliveries_provider_message_id_idx ON email_deliveries (provider_message_id);
+CREATE INDEX email_deliveries_subscriber_id_idx ON email_deliveries (subscriber_id);
+CREATE INDEX email_deliveries_newsletter_issue_id_idx ON email_deliveries (newsletter_issue_id);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- addresses we never email, whoever asks
+CREATE TABLE suppressions(
+    suppression_id uuid PRIMARY KEY,
+    email TEXT NOT NULL,
+    -- 'unsubscribed', 'bounced', 'complained', 'erasure_request' or 'manual'
+    reason TEXT NOT NULL,
+    -- 'admin' or 'postmark'
+    source TEXT NOT NULL,
+    note TEXT NULL,
+    -- NULL unless an admin added the address
+    created_by uuid NULL
+    REFERENCES users (user_id),
+    created_at timestamptz NOT NULL
+);
+CREATE UNIQUE INDEX suppressions_email_idx ON suppressions (lower(email));
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- emails kept by the local mail catcher instead of being sent, never
+-- written to in production
+CREATE TABLE caught_emails(
+    email_id uuid PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    -- the request that would have been sent to Postmark
+    message jsonb NOT NULL,
+    caught_at timestamptz NOT NULL
+);
+CREATE INDEX caught_emails_caught_at_idx ON caught_emails (caught_at);
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- wrong second factor codes in a row, kept here rather than in the session
+-- cookie, which the client can replay
+ALTER TABLE users ADD COLUMN failed_two_factor_attempts INTEGER NOT NULL DEFAULT 0;
+-- no second factor is accepted until then
+ALTER TABLE users ADD COLUMN two_factor_locked_until timestamptz NULL;
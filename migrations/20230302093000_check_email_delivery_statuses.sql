@@ -0,0 +1,5 @@
+-- Add migration script here
+-- 'sent' or 'failed' when submitted, 'suppressed' if it never was, 'bounced'
+-- or 'complained' once the provider's webhooks report it
+ALTER TABLE email_deliveries ADD CONSTRAINT email_deliveries_status_check
+    CHECK (status IN ('sent', 'failed', 'suppressed', 'bounced', 'complained'));
@@ -0,0 +1,273 @@
+//! An append-only record of privileged actions, kept in the `audit_log` table.
+use actix_web::dev::Payload;
+use actix_web::http::header::USER_AGENT;
+use actix_web::{FromRequest, HttpRequest};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+/// Where a request came from, captured next to each audit event.
+#[derive(Clone, Debug, Default)]
+pub struct AuditContext {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AuditContext {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        // the connection's, not the forwarding headers' which anyone can set
+        let ip = request.peer_addr().map(|addr| addr.ip().to_string());
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_owned());
+        Self { ip, user_agent }
+    }
+
+    /// The command line, which has no address of its own.
+    pub fn cli() -> Self {
+        Self {
+            ip: None,
+            user_agent: Some("zero2prod-cli".into()),
+        }
+    }
+}
+
+impl FromRequest for AuditContext {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(AuditContext::from_request(req)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    NewsletterPublish,
+    SubscriberUnsubscribe,
+    SuppressionAdd,
+    SuppressionRemove,
+    SettingsUpdate,
+    LogFilterChange,
+    TwoFactorEnable,
+    TwoFactorDisable,
+    TwoFactorRecoveryCodes,
+    ApiTokenCreate,
+    ApiTokenRevoke,
+    UserInvite,
+    UserInvitationRevoke,
+    UserInvitationAccept,
+    UserRoleChange,
+    UserDeactivate,
+    UserReactivate,
+    PasswordReset,
+    UserCreate,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 22] = [
+        AuditAction::Login,
+        AuditAction::LoginFailed,
+        AuditAction::Logout,
+        AuditAction::NewsletterPublish,
+        AuditAction::SubscriberUnsubscribe,
+        AuditAction::SuppressionAdd,
+        AuditAction::SuppressionRemove,
+        AuditAction::SettingsUpdate,
+        AuditAction::LogFilterChange,
+        AuditAction::TwoFactorEnable,
+        AuditAction::TwoFactorDisable,
+        AuditAction::TwoFactorRecoveryCodes,
+        AuditAction::ApiTokenCreate,
+        AuditAction::ApiTokenRevoke,
+        AuditAction::UserInvite,
+        AuditAction::UserInvitationRevoke,
+        AuditAction::UserInvitationAccept,
+        AuditAction::UserRoleChange,
+        AuditAction::UserDeactivate,
+        AuditAction::UserReactivate,
+        AuditAction::PasswordReset,
+        AuditAction::UserCreate,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::NewsletterPublish => "newsletter.publish",
+            AuditAction::SubscriberUnsubscribe => "subscriber.unsubscribe",
+            AuditAction::SuppressionAdd => "suppression.add",
+            AuditAction::SuppressionRemove => "suppression.remove",
+            AuditAction::SettingsUpdate => "settings.update",
+            AuditAction::LogFilterChange => "log_filter.change",
+            AuditAction::TwoFactorEnable => "two_factor.enable",
+            AuditAction::TwoFactorDisable => "two_factor.disable",
+            AuditAction::TwoFactorRecoveryCodes => "two_factor.recovery_codes",
+            AuditAction::ApiTokenCreate => "api_token.create",
+            AuditAction::ApiTokenRevoke => "api_token.revoke",
+            AuditAction::UserInvite => "user.invite",
+            AuditAction::UserInvitationRevoke => "user.invitation_revoke",
+            AuditAction::UserInvitationAccept => "user.invitation_accept",
+            AuditAction::UserRoleChange => "user.role_change",
+            AuditAction::UserDeactivate => "user.deactivate",
+            AuditAction::UserReactivate => "user.reactivate",
+            AuditAction::PasswordReset => "password.reset",
+            AuditAction::UserCreate => "user.create",
+        }
+    }
+}
+
+/// A single entry for the audit log.
+///
+/// ```ignore
+/// AuditEvent::new(AuditAction::UserRoleChange)
+///     .actor(user_id)
+///     .target("user", target_id)
+///     .changes(json!({"role": {"old": "editor", "new": "owner"}}))
+/// ```
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    actor_id: Option<Uuid>,
+    target_type: Option<&'static str>,
+    target_id: Option<String>,
+    changes: Value,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor_id: None,
+            target_type: None,
+            target_id: None,
+            changes: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn actor(mut self, actor_id: Uuid) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: impl ToString) -> Self {
+        self.target_type = Some(target_type);
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    /// What the action changed, usually as `{"field": {"old": .., "new": ..}}`.
+    pub fn changes(mut self, changes: Value) -> Self {
+        self.changes = changes;
+        self
+    }
+}
+
+#[tracing::instrument(name = "Record an audit event", skip(pool, context))]
+pub async fn record_audit_event(
+    pool: &PgPool,
+    context: &AuditContext,
+    event: AuditEvent,
+) -> Result<(), anyhow::Error> {
+    insert_audit_event(pool, context, event).await
+}
+
+/// Like [`record_audit_event`], committed or rolled back along with the rest
+/// of `transaction`.
+#[tracing::instrument(name = "Record an audit event", skip(transaction, context))]
+pub async fn record_audit_event_in(
+    transaction: &mut Transaction<'_, Postgres>,
+    context: &AuditContext,
+    event: AuditEvent,
+) -> Result<(), anyhow::Error> {
+    insert_audit_event(&mut *transaction, context, event).await
+}
+
+async fn insert_audit_event(
+    executor: impl PgExecutor<'_>,
+    context: &AuditContext,
+    event: AuditEvent,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (occurred_at, actor_id, action, target_type, target_id, ip, user_agent, changes)
+        VALUES (now(), $1, $2, $3, $4, $5, $6, $7)
+        "#,
+        event.actor_id,
+        event.action.as_str(),
+        event.target_type,
+        event.target_id,
+        context.ip,
+        context.user_agent,
+        event.changes,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record an audit event.")?;
+    Ok(())
+}
+
+/// Narrows down the entries returned by [`search_audit_log`].
+#[derive(Debug, Default)]
+pub struct AuditLogFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub struct AuditLogEntry {
+    pub occurred_at: DateTime<Utc>,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub changes: Value,
+}
+
+/// Entries matching `filter`, newest first. `from` is inclusive, `to` is
+/// exclusive.
+#[tracing::instrument(name = "Search the audit log", skip(pool))]
+pub async fn search_audit_log(
+    pool: &PgPool,
+    filter: &AuditLogFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AuditLogEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT a.occurred_at, u.username AS "actor?", a.action, a.target_type,
+            a.target_id, a.ip, a.user_agent, a.changes
+        FROM audit_log a
+        LEFT JOIN users u ON u.user_id = a.actor_id
+        WHERE ($1::TEXT IS NULL OR u.username = $1)
+            AND ($2::TEXT IS NULL OR a.action = $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR a.occurred_at >= $3)
+            AND ($4::TIMESTAMPTZ IS NULL OR a.occurred_at < $4)
+        ORDER BY a.occurred_at DESC, a.id DESC
+        LIMIT $5 OFFSET $6
+        "#,
+        filter.actor,
+        filter.action,
+        filter.from,
+        filter.to,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to search the audit log.")?;
+    Ok(entries)
+}
@@ -0,0 +1,108 @@
+use crate::authentication::api_tokens::{validate_api_token, ApiScope};
+use crate::authentication::{
+    authorize, get_stored_totp, validate_credentials, AuthError, Credentials,
+};
+use crate::configuration::AuthenticationSettings;
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Credentials accepted by the JSON API.
+pub enum ApiCredentials {
+    Basic(Credentials),
+    Bearer(Secret<String>),
+}
+
+/// Extract API credentials from the `Authorization` header, accepting either
+/// the 'Basic' or the 'Bearer' scheme.
+pub fn api_credentials(headers: &HeaderMap) -> Result<ApiCredentials, anyhow::Error> {
+    // header value if present must be a utf-8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 String")?;
+
+    if let Some(token) = header_value.strip_prefix("Bearer ") {
+        return Ok(ApiCredentials::Bearer(Secret::new(
+            token.trim().to_string(),
+        )));
+    }
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was neither 'Basic ' nor 'Bearer '.")?;
+    basic_credentials(base64encoded_segment).map(ApiCredentials::Basic)
+}
+
+fn basic_credentials(base64encoded_segment: &str) -> Result<Credentials, anyhow::Error> {
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("Failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credentials string is not valid UTF8.")?;
+
+    // splitting the decoded credentials into two segments, username and password via : delimiter
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
+
+/// Authenticate a JSON API request, returning the id of the user acting.
+///
+/// API tokens must carry `scope` and, either way, the user's role must grant
+/// the matching permission. 'Basic' credentials act with the full rights
+/// of the user, so they can be switched off and are refused for accounts
+/// protected by a second factor.
+pub async fn authenticate_api_request(
+    credentials: ApiCredentials,
+    pool: &PgPool,
+    scope: ApiScope,
+    settings: &AuthenticationSettings,
+) -> Result<Uuid, AuthError> {
+    let user_id = match credentials {
+        ApiCredentials::Bearer(token) => {
+            let owner = validate_api_token(pool, &token).await?.ok_or_else(|| {
+                AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "Unknown, expired or revoked API token."
+                ))
+            })?;
+            if !owner.has_scope(scope) {
+                return Err(AuthError::Forbidden(anyhow::anyhow!(
+                    "The API token is missing the '{}' scope.",
+                    scope.as_str()
+                )));
+            }
+            owner.user_id
+        }
+        ApiCredentials::Basic(credentials) => {
+            if !settings.basic_auth_enabled {
+                return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "'Basic' authentication is disabled, use an API token."
+                )));
+            }
+            let user_id =
+                validate_credentials(credentials, pool, &settings.password_hashing).await?;
+            // a password alone is not enough for accounts protected by a second factor
+            if get_stored_totp(pool, user_id).await?.is_some() {
+                return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "Two-factor authentication is enabled, 'Basic' credentials are not accepted."
+                )));
+            }
+            user_id
+        }
+    };
+    authorize(pool, user_id, scope.required_permission()).await?;
+    Ok(user_id)
+}
@@ -0,0 +1,239 @@
+use crate::authentication::Permission;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// makes leaked tokens easy to spot, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "z2p_";
+const TOKEN_LENGTH: usize = 40;
+
+/// What an API token is allowed to do.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    NewslettersPublish,
+    SubscribersRead,
+    SubscribersManage,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [
+        ApiScope::NewslettersPublish,
+        ApiScope::SubscribersRead,
+        ApiScope::SubscribersManage,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::NewslettersPublish => "newsletters:publish",
+            ApiScope::SubscribersRead => "subscribers:read",
+            ApiScope::SubscribersManage => "subscribers:manage",
+        }
+    }
+
+    /// A token can never do more than the role of the user who owns it.
+    pub fn required_permission(&self) -> Permission {
+        match self {
+            ApiScope::NewslettersPublish => Permission::PublishNewsletters,
+            ApiScope::SubscribersRead => Permission::ViewSubscribers,
+            ApiScope::SubscribersManage => Permission::ManageSubscribers,
+        }
+    }
+}
+
+impl TryFrom<&str> for ApiScope {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        ApiScope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known API scope.", s))
+    }
+}
+
+pub struct NewApiToken {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+pub struct ApiTokenSummary {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiTokenSummary {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|e| e > Utc::now())
+    }
+}
+
+/// The owner of a valid API token.
+#[derive(Debug)]
+pub struct ApiTokenOwner {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<String>,
+}
+
+impl ApiTokenOwner {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.iter().any(|s| s == scope.as_str())
+    }
+}
+
+/// Store a new token for the user, returning its id and the clear text token.
+/// The clear text token is returned exactly once: only its hash is persisted.
+#[tracing::instrument(name = "Create API token", skip(pool, new_token))]
+pub async fn create_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    new_token: &NewApiToken,
+) -> Result<(Uuid, Secret<String>), anyhow::Error> {
+    let token_id = Uuid::new_v4();
+    let token = generate_api_token();
+    let scopes: Vec<String> = new_token
+        .scopes
+        .iter()
+        .map(|s| s.as_str().to_string())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        token_id,
+        user_id,
+        new_token.name,
+        hash_api_token(token.expose_secret()),
+        &scopes,
+        Utc::now(),
+        new_token.expires_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new API token.")?;
+    Ok((token_id, token))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiTokenSummary>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"
+        SELECT token_id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve API tokens.")?;
+    Ok(tokens)
+}
+
+/// Revoke one of the user's tokens. Returns `false` if the user owns no such token.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = COALESCE(revoked_at, $3)
+        WHERE token_id = $1 AND user_id = $2
+        "#,
+        token_id,
+        user_id,
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token.")?;
+    Ok(updated.rows_affected() == 1)
+}
+
+/// Look up an unrevoked, unexpired token, recording that it has been used.
+#[tracing::instrument(name = "Validate API token", skip(pool, token))]
+pub async fn validate_api_token(
+    pool: &PgPool,
+    token: &Secret<String>,
+) -> Result<Option<ApiTokenOwner>, anyhow::Error> {
+    let now = Utc::now();
+    let owner = sqlx::query_as!(
+        ApiTokenOwner,
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = $2
+        WHERE token_hash = $1
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > $2)
+        RETURNING token_id, user_id, scopes
+        "#,
+        hash_api_token(token.expose_secret()),
+        now,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?;
+    Ok(owner)
+}
+
+fn generate_api_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
+
+// Tokens are long random strings, a fast unsalted hash is enough to keep
+// a database dump from handing them out.
+fn hash_api_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_token, ApiScope};
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn generated_tokens_are_prefixed() {
+        let token = generate_api_token();
+        assert!(token.expose_secret().starts_with("z2p_"));
+        assert_eq!(token.expose_secret().len(), 44);
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in ApiScope::ALL {
+            assert_ok_eq!(ApiScope::try_from(scope.as_str()), scope);
+        }
+    }
+
+    #[test]
+    fn unknown_scopes_are_rejected() {
+        assert_err!(ApiScope::try_from("newsletters:delete"));
+    }
+}
@@ -0,0 +1,283 @@
+use crate::authentication::Role;
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const INVITATION_TTL_HOURS: i64 = 72;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AcceptInvitationError {
+    #[error("This invitation is invalid or has expired.")]
+    InvalidInvitation,
+    #[error("That username is already taken.")]
+    UsernameTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct NewInvitation {
+    pub email: SubscriberEmail,
+    pub role: Role,
+}
+
+pub struct PendingInvitation {
+    pub invitation_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The query string of an invitation link. The tag signs the invitation id and
+/// expiry with the application's HMAC secret, so neither can be tampered with.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct InvitationLink {
+    pub invitation_id: Uuid,
+    pub expires: i64,
+    pub tag: String,
+}
+
+impl InvitationLink {
+    fn new(invitation_id: Uuid, expires_at: DateTime<Utc>, secret: &Secret<String>) -> Self {
+        let expires = expires_at.timestamp();
+        let tag = HEXLOWER.encode(
+            &Self::mac(invitation_id, expires, secret)
+                .finalize()
+                .into_bytes(),
+        );
+        Self {
+            invitation_id,
+            expires,
+            tag,
+        }
+    }
+
+    /// Check the signature and the expiry. The invitation itself may still
+    /// have been used or revoked since.
+    pub fn verify(&self, secret: &Secret<String>) -> bool {
+        let tag = match HEXLOWER.decode(self.tag.as_bytes()) {
+            Ok(tag) => tag,
+            Err(_) => return false,
+        };
+        Self::mac(self.invitation_id, self.expires, secret)
+            .verify_slice(&tag)
+            .is_ok()
+            && self.expires > Utc::now().timestamp()
+    }
+
+    pub fn query_string(&self) -> String {
+        format!(
+            "invitation_id={}&expires={}&tag={}",
+            self.invitation_id, self.expires, self.tag
+        )
+    }
+
+    fn mac(invitation_id: Uuid, expires: i64, secret: &Secret<String>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("invitation:{}:{}", invitation_id, expires).as_bytes());
+        mac
+    }
+}
+
+/// Store a new invitation, returning the signed link to send to the invitee.
+#[tracing::instrument(name = "Create invitation", skip(pool, invitation, secret))]
+pub async fn create_invitation(
+    pool: &PgPool,
+    invited_by: Uuid,
+    invitation: &NewInvitation,
+    secret: &Secret<String>,
+) -> Result<InvitationLink, anyhow::Error> {
+    let invitation_id = Uuid::new_v4();
+    let now = Utc::now();
+    let expires_at = now + Duration::hours(INVITATION_TTL_HOURS);
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (invitation_id, email, role, invited_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        invitation_id,
+        invitation.email.as_ref(),
+        invitation.role.as_str(),
+        invited_by,
+        now,
+        expires_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new invitation.")?;
+    Ok(InvitationLink::new(invitation_id, expires_at, secret))
+}
+
+#[tracing::instrument(name = "List pending invitations", skip(pool))]
+pub async fn list_pending_invitations(
+    pool: &PgPool,
+) -> Result<Vec<PendingInvitation>, anyhow::Error> {
+    let invitations = sqlx::query_as!(
+        PendingInvitation,
+        r#"
+        SELECT invitation_id, email, role, expires_at
+        FROM user_invitations
+        WHERE accepted_at IS NULL AND revoked_at IS NULL AND expires_at > now()
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve pending invitations.")?;
+    Ok(invitations)
+}
+
+/// The email address of an invitation that can still be accepted.
+#[tracing::instrument(name = "Get open invitation", skip(pool))]
+pub async fn get_open_invitation_email(
+    pool: &PgPool,
+    invitation_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email
+        FROM user_invitations
+        WHERE invitation_id = $1
+            AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > now()
+        "#,
+        invitation_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve an invitation.")?;
+    Ok(row.map(|r| r.email))
+}
+
+/// Returns `false` if the invitation had already been accepted or revoked.
+#[tracing::instrument(name = "Revoke invitation", skip(pool))]
+pub async fn revoke_invitation(pool: &PgPool, invitation_id: Uuid) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE user_invitations
+        SET revoked_at = now()
+        WHERE invitation_id = $1 AND accepted_at IS NULL AND revoked_at IS NULL
+        "#,
+        invitation_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an invitation.")?;
+    Ok(updated.rows_affected() == 1)
+}
+
+/// Create the invitee's account with the role they were invited with. An
+/// invitation can only be accepted once.
+#[tracing::instrument(name = "Accept invitation", skip(pool, password_hash))]
+pub async fn accept_invitation(
+    pool: &PgPool,
+    invitation_id: Uuid,
+    username: &str,
+    password_hash: Secret<String>,
+) -> Result<Uuid, AcceptInvitationError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let invitation = sqlx::query!(
+        r#"
+        UPDATE user_invitations
+        SET accepted_at = now()
+        WHERE invitation_id = $1
+            AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > now()
+        RETURNING email, role
+        "#,
+        invitation_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to mark an invitation as accepted.")?
+    .ok_or(AcceptInvitationError::InvalidInvitation)?;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        invitation.role,
+        invitation.email,
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.constraint() == Some("users_username_key") => {
+            AcceptInvitationError::UsernameTaken
+        }
+        sqlx::Error::Database(db) if db.constraint() == Some("users_email_key") => {
+            AcceptInvitationError::InvalidInvitation
+        }
+        _ => AcceptInvitationError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to create the invited user."),
+        ),
+    })?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invitation.")?;
+    Ok(user_id)
+}
+
+/// Whether a user already signed up with the email address.
+#[tracing::instrument(name = "Check if email belongs to a user", skip(pool))]
+pub async fn email_belongs_to_user(pool: &PgPool, email: &str) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT user_id FROM users WHERE email = $1"#, email)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up a user by email.")?;
+    Ok(row.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InvitationLink;
+    use chrono::{Duration, Utc};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-long-and-secret-hmac-key".into())
+    }
+
+    #[test]
+    fn a_signed_link_verifies() {
+        let link = InvitationLink::new(Uuid::new_v4(), Utc::now() + Duration::hours(1), &secret());
+        assert!(link.verify(&secret()));
+    }
+
+    #[test]
+    fn a_link_signed_with_another_secret_is_rejected() {
+        let link = InvitationLink::new(
+            Uuid::new_v4(),
+            Utc::now() + Duration::hours(1),
+            &Secret::new("another-secret".into()),
+        );
+        assert!(!link.verify(&secret()));
+    }
+
+    #[test]
+    fn tampering_with_the_expiry_invalidates_the_link() {
+        let mut link =
+            InvitationLink::new(Uuid::new_v4(), Utc::now() + Duration::hours(1), &secret());
+        link.expires += 3600;
+        assert!(!link.verify(&secret()));
+    }
+
+    #[test]
+    fn an_expired_link_is_rejected() {
+        let link = InvitationLink::new(Uuid::new_v4(), Utc::now() - Duration::hours(1), &secret());
+        assert!(!link.verify(&secret()));
+    }
+}
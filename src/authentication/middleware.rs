@@ -0,0 +1,112 @@
+use crate::authentication::{get_stored_totp, is_valid_session, two_factor_required};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other, see_other_with_flash};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage};
+use sqlx::PgPool;
+use std::ops::Deref;
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Only let through requests carrying a fully authenticated, still valid
+/// session of an active user, making the user id available to handlers as a
+/// `web::ReqData<UserId>`.
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    match session.get_user_id().map_err(e500)? {
+        Some(user_id) => {
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .ok_or_else(|| e500("The connection pool is missing from the application data"))?;
+            // sessions from before generations were recorded count as the first one
+            let generation = session.get_session_generation().map_err(e500)?.unwrap_or(0);
+            // the user may have been deactivated, or reset their password, after logging in
+            if !is_valid_session(pool, user_id, generation)
+                .await
+                .map_err(e500)?
+            {
+                // an error response would skip the session middleware, and
+                // with it the removal of the session cookie
+                session.log_out();
+                return Ok(req.into_response(see_other("/login")).map_into_boxed_body());
+            }
+            if let Some(root_span) = req.extensions().get::<RootSpan>() {
+                root_span.record("user_id", tracing::field::display(&user_id));
+            }
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await.map(|r| r.map_into_boxed_body())
+        }
+        None => {
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("The user has not logged in");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+// pages a user who has not enrolled yet can still reach while 2FA is mandatory
+const ENROLLMENT_PATHS: [&str; 2] = ["/admin/two-factor", "/admin/logout"];
+
+/// When two-factor authentication has been made mandatory, send users who have
+/// not enrolled yet to the enrollment page before anything else.
+///
+/// Must run after `reject_anonymous_users`.
+pub async fn require_two_factor_enrollment(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let exempt = ENROLLMENT_PATHS
+        .iter()
+        .any(|path| req.path().starts_with(path));
+    if !exempt {
+        let user_id = *req
+            .extensions()
+            .get::<UserId>()
+            .copied()
+            .ok_or_else(|| e500("The user id is missing from the request extensions"))?;
+        let pool = req
+            .app_data::<web::Data<PgPool>>()
+            .ok_or_else(|| e500("The connection pool is missing from the application data"))?;
+        if two_factor_required(pool).await.map_err(e500)?
+            && get_stored_totp(pool, user_id)
+                .await
+                .map_err(e500)?
+                .is_none()
+        {
+            let response = see_other_with_flash(
+                "/admin/two-factor",
+                "Two-factor authentication is required. Please set it up to continue.",
+            );
+            let e = anyhow::anyhow!("The user has not enrolled in two-factor authentication");
+            return Err(InternalError::from_response(e, response).into());
+        }
+    }
+    next.call(req).await
+}
@@ -0,0 +1,21 @@
+mod api;
+mod api_tokens;
+mod invitations;
+mod middleware;
+mod password;
+mod password_reset;
+mod roles;
+pub mod totp;
+mod two_factor;
+mod users;
+pub use api::{api_credentials, authenticate_api_request, ApiCredentials};
+pub use api_tokens::*;
+pub use invitations::*;
+pub use middleware::{reject_anonymous_users, require_two_factor_enrollment, UserId};
+pub use password::{
+    compute_password_hash, validate_credentials, validate_new_password, AuthError, Credentials,
+};
+pub use password_reset::*;
+pub use roles::*;
+pub use two_factor::*;
+pub use users::*;
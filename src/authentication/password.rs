@@ -0,0 +1,278 @@
+use crate::configuration::PasswordHashingSettings;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("Insufficient permissions.")]
+    Forbidden(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Check a username and password.
+///
+/// A hash stored with weaker parameters than `hashing` requires is upgraded
+/// along the way, so that the cost can be raised without a password reset.
+#[tracing::instrument(name = "Validating credentials", skip(credentials, pool, hashing))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+) -> Result<uuid::Uuid, AuthError> {
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    let (user_id, expected_password_hash) =
+        match get_stored_credentials(&credentials.username, pool).await? {
+            Some(stored) => stored,
+            None => {
+                // preventing timing attack: take as long as checking a password would
+                spawn_blocking_with_tracing(move || hash_password(&credentials.password, params))
+                    .await
+                    .context("Failed to spawn blocking task")??;
+                return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "Unknown Username."
+                )));
+            }
+        };
+
+    let upgraded_password_hash = spawn_blocking_with_tracing(move || {
+        verify_password_hash(&expected_password_hash, &credentials.password)?;
+        if needs_rehash(&expected_password_hash, &params)? {
+            Ok(Some((
+                expected_password_hash,
+                hash_password(&credentials.password, params)?,
+            )))
+        } else {
+            Ok::<_, AuthError>(None)
+        }
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+
+    if let Some((previous_hash, new_hash)) = upgraded_password_hash {
+        // the login went through, failing to upgrade the hash should not block it
+        if let Err(e) = store_upgraded_password_hash(pool, user_id, previous_hash, new_hash).await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to upgrade a password hash");
+        }
+    }
+    Ok(user_id)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: &Secret<String>,
+    password_candidate: &Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Whether a stored hash was computed with another algorithm or with cheaper
+/// parameters than the current policy.
+fn needs_rehash(password_hash: &Secret<String>, policy: &Params) -> Result<bool, anyhow::Error> {
+    let password_hash = PasswordHash::new(password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return Ok(true);
+    }
+    let params = Params::try_from(&password_hash).context("Failed to parse Argon2 parameters.")?;
+    Ok(params.m_cost() < policy.m_cost()
+        || params.t_cost() < policy.t_cost()
+        || params.p_cost() < policy.p_cost())
+}
+
+#[tracing::instrument(name = "Hash password", skip(password, params))]
+fn hash_password(
+    password: &Secret<String>,
+    params: Params,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .context("Failed to hash password")?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "Store upgraded password hash", skip_all)]
+async fn store_upgraded_password_hash(
+    pool: &PgPool,
+    user_id: uuid::Uuid,
+    previous_hash: Secret<String>,
+    new_hash: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    // leave the password alone if it was changed since we read it
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $3
+        WHERE user_id = $1 AND password_hash = $2
+        "#,
+        user_id,
+        previous_hash.expose_secret(),
+        new_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an upgraded password hash.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1 AND deactivated_at IS NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate auth credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+/// Hash a new password, on a blocking thread since Argon2 is deliberately slow.
+pub async fn compute_password_hash(
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
+    let params = hashing
+        .params()
+        .context("Invalid password hashing parameters.")?;
+    spawn_blocking_with_tracing(move || hash_password(&password, params))
+        .await
+        .context("Failed to spawn blocking task")?
+}
+
+/// Check a password chosen by a user, typed twice to rule out typos.
+pub fn validate_new_password(
+    password: &Secret<String>,
+    password_check: &Secret<String>,
+) -> Result<(), String> {
+    let password_length = password.expose_secret().graphemes(true).count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password_length) {
+        return Err(format!(
+            "Passwords must be between {} and {} characters long.",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        return Err("You entered two different passwords - the field values must match.".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_password, needs_rehash, validate_new_password};
+    use argon2::Params;
+    use claim::{assert_err, assert_ok, assert_ok_eq};
+    use secrecy::Secret;
+
+    fn secret(s: &str) -> Secret<String> {
+        Secret::new(s.to_string())
+    }
+
+    #[test]
+    fn a_long_enough_matching_password_is_accepted() {
+        assert_ok!(validate_new_password(
+            &secret("correct-horse-battery"),
+            &secret("correct-horse-battery")
+        ));
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert_err!(validate_new_password(&secret("short"), &secret("short")));
+    }
+
+    #[test]
+    fn overly_long_passwords_are_rejected() {
+        let password = "a".repeat(129);
+        assert_err!(validate_new_password(
+            &secret(&password),
+            &secret(&password)
+        ));
+    }
+
+    #[test]
+    fn mismatched_passwords_are_rejected() {
+        assert_err!(validate_new_password(
+            &secret("correct-horse-battery"),
+            &secret("correct-horse-staple")
+        ));
+    }
+
+    fn params(m_cost: u32, t_cost: u32, p_cost: u32) -> Params {
+        Params::new(m_cost, t_cost, p_cost, None).unwrap()
+    }
+
+    #[test]
+    fn a_hash_matching_the_policy_is_kept() {
+        let hash = hash_password(&secret("password"), params(1024, 2, 1)).unwrap();
+        assert_ok_eq!(needs_rehash(&hash, &params(1024, 2, 1)), false);
+    }
+
+    #[test]
+    fn a_hash_cheaper_than_the_policy_is_upgraded() {
+        let hash = hash_password(&secret("password"), params(1024, 2, 1)).unwrap();
+        assert_ok_eq!(needs_rehash(&hash, &params(2048, 2, 1)), true);
+        assert_ok_eq!(needs_rehash(&hash, &params(1024, 3, 1)), true);
+        assert_ok_eq!(needs_rehash(&hash, &params(1024, 2, 2)), true);
+    }
+
+    #[test]
+    fn a_hash_costlier_than_the_policy_is_kept() {
+        let hash = hash_password(&secret("password"), params(2048, 3, 1)).unwrap();
+        assert_ok_eq!(needs_rehash(&hash, &params(1024, 2, 1)), false);
+    }
+
+    #[test]
+    fn hashes_from_other_argon2_variants_are_upgraded() {
+        let hash = Secret::new(
+            "$argon2i$v=19$m=15000,t=2,p=1$\
+gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+                .to_string(),
+        );
+        assert_ok_eq!(needs_rehash(&hash, &params(15000, 2, 1)), true);
+    }
+}
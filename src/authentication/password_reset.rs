@@ -0,0 +1,149 @@
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use data_encoding::HEXLOWER;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+const RESET_TOKEN_LENGTH: usize = 40;
+
+/// Issue a reset token for the active user registered with `email`.
+///
+/// Returns `None` when there is no such user: callers must not let that show
+/// in their response, or the form becomes a way to probe for accounts.
+#[tracing::instrument(name = "Create password reset token", skip(pool, email))]
+pub async fn create_password_reset_token(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let user = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE email = $1 AND deactivated_at IS NULL"#,
+        email
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by email.")?;
+    let user_id = match user {
+        Some(user) => user.user_id,
+        None => return Ok(None),
+    };
+    let token = generate_reset_token();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_reset_token(token.expose_secret()),
+        user_id,
+        now,
+        now + Duration::minutes(RESET_TOKEN_TTL_MINUTES),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(Some(token))
+}
+
+/// Whether the token can still be used to reset a password.
+#[tracing::instrument(name = "Check password reset token", skip(pool, token))]
+pub async fn is_valid_password_reset_token(
+    pool: &PgPool,
+    token: &Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM password_reset_tokens
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        "#,
+        hash_reset_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a password reset token.")?;
+    Ok(row.is_some())
+}
+
+/// Set a new password with a reset token, which is used up in the process.
+///
+/// All of the user's outstanding reset tokens are voided and every session
+/// they have open is invalidated. Returns `None` if the token is not valid.
+#[tracing::instrument(name = "Reset password", skip(pool, token, password_hash))]
+pub async fn reset_password(
+    pool: &PgPool,
+    token: &Secret<String>,
+    password_hash: Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let user_id = match sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_reset_token(token.expose_secret())
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to consume a password reset token.")?
+    {
+        Some(row) => row.user_id,
+        None => return Ok(None),
+    };
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $2, session_generation = session_generation + 1
+        WHERE user_id = $1 AND deactivated_at IS NULL
+        "#,
+        user_id,
+        password_hash.expose_secret(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to change the user's password.")?;
+    if updated.rows_affected() != 1 {
+        // the user was deactivated after asking for the reset
+        return Ok(None);
+    }
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to void outstanding password reset tokens.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+    Ok(Some(user_id))
+}
+
+fn generate_reset_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    Secret::new(
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(RESET_TOKEN_LENGTH)
+            .collect(),
+    )
+}
+
+// like API tokens, reset tokens are long and random: a fast hash is enough
+fn hash_reset_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
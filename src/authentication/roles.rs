@@ -0,0 +1,279 @@
+use crate::authentication::{AuthError, UserId};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpMessage, HttpResponse};
+use anyhow::Context;
+use futures_util::future::LocalBoxFuture;
+use sqlx::PgPool;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+/// What an admin user is allowed to do, stored in `users.role`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Everything, including managing other users and settings.
+    Owner,
+    /// Drafts and publishes newsletter issues.
+    Editor,
+    /// Read-only access to statistics.
+    Analyst,
+    /// Looks after subscribers.
+    Support,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Permission {
+    PublishNewsletters,
+    ViewStats,
+    ViewSubscribers,
+    ManageSubscribers,
+    ManageUsers,
+    ManageSettings,
+    ViewAuditLog,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Editor, Role::Analyst, Role::Support];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Analyst => "analyst",
+            Role::Support => "support",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission::*;
+        match self {
+            Role::Owner => true,
+            Role::Editor => matches!(permission, PublishNewsletters),
+            Role::Analyst => matches!(permission, ViewStats),
+            Role::Support => matches!(permission, ViewSubscribers | ManageSubscribers),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s.to_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "{} is not a supported role. Use one of `owner`, `editor`, `analyst` or `support`.",
+                    s
+                )
+            })
+    }
+}
+
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::PublishNewsletters,
+        Permission::ViewStats,
+        Permission::ViewSubscribers,
+        Permission::ManageSubscribers,
+        Permission::ManageUsers,
+        Permission::ManageSettings,
+        Permission::ViewAuditLog,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::PublishNewsletters => "publish_newsletters",
+            Permission::ViewStats => "view_stats",
+            Permission::ViewSubscribers => "view_subscribers",
+            Permission::ManageSubscribers => "manage_subscribers",
+            Permission::ManageUsers => "manage_users",
+            Permission::ManageSettings => "manage_settings",
+            Permission::ViewAuditLog => "view_audit_log",
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get user role", skip(pool))]
+pub async fn get_user_role(pool: &PgPool, user_id: Uuid) -> Result<Option<Role>, anyhow::Error> {
+    // deactivated users have no role, and therefore no permissions
+    let row = sqlx::query!(
+        r#"SELECT role FROM users WHERE user_id = $1 AND deactivated_at IS NULL"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the user's role.")?;
+    row.map(|r| Role::try_from(r.role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// Check that the user's role grants `permission`.
+///
+/// The role is read on every call, so a change of role applies to existing
+/// sessions and API tokens straight away.
+#[tracing::instrument(name = "Authorize", skip(pool))]
+pub async fn authorize(
+    pool: &PgPool,
+    user_id: Uuid,
+    permission: Permission,
+) -> Result<Role, AuthError> {
+    let role = get_user_role(pool, user_id)
+        .await?
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown user.")))?;
+    if role.can(permission) {
+        Ok(role)
+    } else {
+        Err(AuthError::Forbidden(anyhow::anyhow!(
+            "The '{}' role does not grant '{}'.",
+            role.as_str(),
+            permission.as_str()
+        )))
+    }
+}
+
+/// Note a denied permission on the request's root span, so that it shows up
+/// next to the status code in the request logs.
+pub fn record_denial(root_span: &RootSpan, permission: Permission) {
+    root_span.record("permission_denied", permission.as_str());
+}
+
+/// Middleware guarding admin routes: responds with a 403 unless the logged in
+/// user's role grants the permission.
+///
+/// Must run after `reject_anonymous_users`.
+#[derive(Copy, Clone)]
+pub struct RequirePermission(pub Permission);
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequirePermissionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequirePermissionMiddleware {
+            service: Rc::new(service),
+            permission: self.0,
+        }))
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<S>,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let permission = self.permission;
+        Box::pin(async move {
+            let user_id = req.extensions().get::<UserId>().copied().ok_or_else(|| {
+                crate::utils::e500("The user id is missing from the request extensions")
+            })?;
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .cloned()
+                .ok_or_else(|| {
+                    crate::utils::e500("The connection pool is missing from the application data")
+                })?;
+            match authorize(&pool, *user_id, permission).await {
+                Ok(_) => service.call(req).await.map(|r| r.map_into_left_body()),
+                Err(AuthError::UnexpectedError(e)) => Err(crate::utils::e500(e)),
+                Err(e) => {
+                    if let Some(root_span) = req.extensions().get::<RootSpan>() {
+                        record_denial(root_span, permission);
+                    }
+                    tracing::warn!(error.cause_chain = ?e, "Permission denied");
+                    let response = HttpResponse::Forbidden().body("Insufficient permissions.");
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+    use claim::{assert_err, assert_ok_eq};
+
+    // the roles granted `permission`, a new permission does not compile
+    // until it is listed here
+    fn granted_to(permission: Permission) -> &'static [Role] {
+        match permission {
+            Permission::PublishNewsletters => &[Role::Owner, Role::Editor],
+            Permission::ViewStats => &[Role::Owner, Role::Analyst],
+            Permission::ViewSubscribers => &[Role::Owner, Role::Support],
+            Permission::ManageSubscribers => &[Role::Owner, Role::Support],
+            Permission::ManageUsers => &[Role::Owner],
+            Permission::ManageSettings => &[Role::Owner],
+            Permission::ViewAuditLog => &[Role::Owner],
+        }
+    }
+
+    #[test]
+    fn every_role_has_exactly_its_permissions() {
+        for role in Role::ALL {
+            for permission in Permission::ALL {
+                assert_eq!(
+                    role.can(permission),
+                    granted_to(permission).contains(&role),
+                    "{} and {}",
+                    role.as_str(),
+                    permission.as_str()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn owners_can_do_everything() {
+        for permission in Permission::ALL {
+            assert!(Role::Owner.can(permission));
+        }
+    }
+
+    #[test]
+    fn analysts_are_read_only() {
+        assert!(Role::Analyst.can(Permission::ViewStats));
+        assert!(!Role::Analyst.can(Permission::PublishNewsletters));
+        assert!(!Role::Analyst.can(Permission::ManageSubscribers));
+    }
+
+    #[test]
+    fn only_owners_manage_users_and_settings() {
+        for role in [Role::Editor, Role::Analyst, Role::Support] {
+            assert!(!role.can(Permission::ManageUsers));
+            assert!(!role.can(Permission::ManageSettings));
+            assert!(!role.can(Permission::ViewAuditLog));
+        }
+    }
+
+    #[test]
+    fn roles_are_parsed_from_their_names() {
+        for role in Role::ALL {
+            assert_ok_eq!(Role::try_from(role.as_str().to_string()), role);
+        }
+        assert_err!(Role::try_from("admin".to_string()));
+    }
+}
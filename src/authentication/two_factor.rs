@@ -0,0 +1,384 @@
+use crate::authentication::totp::{self, TotpSecret};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use data_encoding::HEXLOWER;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Wrong second factor codes in a row before the second factor is locked.
+const MAX_FAILED_SECOND_FACTOR_ATTEMPTS: i32 = 5;
+const SECOND_FACTOR_LOCKOUT_MINUTES: i64 = 15;
+
+pub struct StoredTotp {
+    pub secret: TotpSecret,
+    pub last_used_step: Option<u64>,
+}
+
+#[tracing::instrument(name = "Get stored TOTP secret", skip(pool))]
+pub async fn get_stored_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<StoredTotp>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_used_step
+        FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NOT NULL
+        "#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the stored TOTP secret.")?;
+
+    match row.and_then(|r| r.totp_secret.map(|s| (s, r.totp_last_used_step))) {
+        None => Ok(None),
+        Some((secret, last_used_step)) => Ok(Some(StoredTotp {
+            secret: TotpSecret::parse(secret).map_err(anyhow::Error::msg)?,
+            last_used_step: last_used_step.map(|s| s as u64),
+        })),
+    }
+}
+
+/// Verify the second factor of a login: either a code from the user's
+/// authenticator app or one of their unused recovery codes.
+///
+/// Returns `false` for a wrong, replayed or already used code.
+#[tracing::instrument(name = "Verify second factor", skip(pool, code))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    if verify_totp_code(pool, user_id, code).await? {
+        return Ok(true);
+    }
+    consume_recovery_code(pool, user_id, code).await
+}
+
+/// Whether the user's second factor is locked after too many wrong codes.
+#[tracing::instrument(name = "Check second factor lockout", skip(pool))]
+pub async fn second_factor_locked(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT two_factor_locked_until FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to check the second factor lockout.")?;
+    Ok(row
+        .two_factor_locked_until
+        .is_some_and(|locked_until| locked_until > Utc::now()))
+}
+
+/// Count a wrong second factor code against the user, locking their second
+/// factor once there are too many in a row. Returns whether it is locked.
+///
+/// The count is kept on the server: a session cookie can be replayed to
+/// reset anything stored in it.
+#[tracing::instrument(name = "Record failed second factor", skip(pool))]
+pub async fn record_failed_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE users
+        SET failed_two_factor_attempts = CASE
+                WHEN failed_two_factor_attempts + 1 >= $2 THEN 0
+                ELSE failed_two_factor_attempts + 1
+            END,
+            two_factor_locked_until = CASE
+                WHEN failed_two_factor_attempts + 1 >= $2 THEN $3
+                ELSE two_factor_locked_until
+            END
+        WHERE user_id = $1
+        RETURNING two_factor_locked_until
+        "#,
+        user_id,
+        MAX_FAILED_SECOND_FACTOR_ATTEMPTS,
+        Utc::now() + Duration::minutes(SECOND_FACTOR_LOCKOUT_MINUTES),
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to record a failed second factor.")?;
+    Ok(row
+        .two_factor_locked_until
+        .is_some_and(|locked_until| locked_until > Utc::now()))
+}
+
+/// Forget about the wrong codes once the user gets one right.
+#[tracing::instrument(name = "Reset failed second factors", skip(pool))]
+pub async fn reset_failed_second_factors(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE users SET failed_two_factor_attempts = 0 WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reset the failed second factors.")?;
+    Ok(())
+}
+
+/// Verify a code from the user's authenticator app, recording the step it
+/// belongs to so that it cannot be used a second time.
+#[tracing::instrument(name = "Verify TOTP code", skip(pool, code))]
+pub async fn verify_totp_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let stored = match get_stored_totp(pool, user_id).await? {
+        Some(stored) => stored,
+        None => return Ok(false),
+    };
+    let step = match stored
+        .secret
+        .verify(code, totp::now(), stored.last_used_step)
+    {
+        Some(step) => step as i64,
+        None => return Ok(false),
+    };
+    // guard against two concurrent logins racing with the same code
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_used_step = $2
+        WHERE user_id = $1
+            AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+        "#,
+        user_id,
+        step,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the last used TOTP step.")?;
+    Ok(updated.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Consume recovery code", skip(pool, code))]
+async fn consume_recovery_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = $3
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code),
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to consume a recovery code.")?;
+    Ok(updated.rows_affected() == 1)
+}
+
+/// Turn on two-factor authentication for the user, returning a fresh set of
+/// recovery codes. The codes are only stored hashed, so this is the one
+/// chance to show them.
+#[tracing::instrument(name = "Enable TOTP", skip(pool, secret))]
+pub async fn enable_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: &TotpSecret,
+    verified_step: u64,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_enabled_at = $3, totp_last_used_step = $4
+        WHERE user_id = $1
+        "#,
+        user_id,
+        secret.as_base32().expose_secret(),
+        Utc::now(),
+        verified_step as i64,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the TOTP secret.")?;
+    let recovery_codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable TOTP.")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable TOTP", skip(pool))]
+pub async fn disable_totp(pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove the TOTP secret.")?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable TOTP.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Regenerate recovery codes", skip(pool))]
+pub async fn regenerate_recovery_codes(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let recovery_codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store recovery codes.")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Count unused recovery codes", skip(pool))]
+pub async fn count_unused_recovery_codes(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM user_recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count unused recovery codes.")?;
+    Ok(row.count)
+}
+
+async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete previous recovery codes.")?;
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    for code in &recovery_codes {
+        sqlx::query!(
+            r#"INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)"#,
+            user_id,
+            hash_recovery_code(code),
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a recovery code.")?;
+    }
+    Ok(recovery_codes)
+}
+
+/// Whether an owner has made two-factor authentication mandatory for every admin user.
+#[tracing::instrument(name = "Check if two-factor authentication is required", skip(pool))]
+pub async fn two_factor_required(pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT require_two_factor FROM admin_settings"#)
+        .fetch_one(pool)
+        .await
+        .context("Failed to read the admin settings.")?;
+    Ok(row.require_two_factor)
+}
+
+#[tracing::instrument(name = "Update two-factor requirement", skip(pool))]
+pub async fn set_two_factor_required(pool: &PgPool, required: bool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE admin_settings SET require_two_factor = $1"#,
+        required
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the admin settings.")?;
+    Ok(())
+}
+
+// Recovery codes are two groups of five lowercase alphanumeric characters,
+// e.g. `k3f9a-0pq7z`, short enough to be typed by hand.
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    let mut group = || -> String {
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(|c| char::from(c).to_ascii_lowercase())
+            .take(5)
+            .collect()
+    };
+    format!("{}-{}", group(), group())
+}
+
+// Recovery codes carry ~50 bits of entropy each, so a fast hash is enough to
+// avoid keeping them in clear text.
+fn hash_recovery_code(code: &str) -> String {
+    let normalised: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    HEXLOWER.encode(&Sha256::digest(normalised.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_code, hash_recovery_code};
+
+    #[test]
+    fn recovery_codes_have_the_expected_shape() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(code.chars().nth(5), Some('-'));
+    }
+
+    #[test]
+    fn recovery_code_hashes_ignore_case_and_whitespace() {
+        assert_eq!(
+            hash_recovery_code("k3f9a-0pq7z"),
+            hash_recovery_code(" K3F9A-0PQ7Z\n")
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::authentication::Role;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+pub struct UserSummary {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub deactivated_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "List users", skip(pool))]
+pub async fn list_users(pool: &PgPool) -> Result<Vec<UserSummary>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT user_id, username, email, role, deactivated_at
+        FROM users
+        ORDER BY username
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve users.")?;
+    Ok(users)
+}
+
+/// Whether a session of the user, started at `session_generation`, is still
+/// valid: the user exists, has not been deactivated and has not invalidated
+/// their sessions since, e.g. by resetting their password.
+#[tracing::instrument(name = "Check if user session is valid", skip(pool))]
+pub async fn is_valid_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_generation: i32,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT deactivated_at, session_generation FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check if the user's session is valid.")?;
+    Ok(row
+        .is_some_and(|r| r.deactivated_at.is_none() && r.session_generation == session_generation))
+}
+
+#[tracing::instrument(name = "Get session generation", skip(pool))]
+pub async fn get_session_generation(pool: &PgPool, user_id: Uuid) -> Result<i32, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT session_generation FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the user's session generation.")?;
+    Ok(row.session_generation)
+}
+
+/// Returns the previous role, or `None` if there is no such user.
+#[tracing::instrument(name = "Set user role", skip(pool))]
+pub async fn set_user_role(
+    pool: &PgPool,
+    user_id: Uuid,
+    role: Role,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE users u SET role = $2
+        FROM (SELECT user_id, role FROM users WHERE user_id = $1 FOR UPDATE) old
+        WHERE u.user_id = old.user_id
+        RETURNING old.role AS previous_role
+        "#,
+        user_id,
+        role.as_str()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to update the user's role.")?;
+    Ok(row.map(|r| r.previous_role))
+}
+
+/// Deactivated users can no longer log in, their sessions are rejected and
+/// their API tokens stop working. Returns `false` if there is no such user.
+#[tracing::instrument(name = "Set user deactivated", skip(pool))]
+pub async fn set_user_deactivated(
+    pool: &PgPool,
+    user_id: Uuid,
+    deactivated: bool,
+) -> Result<bool, anyhow::Error> {
+    let deactivated_at = deactivated.then(Utc::now);
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET deactivated_at = CASE WHEN $2::timestamptz IS NULL THEN NULL
+            ELSE COALESCE(deactivated_at, $2) END
+        WHERE user_id = $1
+        "#,
+        user_id,
+        deactivated_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the user's status.")?;
+    Ok(updated.rows_affected() == 1)
+}
+
+/// Create a user directly, bypassing invitations. Used to bootstrap an
+/// environment from the command line.
+#[tracing::instrument(name = "Create user", skip(transaction, password_hash))]
+pub async fn create_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    email: Option<&str>,
+    password_hash: Secret<String>,
+    role: Role,
+) -> Result<Uuid, anyhow::Error> {
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        role.as_str(),
+        email,
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.constraint() == Some("users_username_key") => {
+            anyhow::anyhow!("There is already a user called {}.", username)
+        }
+        sqlx::Error::Database(db) if db.constraint() == Some("users_email_key") => {
+            anyhow::anyhow!("There is already a user with that email address.")
+        }
+        _ => anyhow::Error::new(e).context("Failed to create a user."),
+    })?;
+    Ok(user_id)
+}
+
+/// Replace the user's password and log them out everywhere. Returns `None`
+/// if there is no such user.
+#[tracing::instrument(name = "Set password", skip(transaction, password_hash))]
+pub async fn set_password(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    password_hash: Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $2, session_generation = session_generation + 1
+        WHERE username = $1
+        RETURNING user_id
+        "#,
+        username,
+        password_hash.expose_secret(),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to change the user's password.")?;
+    Ok(row.map(|r| r.user_id))
+}
@@ -0,0 +1,384 @@
+//! Subcommands of the `zero2prod` binary, to operate an environment without
+//! reaching for psql.
+use crate::audit::{record_audit_event_in, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    compute_password_hash, create_user, set_password, validate_new_password, Role,
+};
+use crate::configuration::{PasswordHashingSettings, Settings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, SentEmail};
+use crate::migrations::run_migrations;
+use crate::startup::{get_connection_pool, Application, MIN_HMAC_SECRET_LENGTH};
+use crate::telemetry::LogFilter;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::io::{BufRead, Write};
+use uuid::Uuid;
+
+// generated passwords are comfortably above the minimum length
+const GENERATED_PASSWORD_LENGTH: usize = 24;
+
+#[derive(Parser, Debug)]
+#[command(name = "zero2prod", version, about = "A newsletter delivery service")]
+pub struct Cli {
+    /// Defaults to `serve`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the HTTP server.
+    Serve,
+    /// Apply the pending database migrations.
+    Migrate,
+    /// Create a user with the owner role.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+        /// Used for password resets.
+        #[arg(long)]
+        email: Option<String>,
+        /// Read the password from the first line of stdin instead of
+        /// generating one.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Set a new password for a user and log them out everywhere.
+    ResetPassword {
+        #[arg(long)]
+        username: String,
+        /// Read the password from the first line of stdin instead of
+        /// generating one.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Print subscribers as tab separated values.
+    ListSubscribers {
+        /// Only list subscribers with this status, e.g. `confirmed`.
+        #[arg(long)]
+        status: Option<String>,
+    },
+    /// Send an email through the configured provider.
+    SendTestEmail {
+        #[arg(long)]
+        to: String,
+    },
+    /// Validate the configuration and check that the database is reachable.
+    CheckConfig,
+}
+
+impl Cli {
+    /// Run the command against an already loaded configuration. The server
+    /// lets admins change `log_filter` at runtime.
+    pub async fn run(
+        self,
+        configuration: Settings,
+        log_filter: LogFilter,
+    ) -> Result<(), anyhow::Error> {
+        let mut stdout = std::io::stdout();
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => {
+                let application = Application::build(configuration, log_filter).await?;
+                application.run_until_stopped().await?;
+            }
+            Command::Migrate => {
+                let pool = get_connection_pool(&configuration.database);
+                run_migrations(&pool).await?;
+                writeln!(stdout, "The database is up to date.")?;
+            }
+            Command::CreateAdmin {
+                username,
+                email,
+                password_stdin,
+            } => {
+                let pool = get_connection_pool(&configuration.database);
+                let (password, generated) = read_or_generate_password(password_stdin)?;
+                create_admin(
+                    &pool,
+                    &configuration.authentication.password_hashing,
+                    &username,
+                    email.as_deref(),
+                    password.clone(),
+                )
+                .await?;
+                writeln!(stdout, "Created {} with the owner role.", username)?;
+                if generated {
+                    writeln!(stdout, "Password: {}", password.expose_secret())?;
+                }
+            }
+            Command::ResetPassword {
+                username,
+                password_stdin,
+            } => {
+                let pool = get_connection_pool(&configuration.database);
+                let (password, generated) = read_or_generate_password(password_stdin)?;
+                reset_password(
+                    &pool,
+                    &configuration.authentication.password_hashing,
+                    &username,
+                    password.clone(),
+                )
+                .await?;
+                writeln!(stdout, "The password of {} has been reset.", username)?;
+                if generated {
+                    writeln!(stdout, "Password: {}", password.expose_secret())?;
+                }
+            }
+            Command::ListSubscribers { status } => {
+                let pool = get_connection_pool(&configuration.database);
+                list_subscribers(&pool, status.as_deref(), &mut stdout).await?;
+            }
+            Command::SendTestEmail { to } => {
+                let recipient = SubscriberEmail::parse(to).map_err(anyhow::Error::msg)?;
+                let pool = get_connection_pool(&configuration.database);
+                let email_client = configuration
+                    .email_client(pool)
+                    .map_err(anyhow::Error::msg)?;
+                let sent = send_test_email(&email_client, &recipient).await?;
+                writeln!(stdout, "A test email has been sent to {}.", recipient)?;
+                if let Some(message_id) = sent.message_id {
+                    writeln!(stdout, "Message id: {}", message_id)?;
+                }
+            }
+            Command::CheckConfig => check_config(&configuration, &mut stdout).await?,
+        }
+        Ok(())
+    }
+}
+
+fn read_or_generate_password(from_stdin: bool) -> Result<(Secret<String>, bool), anyhow::Error> {
+    if from_stdin {
+        let mut line = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .context("Failed to read the password from stdin.")?;
+        let password = line.trim_end_matches(['\r', '\n']).to_string();
+        Ok((Secret::new(password), false))
+    } else {
+        let password = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(GENERATED_PASSWORD_LENGTH)
+            .collect();
+        Ok((Secret::new(password), true))
+    }
+}
+
+#[tracing::instrument(name = "Create an admin", skip(pool, password_hashing, password))]
+pub async fn create_admin(
+    pool: &PgPool,
+    password_hashing: &PasswordHashingSettings,
+    username: &str,
+    email: Option<&str>,
+    password: Secret<String>,
+) -> Result<Uuid, anyhow::Error> {
+    let username = username.trim();
+    if username.is_empty() {
+        anyhow::bail!("The username cannot be empty.");
+    }
+    let email = email
+        .map(|e| SubscriberEmail::parse(e.trim().to_string()))
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    validate_new_password(&password, &password).map_err(anyhow::Error::msg)?;
+    let password_hash = compute_password_hash(password, password_hashing).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection.")?;
+    let user_id = create_user(
+        &mut transaction,
+        username,
+        email.as_ref().map(|e| e.as_ref()),
+        password_hash,
+        Role::Owner,
+    )
+    .await?;
+    let event = AuditEvent::new(AuditAction::UserCreate)
+        .target("user", user_id)
+        .changes(serde_json::json!({"username": username, "role": Role::Owner.as_str()}));
+    record_audit_event_in(&mut transaction, &AuditContext::cli(), event).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new user.")?;
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "Reset a password", skip(pool, password_hashing, password))]
+pub async fn reset_password(
+    pool: &PgPool,
+    password_hashing: &PasswordHashingSettings,
+    username: &str,
+    password: Secret<String>,
+) -> Result<Uuid, anyhow::Error> {
+    validate_new_password(&password, &password).map_err(anyhow::Error::msg)?;
+    let password_hash = compute_password_hash(password, password_hashing).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection.")?;
+    let user_id = set_password(&mut transaction, username, password_hash)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("There is no user called {}.", username))?;
+    let event = AuditEvent::new(AuditAction::PasswordReset).target("user", user_id);
+    record_audit_event_in(&mut transaction, &AuditContext::cli(), event).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new password.")?;
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "List subscribers", skip(pool, out))]
+pub async fn list_subscribers(
+    pool: &PgPool,
+    status: Option<&str>,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    struct Row {
+        email: String,
+        name: String,
+        status: String,
+        subscribed_at: DateTime<Utc>,
+    }
+    let rows = sqlx::query_as!(
+        Row,
+        r#"
+        SELECT email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+        ORDER BY subscribed_at
+        "#,
+        status
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscribers.")?;
+    writeln!(out, "email\tname\tstatus\tsubscribed_at")?;
+    for row in rows {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            row.email,
+            row.name,
+            row.status,
+            row.subscribed_at.to_rfc3339()
+        )?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Send a test email", skip(email_client))]
+pub async fn send_test_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+) -> Result<SentEmail, anyhow::Error> {
+    email_client
+        .send_email(
+            recipient,
+            "Test email from zero2prod",
+            "If you can read this, email delivery is working.",
+            "If you can read this, email delivery is working.",
+        )
+        .await
+        .context("Failed to send a test email.")
+}
+
+/// Print one line per check, failing if any of them did.
+pub async fn check_config(
+    configuration: &Settings,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let mut checks: Vec<(&str, Result<(), String>)> = vec![
+        (
+            "application.base_url",
+            reqwest::Url::parse(&configuration.application.base_url)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        ),
+        (
+            "application.hmac_secret",
+            if configuration.application.hmac_secret.expose_secret().len() >= MIN_HMAC_SECRET_LENGTH
+            {
+                Ok(())
+            } else {
+                Err(format!(
+                    "must be at least {} bytes long",
+                    MIN_HMAC_SECRET_LENGTH
+                ))
+            },
+        ),
+        (
+            "email_client.base_url",
+            reqwest::Url::parse(&configuration.email_client.base_url)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        ),
+        (
+            "email_client.sender_email",
+            configuration.email_client.sender().map(|_| ()),
+        ),
+        (
+            "email_client.limits",
+            configuration.email_client.limits.validate(),
+        ),
+        (
+            "email_client.circuit_breaker",
+            match &configuration.email_client.circuit_breaker {
+                Some(circuit_breaker) => circuit_breaker.validate(),
+                None => Ok(()),
+            },
+        ),
+        ("email_client.mailbox", configuration.mailbox().map(|_| ())),
+        (
+            "email_client.secondary.base_url",
+            match &configuration.email_client.secondary {
+                Some(secondary) => reqwest::Url::parse(&secondary.base_url)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                None => Ok(()),
+            },
+        ),
+        (
+            "authentication.password_hashing",
+            configuration
+                .authentication
+                .password_hashing
+                .params()
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        ),
+    ];
+    let pool = get_connection_pool(&configuration.database);
+    checks.push((
+        "database",
+        sqlx::query("SELECT 1")
+            .execute(&pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    ));
+
+    let mut failures = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => writeln!(out, "ok     {}", name)?,
+            Err(e) => {
+                failures += 1;
+                writeln!(out, "error  {}: {}", name, e)?
+            }
+        }
+    }
+    if failures > 0 {
+        anyhow::bail!("{} configuration check(s) failed.", failures);
+    }
+    Ok(())
+}
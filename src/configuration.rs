@@ -1,16 +1,24 @@
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
-use sqlx::ConnectOptions;
+use sqlx::{ConnectOptions, PgPool};
 
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{CircuitBreakerPolicy, EmailClient, Mailbox, RetryPolicy, SendLimits};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
+    /// Set from `APP_ENVIRONMENT`.
+    pub environment: Environment,
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub authentication: AuthenticationSettings,
+    pub metrics: MetricsSettings,
+    pub telemetry: TelemetrySettings,
+    pub webhooks: WebhookSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -32,6 +40,33 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Apply the embedded migrations before accepting requests.
+    pub run_migrations_on_startup: bool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct AuthenticationSettings {
+    /// Accept username/password 'Basic' credentials on the JSON API, next to API tokens.
+    pub basic_auth_enabled: bool,
+    pub password_hashing: PasswordHashingSettings,
+}
+
+/// Argon2id cost for new password hashes. Stored hashes below it are upgraded
+/// the next time their user logs in.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
 }
 
 /// Used to get the PgConnection::connect string from the database settings
@@ -58,6 +93,64 @@ impl DatabaseSettings {
     }
 }
 
+/// Endpoints for the email provider to call back. Each one is only served
+/// if its credentials are set.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub postmark: Option<WebhookCredentials>,
+}
+
+/// Expected as 'Basic' credentials, which Postmark sends when they are part
+/// of the webhook's URL.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookCredentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// `/metrics` is only served if one of these is set.
+#[derive(serde::Deserialize, Clone)]
+pub struct MetricsSettings {
+    /// Serve the metrics on their own listener, e.g. `127.0.0.1:9091`, that is
+    /// not exposed to the internet.
+    pub listen_address: Option<String>,
+    /// Serve the metrics on the main listener, to requests with an
+    /// `Authorization: Bearer <token>` header.
+    pub bearer_token: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    /// Where to export spans with OTLP over HTTP, e.g.
+    /// `http://localhost:4318/v1/traces`. Nothing is exported if unset.
+    pub otlp_endpoint: Option<String>,
+    pub pii: PiiSettings,
+}
+
+/// How personal data in logs and spans is rendered, see
+/// [`crate::telemetry::PII_FIELDS`].
+#[derive(serde::Deserialize, Clone)]
+pub struct PiiSettings {
+    pub policy: PiiPolicy,
+    /// Keys the hashes of the `hash` policy, so that they cannot be reversed
+    /// by hashing candidate values.
+    pub salt: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiPolicy {
+    /// Record the values as they are.
+    Plain,
+    /// Keep the first character and the email domain, e.g. `u***@example.com`.
+    Mask,
+    /// Replace the values with a salted hash, which still correlates the
+    /// records of the same person.
+    Hash,
+    /// Leave the fields out.
+    Drop,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
     pub base_url: String,
@@ -65,6 +158,170 @@ pub struct EmailClientSettings {
     pub authorization_token: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    pub limits: EmailLimitSettings,
+    /// Never opens if not set.
+    #[serde(default)]
+    pub circuit_breaker: Option<EmailCircuitBreakerSettings>,
+    /// Sends through another provider while the primary's circuit is open.
+    #[serde(default)]
+    pub secondary: Option<EmailProviderSettings>,
+    /// Keeps the emails on `/dev/mailbox` instead of sending them. Refused
+    /// in production.
+    #[serde(default)]
+    pub mailbox: Option<MailboxSettings>,
+}
+
+/// The local mail catcher, see [`crate::email_client::Mailbox`].
+#[derive(serde::Deserialize, Clone)]
+pub struct MailboxSettings {
+    pub storage: MailboxStorage,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MailboxStorage {
+    /// Lost on restart.
+    Memory,
+    /// In the `caught_emails` table.
+    Postgres,
+}
+
+/// Another provider speaking Postmark's API.
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailProviderSettings {
+    pub base_url: String,
+    pub authorization_token: Secret<String>,
+}
+
+/// When to stop calling a failing provider, see [`CircuitBreakerPolicy`].
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailCircuitBreakerSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_threshold: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub open_duration_milliseconds: u64,
+}
+
+impl EmailCircuitBreakerSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_threshold == 0 {
+            return Err("failure_threshold must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self) -> CircuitBreakerPolicy {
+        CircuitBreakerPolicy {
+            failure_threshold: self.failure_threshold,
+            open_duration: std::time::Duration::from_millis(self.open_duration_milliseconds),
+        }
+    }
+}
+
+/// Retries of transient failures and rate limiting, see [`RetryPolicy`].
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailRetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    /// How long sending one email may take, retries included.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub budget_milliseconds: u64,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            budget: std::time::Duration::from_millis(self.budget_milliseconds),
+        }
+    }
+}
+
+/// Throttling of the calls to the email provider, see [`SendLimits`].
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailLimitSettings {
+    /// Unlimited if not set.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_in_flight: Option<usize>,
+    /// Messages per second, unlimited if not set.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub rate_per_second: Option<f64>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub burst: u32,
+    /// Apply the rate to every recipient domain separately.
+    pub per_domain: bool,
+}
+
+impl EmailLimitSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_in_flight == Some(0) {
+            return Err("max_in_flight must be at least 1".into());
+        }
+        match self.rate_per_second {
+            Some(rate) if rate <= 0.0 || !rate.is_finite() => {
+                Err("rate_per_second must be a positive number".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn limits(&self) -> SendLimits {
+        let mut limits = SendLimits::none();
+        if let Some(max_in_flight) = self.max_in_flight {
+            limits = limits.max_in_flight(max_in_flight);
+        }
+        if let Some(rate_per_second) = self.rate_per_second {
+            limits = limits.rate(rate_per_second, self.burst, self.per_domain);
+        }
+        limits
+    }
+}
+
+impl Settings {
+    /// The mail catcher's settings, an error if it is enabled in production.
+    pub fn mailbox(&self) -> Result<Option<&MailboxSettings>, String> {
+        match (&self.email_client.mailbox, self.environment) {
+            (Some(_), Environment::Production) => {
+                Err("the mailbox cannot be enabled in production".into())
+            }
+            (mailbox, _) => Ok(mailbox.as_ref()),
+        }
+    }
+
+    /// The client every email goes through, the application's as well as
+    /// the CLI's: nothing is sent to the addresses suppressed in `pool`, and
+    /// nothing at all if the mailbox is enabled.
+    pub fn email_client(&self, pool: PgPool) -> Result<EmailClient, String> {
+        // a limit of zero would make every send wait forever
+        self.email_client
+            .limits
+            .validate()
+            .map_err(|e| format!("email_client.limits: {}", e))?;
+        if let Some(circuit_breaker) = &self.email_client.circuit_breaker {
+            circuit_breaker
+                .validate()
+                .map_err(|e| format!("email_client.circuit_breaker: {}", e))?;
+        }
+        let mailbox = self.mailbox()?.map(|settings| match settings.storage {
+            MailboxStorage::Memory => Mailbox::memory(),
+            MailboxStorage::Postgres => Mailbox::postgres(pool.clone()),
+        });
+        let email_client = self.email_client.clone().client(pool);
+        Ok(match mailbox {
+            Some(mailbox) => {
+                tracing::warn!("Emails are not sent, they are kept on /dev/mailbox");
+                email_client.with_mailbox(mailbox)
+            }
+            None => email_client,
+        })
+    }
 }
 
 impl EmailClientSettings {
@@ -76,18 +333,32 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 
-    pub fn client(self) -> EmailClient {
-        let sender_email = self.sender().expect("invalid sender email address.");
+    // see `Settings::email_client`, which adds the mailbox
+    fn client(self, pool: PgPool) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
         let timeout = self.timeout();
-        EmailClient::new(
+        let mut email_client = EmailClient::new(
             self.base_url,
             sender_email,
             self.authorization_token,
             timeout,
         )
+        .with_retry_policy(self.retry.policy())
+        .with_limits(self.limits.limits())
+        .with_suppressions(pool);
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            email_client = email_client.with_circuit_breaker(circuit_breaker.policy());
+        }
+        if let Some(secondary) = self.secondary {
+            email_client =
+                email_client.with_secondary(secondary.base_url, secondary.authorization_token);
+        }
+        email_client
     }
 }
 
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum Environment {
     Local,
     Production,
@@ -141,6 +412,7 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .prefix_separator("_")
                 .separator("__"),
         )
+        .set_override("environment", environment.as_str())?
         .build()?;
     // Try to convert the configuration values it read into
     // our Settings type
@@ -0,0 +1,123 @@
+//! Protection against cross-site request forgery for the HTML forms, using
+//! per-session synchronizer tokens.
+//!
+//! Every form renders `csrf_field`; `reject_forged_requests` then refuses any
+//! state-changing request whose token does not match the one in the session.
+use crate::session_state::TypedSession;
+use crate::utils::{constant_time_eq, e500};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{ContentType, CONTENT_TYPE};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+const CSRF_FIELD: &str = "csrf_token";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+// Routes that are not driven by our HTML forms, along with the paths below
+// them:
+// - the JSON API authenticates every request with a token or credentials, it
+//   carries no session for a forged request to ride on;
+// - subscribing is public and confirmed by email, forms on other sites are
+//   expected to post to it.
+const EXEMPT_PATHS: [&str; 4] = [
+    "/newsletters",
+    "/subscriptions",
+    "/suppressions",
+    "/webhooks/postmark",
+];
+
+/// Hidden form field carrying the session's CSRF token.
+pub fn csrf_field(request: &HttpRequest) -> Result<String, actix_web::Error> {
+    let token = TypedSession::from(request)
+        .get_or_insert_csrf_token()
+        .map_err(e500)?;
+    Ok(format!(
+        r#"<input hidden type="text" name="{}" value="{}" />"#,
+        CSRF_FIELD, token
+    ))
+}
+
+/// Reject state-changing requests that do not carry the session's CSRF token,
+/// either in the `csrf_token` form field or in the `X-CSRF-Token` header.
+///
+/// Must run inside the session middleware.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let safe_method = matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    );
+    if safe_method || is_exempt(req.path()) {
+        return next.call(req).await.map(|r| r.map_into_boxed_body());
+    }
+
+    let expected = TypedSession::from(req.request())
+        .get_csrf_token()
+        .map_err(e500)?;
+    let submitted = match req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok()) {
+        Some(token) => Some(token.to_string()),
+        None => submitted_form_token(&mut req).await?,
+    };
+    match (expected, submitted) {
+        (Some(expected), Some(submitted))
+            if constant_time_eq(expected.as_bytes(), submitted.as_bytes()) =>
+        {
+            next.call(req).await.map(|r| r.map_into_boxed_body())
+        }
+        _ => {
+            tracing::warn!(path = %req.path(), "Rejected a request with a missing or invalid CSRF token");
+            Ok(req.into_response(forbidden()))
+        }
+    }
+}
+
+fn is_exempt(path: &str) -> bool {
+    EXEMPT_PATHS.iter().any(|exempt| {
+        path.strip_prefix(exempt)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+// Read the token out of a url-encoded body, putting the body back for the handler.
+async fn submitted_form_token(
+    req: &mut ServiceRequest,
+) -> Result<Option<String>, actix_web::Error> {
+    let is_form = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Ok(None);
+    }
+    let body = req.extract::<web::Bytes>().await?;
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+        .ok()
+        .and_then(|fields| {
+            fields
+                .into_iter()
+                .find(|(key, _)| key == CSRF_FIELD)
+                .map(|(_, value)| value)
+        });
+    req.set_payload(Payload::from(body));
+    Ok(token)
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .cookie(
+            Cookie::build(
+                "_flash",
+                "Your form has expired or could not be verified. Please try again.",
+            )
+            .path("/")
+            .finish(),
+        )
+        .body(r#"<p>Invalid or missing CSRF token.</p><p><a href="/login">Continue</a></p>"#)
+}
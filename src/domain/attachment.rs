@@ -0,0 +1,167 @@
+/// The largest file we attach. Postmark refuses messages over 10 MB, and
+/// attachments grow by a third once base64 encoded.
+pub const MAX_ATTACHMENT_SIZE: usize = 5 * 1024 * 1024;
+/// The most all the attachments of a message may weigh together.
+pub const MAX_TOTAL_ATTACHMENT_SIZE: usize = 7 * 1024 * 1024;
+
+/// What can be attached. Images may also be shown inline.
+const ALLOWED_CONTENT_TYPES: [&str; 8] = [
+    "application/pdf",
+    "text/plain",
+    "text/csv",
+    "text/calendar",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+];
+
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    name: String,
+    content_type: String,
+    content: Vec<u8>,
+    content_id: Option<String>,
+}
+
+impl Attachment {
+    /// `content_id` makes the attachment an inline image, shown wherever the
+    /// HTML body refers to `cid:<content_id>`.
+    pub fn parse(
+        name: String,
+        content_type: String,
+        content: Vec<u8>,
+        content_id: Option<String>,
+    ) -> Result<Attachment, String> {
+        let forbidden_characters = ['/', '\\', '"', '<', '>', ':'];
+        if name.trim().is_empty()
+            || name.chars().count() > 255
+            || name
+                .chars()
+                .any(|c| c.is_control() || forbidden_characters.contains(&c))
+        {
+            return Err(format!("{} is not a valid attachment name.", name));
+        }
+        let content_type = content_type.to_ascii_lowercase();
+        if !ALLOWED_CONTENT_TYPES.contains(&content_type.as_str()) {
+            return Err(format!("{} files cannot be attached.", content_type));
+        }
+        if content.len() > MAX_ATTACHMENT_SIZE {
+            return Err(format!(
+                "{} is larger than {} bytes.",
+                name, MAX_ATTACHMENT_SIZE
+            ));
+        }
+        if let Some(content_id) = &content_id {
+            if !content_type.starts_with("image/") {
+                return Err(format!("{} cannot be shown inline.", name));
+            }
+            let is_valid = !content_id.is_empty()
+                && content_id.len() <= 255
+                && content_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".-_@".contains(c));
+            if !is_valid {
+                return Err(format!("{} is not a valid content id.", content_id));
+            }
+        }
+        Ok(Self {
+            name,
+            content_type,
+            content,
+            content_id,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    pub fn content_id(&self) -> Option<&str> {
+        self.content_id.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{Attachment, MAX_ATTACHMENT_SIZE};
+    use claim::{assert_err, assert_ok};
+
+    fn parse(
+        name: &str,
+        content_type: &str,
+        size: usize,
+        content_id: Option<&str>,
+    ) -> Result<Attachment, String> {
+        Attachment::parse(
+            name.into(),
+            content_type.into(),
+            vec![0; size],
+            content_id.map(Into::into),
+        )
+    }
+
+    #[test]
+    fn a_pdf_is_accepted() {
+        assert_ok!(parse("issue-42.pdf", "application/pdf", 1024, None));
+    }
+
+    #[test]
+    fn content_types_are_case_insensitive() {
+        let attachment = parse("logo.png", "Image/PNG", 10, None).unwrap();
+        assert_eq!(attachment.content_type(), "image/png");
+    }
+
+    #[test]
+    fn executables_are_rejected() {
+        assert_err!(parse("setup.exe", "application/x-msdownload", 10, None));
+    }
+
+    #[test]
+    fn an_attachment_at_the_size_limit_is_accepted() {
+        assert_ok!(parse(
+            "big.pdf",
+            "application/pdf",
+            MAX_ATTACHMENT_SIZE,
+            None
+        ));
+    }
+
+    #[test]
+    fn an_attachment_over_the_size_limit_is_rejected() {
+        assert_err!(parse(
+            "big.pdf",
+            "application/pdf",
+            MAX_ATTACHMENT_SIZE + 1,
+            None
+        ));
+    }
+
+    #[test]
+    fn names_must_not_be_paths() {
+        for name in ["", " ", "../etc/passwd", "C:\\boot.ini", "a\nb.pdf"] {
+            assert_err!(parse(name, "application/pdf", 10, None), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn only_images_can_be_inline() {
+        assert_ok!(parse("logo.png", "image/png", 10, Some("logo@newsletter")));
+        assert_err!(parse("issue.pdf", "application/pdf", 10, Some("issue")));
+    }
+
+    #[test]
+    fn content_ids_are_restricted_to_safe_characters() {
+        assert_err!(parse("logo.png", "image/png", 10, Some("")));
+        assert_err!(parse("logo.png", "image/png", 10, Some("a b")));
+        assert_err!(parse("logo.png", "image/png", 10, Some("cid:<x>")));
+    }
+}
@@ -1,7 +1,9 @@
+mod attachment;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use attachment::{Attachment, MAX_ATTACHMENT_SIZE, MAX_TOTAL_ATTACHMENT_SIZE};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
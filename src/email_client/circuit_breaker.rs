@@ -0,0 +1,205 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When to stop calling a failing provider, and for how long.
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerPolicy {
+    /// Consecutive transient failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long calls are refused before one is let through to test the
+    /// provider again.
+    pub open_duration: Duration,
+}
+
+#[derive(serde::Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    /// Calls are refused.
+    Open,
+    /// The next call goes through, its outcome closes or opens the circuit.
+    HalfOpen,
+}
+
+/// Stops calling a provider after too many consecutive transient failures,
+/// so that senders fail fast instead of waiting out every timeout.
+#[derive(Debug)]
+pub(super) struct CircuitBreaker {
+    policy: Option<CircuitBreakerPolicy>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { failures: u32 },
+    // one call is let through once `until` has passed, pushing it back so
+    // that the others keep waiting for its outcome
+    Open { until: Instant },
+}
+
+impl CircuitBreaker {
+    /// Without a policy, the circuit never opens.
+    pub(super) fn new(policy: Option<CircuitBreakerPolicy>) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    pub(super) fn policy(&self) -> Option<&CircuitBreakerPolicy> {
+        self.policy.as_ref()
+    }
+
+    pub(super) fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if until <= Instant::now() => CircuitState::HalfOpen,
+            State::Open { .. } => CircuitState::Open,
+        }
+    }
+
+    /// Whether a call may go ahead.
+    pub(super) fn allow(&self) -> bool {
+        let open_duration = match &self.policy {
+            Some(policy) => policy.open_duration,
+            None => return true,
+        };
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed { .. } => true,
+            State::Open { until } => {
+                let now = Instant::now();
+                if *until > now {
+                    return false;
+                }
+                *until = now + open_duration;
+                true
+            }
+        }
+    }
+
+    /// The provider answered. Returns whether that closed the circuit.
+    pub(super) fn record_success(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let was_open = matches!(*state, State::Open { .. });
+        *state = State::Closed { failures: 0 };
+        was_open
+    }
+
+    /// The provider failed to answer. Returns whether that opened the circuit.
+    pub(super) fn record_failure(&self) -> bool {
+        let policy = match &self.policy {
+            Some(policy) => policy,
+            None => return false,
+        };
+        let until = Instant::now() + policy.open_duration;
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed { failures } => {
+                *failures += 1;
+                if *failures < policy.failure_threshold {
+                    return false;
+                }
+                *state = State::Open { until };
+                true
+            }
+            // the test call failed
+            State::Open { until: open_until } => {
+                *open_until = until;
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitBreakerPolicy, CircuitState};
+    use std::time::Duration;
+
+    fn circuit_breaker(open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(Some(CircuitBreakerPolicy {
+            failure_threshold: 3,
+            open_duration,
+        }))
+    }
+
+    #[test]
+    fn the_circuit_opens_after_consecutive_failures() {
+        let circuit = circuit_breaker(Duration::from_secs(60));
+
+        assert!(!circuit.record_failure());
+        assert!(!circuit.record_failure());
+        assert!(circuit.record_failure());
+
+        assert_eq!(circuit.state(), CircuitState::Open);
+        assert!(!circuit.allow());
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let circuit = circuit_breaker(Duration::from_secs(60));
+
+        circuit.record_failure();
+        circuit.record_failure();
+        circuit.record_success();
+        circuit.record_failure();
+
+        assert_eq!(circuit.state(), CircuitState::Closed);
+        assert!(circuit.allow());
+    }
+
+    #[test]
+    fn a_single_test_call_goes_through_once_the_circuit_has_been_open_long_enough() {
+        let circuit = circuit_breaker(Duration::ZERO);
+        for _ in 0..3 {
+            circuit.record_failure();
+        }
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+
+        assert!(circuit.allow());
+        assert!(circuit.record_success());
+
+        assert_eq!(circuit.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn other_calls_wait_for_the_outcome_of_the_test_call() {
+        let circuit = circuit_breaker(Duration::from_millis(50));
+        for _ in 0..3 {
+            circuit.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert!(circuit.allow());
+        assert!(!circuit.allow());
+    }
+
+    #[test]
+    fn a_failed_test_call_opens_the_circuit_again() {
+        let circuit = circuit_breaker(Duration::from_millis(50));
+        for _ in 0..3 {
+            circuit.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(circuit.allow());
+
+        // already counted as opened
+        assert!(!circuit.record_failure());
+
+        assert_eq!(circuit.state(), CircuitState::Open);
+        assert!(!circuit.allow());
+    }
+
+    #[test]
+    fn a_disabled_circuit_never_opens() {
+        let circuit = CircuitBreaker::new(None);
+
+        for _ in 0..100 {
+            circuit.record_failure();
+        }
+
+        assert_eq!(circuit.state(), CircuitState::Closed);
+        assert!(circuit.allow());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::domain::SubscriberEmail;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+// forget the buckets of idle domains past this many
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+/// Limits on the calls made to the email provider, shared by every send of
+/// an `EmailClient`.
+#[derive(Debug, Default)]
+pub struct SendLimits {
+    in_flight: Option<Semaphore>,
+    rate: Option<RateLimit>,
+}
+
+#[derive(Debug)]
+struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    per_domain: bool,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+// `tokens` goes negative when sends are queued up, each of them waits for its
+// share of the deficit to refill
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl SendLimits {
+    /// No limits at all.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// At most `max_in_flight` calls to the provider at any time.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.in_flight = Some(Semaphore::new(max_in_flight));
+        self
+    }
+
+    /// At most `per_second` messages on average, in bursts of up to `burst`.
+    /// With `per_domain`, every recipient domain gets that rate of its own.
+    pub fn rate(mut self, per_second: f64, burst: u32, per_domain: bool) -> Self {
+        assert!(per_second > 0.0, "the email send rate must be positive.");
+        self.rate = Some(RateLimit {
+            per_second,
+            burst: f64::from(burst.max(1)),
+            per_domain,
+            buckets: Mutex::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// Wait until a call sending to `recipients` is allowed. The call may go
+    /// ahead for as long as the permit is held.
+    pub async fn acquire(&self, recipients: &[SubscriberEmail]) -> Option<SemaphorePermit<'_>> {
+        if let Some(rate) = &self.rate {
+            let wait = rate.reserve(recipients);
+            if !wait.is_zero() {
+                tracing::debug!(
+                    wait_ms = wait.as_millis() as u64,
+                    "Waiting for the email send rate"
+                );
+                tokio::time::sleep(wait).await;
+            }
+        }
+        match &self.in_flight {
+            // the semaphore is never closed
+            Some(in_flight) => in_flight.acquire().await.ok(),
+            None => None,
+        }
+    }
+}
+
+impl RateLimit {
+    // take a token per recipient from the buckets they belong to, returning
+    // how long to wait for the slowest of them
+    fn reserve(&self, recipients: &[SubscriberEmail]) -> Duration {
+        let mut counts: HashMap<&str, f64> = HashMap::new();
+        for recipient in recipients {
+            let key = if self.per_domain {
+                domain(recipient)
+            } else {
+                ""
+            };
+            *counts.entry(key).or_default() += 1.0;
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| bucket.refilled(now, self) < self.burst);
+        }
+        let mut wait = Duration::ZERO;
+        for (key, count) in counts {
+            let bucket = buckets
+                .entry(key.to_lowercase())
+                .or_insert_with(|| TokenBucket {
+                    tokens: self.burst,
+                    updated: now,
+                });
+            bucket.tokens = bucket.refilled(now, self) - count;
+            bucket.updated = now;
+            if bucket.tokens < 0.0 {
+                wait = wait.max(Duration::from_secs_f64(-bucket.tokens / self.per_second));
+            }
+        }
+        wait
+    }
+}
+
+impl TokenBucket {
+    fn refilled(&self, now: Instant, rate: &RateLimit) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * rate.per_second).min(rate.burst)
+    }
+}
+
+fn domain(recipient: &SubscriberEmail) -> &str {
+    let email: &str = recipient.as_ref();
+    email.rsplit_once('@').map_or(email, |(_, domain)| domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendLimits;
+    use crate::domain::SubscriberEmail;
+    use std::time::{Duration, Instant};
+
+    fn recipients(emails: &[&str]) -> Vec<SubscriberEmail> {
+        emails
+            .iter()
+            .map(|email| SubscriberEmail::parse(email.to_string()).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sends_within_the_burst_do_not_wait() {
+        let limits = SendLimits::none().rate(1.0, 3, false);
+        let started = Instant::now();
+
+        limits
+            .acquire(&recipients(&["a@example.com", "b@example.com"]))
+            .await;
+        limits.acquire(&recipients(&["c@example.com"])).await;
+
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn sends_beyond_the_burst_wait_for_the_bucket_to_refill() {
+        let limits = SendLimits::none().rate(10.0, 1, false);
+        let started = Instant::now();
+
+        for _ in 0..3 {
+            limits.acquire(&recipients(&["a@example.com"])).await;
+        }
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn every_domain_has_a_bucket_of_its_own() {
+        let limits = SendLimits::none().rate(0.1, 1, true);
+        let started = Instant::now();
+
+        limits.acquire(&recipients(&["a@example.com"])).await;
+        limits.acquire(&recipients(&["a@example.org"])).await;
+        limits.acquire(&recipients(&["b@EXAMPLE.net"])).await;
+
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn a_batch_waits_for_its_busiest_domain() {
+        let limits = SendLimits::none().rate(10.0, 1, true);
+        let batch = recipients(&["a@example.com", "b@example.com", "c@example.org"]);
+
+        let wait = limits.rate.as_ref().unwrap().reserve(&batch);
+
+        assert!(wait > Duration::from_millis(50) && wait <= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn no_more_calls_than_allowed_are_in_flight() {
+        let limits = SendLimits::none().max_in_flight(1);
+        let first = limits.acquire(&[]).await;
+
+        let second = tokio::time::timeout(Duration::from_millis(50), limits.acquire(&[])).await;
+
+        assert!(first.is_some());
+        assert!(second.is_err());
+    }
+}
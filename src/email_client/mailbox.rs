@@ -0,0 +1,191 @@
+//! A mail catcher for local development: emails are kept here instead of
+//! being sent, and shown on `/dev/mailbox`.
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// How many emails are listed, and kept when in memory.
+pub const MAILBOX_SIZE: usize = 100;
+
+#[derive(Clone, Debug)]
+pub struct Mailbox {
+    storage: Storage,
+}
+
+#[derive(Clone, Debug)]
+enum Storage {
+    /// The most recent first, lost on restart.
+    Memory(Arc<Mutex<VecDeque<CaughtEmail>>>),
+    Postgres(PgPool),
+}
+
+#[derive(Clone, Debug)]
+pub struct CaughtEmail {
+    pub email_id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    /// The request that would have been sent to the provider.
+    pub message: serde_json::Value,
+    pub caught_at: DateTime<Utc>,
+}
+
+impl CaughtEmail {
+    /// A field of the provider's request, e.g. `HtmlBody`.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.message.get(name).and_then(|value| value.as_str())
+    }
+
+    /// Every distinct link in the text body, or in the HTML body if there is
+    /// no text, in order.
+    pub fn links(&self) -> Vec<&str> {
+        let body = match self.field("TextBody") {
+            Some(text) if !text.trim().is_empty() => text,
+            _ => self.field("HtmlBody").unwrap_or_default(),
+        };
+        find_links(body)
+    }
+}
+
+impl Mailbox {
+    pub fn memory() -> Self {
+        Self {
+            storage: Storage::Memory(Arc::default()),
+        }
+    }
+
+    pub fn postgres(pool: PgPool) -> Self {
+        Self {
+            storage: Storage::Postgres(pool),
+        }
+    }
+
+    /// Keep `message`, a request to the provider, as if it had been sent.
+    #[tracing::instrument(name = "Catch an email", skip_all)]
+    pub async fn catch(&self, message: serde_json::Value) -> Result<CaughtEmail, anyhow::Error> {
+        let text = |name: &str| {
+            message
+                .get(name)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let email = CaughtEmail {
+            email_id: Uuid::new_v4(),
+            recipient: text("To"),
+            subject: text("Subject"),
+            caught_at: Utc::now(),
+            message,
+        };
+        match &self.storage {
+            Storage::Memory(emails) => {
+                let mut emails = emails.lock().unwrap();
+                emails.push_front(email.clone());
+                emails.truncate(MAILBOX_SIZE);
+            }
+            Storage::Postgres(pool) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO caught_emails (email_id, recipient, subject, message, caught_at)
+                    VALUES ($1, $2, $3, $4, $5)
+                    "#,
+                    email.email_id,
+                    email.recipient,
+                    email.subject,
+                    email.message,
+                    email.caught_at
+                )
+                .execute(pool)
+                .await
+                .context("Failed to store a caught email.")?;
+            }
+        }
+        Ok(email)
+    }
+
+    /// The `MAILBOX_SIZE` most recent emails, the most recent first.
+    pub async fn list(&self) -> Result<Vec<CaughtEmail>, anyhow::Error> {
+        match &self.storage {
+            Storage::Memory(emails) => Ok(emails.lock().unwrap().iter().cloned().collect()),
+            Storage::Postgres(pool) => sqlx::query_as!(
+                CaughtEmail,
+                r#"
+                SELECT email_id, recipient, subject, message, caught_at FROM caught_emails
+                ORDER BY caught_at DESC
+                LIMIT $1
+                "#,
+                MAILBOX_SIZE as i64
+            )
+            .fetch_all(pool)
+            .await
+            .context("Failed to list the caught emails."),
+        }
+    }
+
+    pub async fn get(&self, email_id: Uuid) -> Result<Option<CaughtEmail>, anyhow::Error> {
+        match &self.storage {
+            Storage::Memory(emails) => Ok(emails
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|email| email.email_id == email_id)
+                .cloned()),
+            Storage::Postgres(pool) => sqlx::query_as!(
+                CaughtEmail,
+                r#"
+                SELECT email_id, recipient, subject, message, caught_at FROM caught_emails
+                WHERE email_id = $1
+                "#,
+                email_id
+            )
+            .fetch_optional(pool)
+            .await
+            .context("Failed to fetch a caught email."),
+        }
+    }
+}
+
+// the http(s) URLs in `body`, without the punctuation around them
+fn find_links(body: &str) -> Vec<&str> {
+    let mut links = Vec::new();
+    for word in body.split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>')) {
+        let start = match word.find("http://").or_else(|| word.find("https://")) {
+            Some(start) => start,
+            None => continue,
+        };
+        let link = word[start..].trim_end_matches(['.', ',', ';', ')', '!', '?']);
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_links;
+
+    #[test]
+    fn links_are_found_in_text_and_html() {
+        let body = r#"Visit http://127.0.0.1:8000/confirm?token=abc to confirm.
+            Or <a href="https://example.com/a">click here</a> (https://example.com/b)."#;
+
+        assert_eq!(
+            find_links(body),
+            [
+                "http://127.0.0.1:8000/confirm?token=abc",
+                "https://example.com/a",
+                "https://example.com/b"
+            ]
+        );
+    }
+
+    #[test]
+    fn links_are_listed_once() {
+        let body = "http://example.com/a and again http://example.com/a.";
+
+        assert_eq!(find_links(body), ["http://example.com/a"]);
+    }
+}
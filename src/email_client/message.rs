@@ -0,0 +1,317 @@
+use crate::domain::{Attachment, SubscriberEmail, MAX_TOTAL_ATTACHMENT_SIZE};
+use std::collections::BTreeMap;
+
+/// The content of an email and how the provider should handle it, to be sent
+/// to one or more recipients.
+#[derive(Clone, Debug)]
+pub struct EmailMessage {
+    subject: String,
+    html_body: String,
+    text_body: String,
+    reply_to: Option<SubscriberEmail>,
+    cc: Vec<SubscriberEmail>,
+    bcc: Vec<SubscriberEmail>,
+    headers: Vec<(String, String)>,
+    tag: Option<String>,
+    metadata: BTreeMap<String, String>,
+    message_stream: Option<String>,
+    attachments: Vec<Attachment>,
+}
+
+impl EmailMessage {
+    pub fn new(
+        subject: impl Into<String>,
+        html_body: impl Into<String>,
+        text_body: impl Into<String>,
+    ) -> Self {
+        Self {
+            subject: subject.into(),
+            html_body: html_body.into(),
+            text_body: text_body.into(),
+            reply_to: None,
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            headers: Vec::new(),
+            tag: None,
+            metadata: BTreeMap::new(),
+            message_stream: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn reply_to(mut self, reply_to: SubscriberEmail) -> Self {
+        self.reply_to = Some(reply_to);
+        self
+    }
+
+    pub fn cc(mut self, cc: SubscriberEmail) -> Self {
+        self.cc.push(cc);
+        self
+    }
+
+    pub fn bcc(mut self, bcc: SubscriberEmail) -> Self {
+        self.bcc.push(bcc);
+        self
+    }
+
+    /// An extra header, e.g. `List-Unsubscribe`. Headers set more than once
+    /// are sent as many times.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Groups messages in the provider's statistics.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Returned as is by the provider's webhooks.
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// The provider's stream to send through, its default transactional
+    /// stream if not set.
+    pub fn message_stream(mut self, message_stream: impl Into<String>) -> Self {
+        self.message_stream = Some(message_stream.into());
+        self
+    }
+
+    /// Fails if the attachments would weigh more than
+    /// `MAX_TOTAL_ATTACHMENT_SIZE` together.
+    pub fn attach(mut self, attachment: Attachment) -> Result<Self, String> {
+        let total: usize = self
+            .attachments
+            .iter()
+            .map(|a| a.content().len())
+            .sum::<usize>()
+            + attachment.content().len();
+        if total > MAX_TOTAL_ATTACHMENT_SIZE {
+            return Err(format!(
+                "The attachments are larger than {} bytes together.",
+                MAX_TOTAL_ATTACHMENT_SIZE
+            ));
+        }
+        self.attachments.push(attachment);
+        Ok(self)
+    }
+
+    /// The addresses copied, Cc then Bcc.
+    pub(super) fn copies(&self) -> impl Iterator<Item = &SubscriberEmail> {
+        self.cc.iter().chain(&self.bcc)
+    }
+
+    /// Only the copies `keep` says to send to.
+    pub(super) fn retain_copies(mut self, keep: impl Fn(&SubscriberEmail) -> bool) -> Self {
+        self.cc.retain(&keep);
+        self.bcc.retain(&keep);
+        self
+    }
+
+    pub(super) fn request<'a>(
+        &'a self,
+        from: &'a SubscriberEmail,
+        to: &'a SubscriberEmail,
+    ) -> SendEmailRequest<'a> {
+        SendEmailRequest {
+            from: from.as_ref(),
+            to: to.as_ref(),
+            cc: address_list(&self.cc),
+            bcc: address_list(&self.bcc),
+            subject: &self.subject,
+            tag: self.tag.as_deref(),
+            html_body: &self.html_body,
+            text_body: &self.text_body,
+            reply_to: self.reply_to.as_ref().map(AsRef::as_ref),
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| Header { name, value })
+                .collect(),
+            metadata: &self.metadata,
+            message_stream: self.message_stream.as_deref(),
+            attachments: self
+                .attachments
+                .iter()
+                .map(|attachment| AttachmentRequest {
+                    name: attachment.name(),
+                    content: base64::encode(attachment.content()),
+                    content_type: attachment.content_type(),
+                    content_id: attachment.content_id().map(|id| format!("cid:{}", id)),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Postmark takes several addresses as a comma separated list
+fn address_list(addresses: &[SubscriberEmail]) -> Option<String> {
+    if addresses.is_empty() {
+        return None;
+    }
+    let addresses: Vec<&str> = addresses.iter().map(AsRef::as_ref).collect();
+    Some(addresses.join(","))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bcc: Option<String>,
+    subject: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header<'a>>,
+    #[serde(skip_serializing_if = "is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_stream: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<AttachmentRequest<'a>>,
+}
+
+fn is_empty(metadata: &&BTreeMap<String, String>) -> bool {
+    metadata.is_empty()
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct AttachmentRequest<'a> {
+    name: &'a str,
+    content: String,
+    content_type: &'a str,
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailMessage;
+    use crate::domain::{Attachment, SubscriberEmail, MAX_ATTACHMENT_SIZE};
+    use claim::assert_err;
+
+    fn address(email: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(email.into()).unwrap()
+    }
+
+    #[test]
+    fn a_plain_message_only_has_the_required_fields() {
+        let message = EmailMessage::new("Subject", "<p>Hi</p>", "Hi");
+        let (from, to) = (address("from@example.com"), address("to@example.com"));
+
+        let body = serde_json::to_value(message.request(&from, &to)).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "From": "from@example.com",
+                "To": "to@example.com",
+                "Subject": "Subject",
+                "HtmlBody": "<p>Hi</p>",
+                "TextBody": "Hi",
+            })
+        );
+    }
+
+    #[test]
+    fn every_option_is_sent_as_postmark_expects_it() {
+        let message = EmailMessage::new("Subject", "<p>Hi</p>", "Hi")
+            .reply_to(address("support@example.com"))
+            .cc(address("cc1@example.com"))
+            .cc(address("cc2@example.com"))
+            .bcc(address("bcc@example.com"))
+            .header("List-Unsubscribe", "<https://example.com/unsubscribe>")
+            .header("Message-ID", "<1@example.com>")
+            .tag("welcome")
+            .metadata("subscriber_id", "42")
+            .message_stream("broadcast");
+        let (from, to) = (address("from@example.com"), address("to@example.com"));
+
+        let body = serde_json::to_value(message.request(&from, &to)).unwrap();
+
+        assert_eq!(body["ReplyTo"], "support@example.com");
+        assert_eq!(body["Cc"], "cc1@example.com,cc2@example.com");
+        assert_eq!(body["Bcc"], "bcc@example.com");
+        assert_eq!(
+            body["Headers"],
+            serde_json::json!([
+                {"Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>"},
+                {"Name": "Message-ID", "Value": "<1@example.com>"},
+            ])
+        );
+        assert_eq!(body["Tag"], "welcome");
+        assert_eq!(body["Metadata"], serde_json::json!({"subscriber_id": "42"}));
+        assert_eq!(body["MessageStream"], "broadcast");
+    }
+
+    #[test]
+    fn attachments_are_base64_encoded() {
+        let pdf = Attachment::parse(
+            "issue.pdf".into(),
+            "application/pdf".into(),
+            b"%PDF-1.7".to_vec(),
+            None,
+        )
+        .unwrap();
+        let logo = Attachment::parse(
+            "logo.png".into(),
+            "image/png".into(),
+            vec![0x89, b'P', b'N', b'G'],
+            Some("logo".into()),
+        )
+        .unwrap();
+        let message = EmailMessage::new("Subject", r#"<img src="cid:logo">"#, "Hi")
+            .attach(pdf)
+            .unwrap()
+            .attach(logo)
+            .unwrap();
+        let (from, to) = (address("from@example.com"), address("to@example.com"));
+
+        let body = serde_json::to_value(message.request(&from, &to)).unwrap();
+
+        assert_eq!(
+            body["Attachments"],
+            serde_json::json!([
+                {"Name": "issue.pdf", "Content": "JVBERi0xLjc=", "ContentType": "application/pdf"},
+                {"Name": "logo.png", "Content": "iVBORw==", "ContentType": "image/png", "ContentID": "cid:logo"},
+            ])
+        );
+    }
+
+    #[test]
+    fn attachments_are_limited_in_total() {
+        let big = || {
+            Attachment::parse(
+                "big.pdf".into(),
+                "application/pdf".into(),
+                vec![0; MAX_ATTACHMENT_SIZE],
+                None,
+            )
+            .unwrap()
+        };
+        let message = EmailMessage::new("Subject", "<p>Hi</p>", "Hi")
+            .attach(big())
+            .unwrap();
+
+        assert_err!(message.attach(big()));
+    }
+}
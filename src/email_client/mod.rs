@@ -0,0 +1,1512 @@
+mod circuit_breaker;
+mod limits;
+mod mailbox;
+mod message;
+
+use crate::domain::SubscriberEmail;
+use crate::metrics::EmailMetrics;
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{find_suppressed, suppression_key};
+use crate::telemetry::trace_context_headers;
+use chrono::{DateTime, Utc};
+use circuit_breaker::CircuitBreaker;
+use futures_util::future::join_all;
+use rand::{thread_rng, Rng};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+pub use circuit_breaker::{CircuitBreakerPolicy, CircuitState};
+pub use limits::SendLimits;
+pub use mailbox::{CaughtEmail, Mailbox, MAILBOX_SIZE};
+pub use message::EmailMessage;
+
+// Postmark's error codes, https://postmarkapp.com/developer/api/overview#error-codes
+// Could be about anything in the request, the message tells.
+const INVALID_EMAIL_REQUEST: i64 = 300;
+const INACTIVE_RECIPIENT: i64 = 406;
+// A pending account only sends to its own domain.
+const PENDING_ACCOUNT_RECIPIENT: i64 = 412;
+// how an invalid email request names the address at fault, e.g. "Invalid
+// 'To' address"
+const RECIPIENT_FIELDS: [&str; 3] = ["'To'", "'Cc'", "'Bcc'"];
+
+/// How many messages Postmark accepts in a single batch.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(Debug)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    http_client: Client,
+    primary: Provider,
+    /// Takes over while the primary's circuit is open.
+    secondary: Option<Provider>,
+    metrics: EmailMetrics,
+    retry_policy: RetryPolicy,
+    limits: SendLimits,
+    suppressions: Option<PgPool>,
+    /// Keeps the emails instead of sending them, in local development.
+    mailbox: Option<Mailbox>,
+}
+
+/// Why an email could not be sent.
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    /// The provider could not be reached, timed out or failed on its side.
+    #[error("The email provider failed to handle the request.")]
+    Transient(#[source] anyhow::Error),
+    #[error("The email provider is rate limiting our requests.")]
+    RateLimited { retry_after: Option<Duration> },
+    /// The recipient bounced or complained before, the provider will not
+    /// deliver to them anymore.
+    #[error("The recipient is inactive: {0}")]
+    InactiveRecipient(String),
+    /// Any other refusal, e.g. an invalid server token or a malformed
+    /// request.
+    /// The address cannot be sent to, sending anything else to it will fail
+    /// the same way.
+    #[error("The email provider rejected the recipient: {message}")]
+    InvalidRecipient { error_code: i64, message: String },
+    #[error("The email provider rejected the request ({status}): {message}")]
+    Rejected {
+        status: u16,
+        error_code: Option<i64>,
+        message: String,
+    },
+    /// The recipient is on our suppression list, nothing was sent.
+    #[error("The recipient is suppressed: {0}")]
+    Suppressed(String),
+    #[error("Failed to check the suppression list.")]
+    SuppressionCheck(#[source] anyhow::Error),
+    /// The circuit of every provider is open, nothing was attempted.
+    #[error("No email provider is available.")]
+    Unavailable,
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailError {
+    /// The provider's error code, if it gave one.
+    pub fn error_code(&self) -> Option<i64> {
+        match self {
+            Self::InvalidRecipient { error_code, .. } => Some(*error_code),
+            Self::InactiveRecipient(_) => Some(INACTIVE_RECIPIENT),
+            Self::Rejected { error_code, .. } => *error_code,
+            Self::Transient(_)
+            | Self::RateLimited { .. }
+            | Self::Suppressed(_)
+            | Self::SuppressionCheck(_)
+            | Self::Unavailable => None,
+        }
+    }
+
+    /// Whether sending the same email again later could succeed. Open
+    /// circuits are not waited for, the point is to fail fast.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Transient(_) | Self::RateLimited { .. })
+    }
+
+    // the same failure for every message of a batch
+    fn duplicate(&self) -> Self {
+        match self {
+            Self::Transient(e) => Self::Transient(anyhow::anyhow!("{:#}", e)),
+            Self::RateLimited { retry_after } => Self::RateLimited {
+                retry_after: *retry_after,
+            },
+            Self::InvalidRecipient {
+                error_code,
+                message,
+            } => Self::InvalidRecipient {
+                error_code: *error_code,
+                message: message.clone(),
+            },
+            Self::InactiveRecipient(message) => Self::InactiveRecipient(message.clone()),
+            Self::Rejected {
+                status,
+                error_code,
+                message,
+            } => Self::Rejected {
+                status: *status,
+                error_code: *error_code,
+                message: message.clone(),
+            },
+            Self::Suppressed(reason) => Self::Suppressed(reason.clone()),
+            Self::SuppressionCheck(e) => Self::SuppressionCheck(anyhow::anyhow!("{:#}", e)),
+            Self::Unavailable => Self::Unavailable,
+        }
+    }
+}
+
+/// What the provider told us about an email it accepted.
+#[derive(Debug)]
+pub struct SentEmail {
+    /// Webhooks refer to the email with it.
+    pub message_id: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+}
+
+impl SentEmail {
+    fn from_response(message_id: Option<String>, submitted_at: Option<String>) -> Self {
+        if message_id.is_none() {
+            tracing::warn!("The email provider did not return a message id");
+        }
+        Self {
+            message_id,
+            // with seven digits of fractional seconds, which RFC 3339 allows
+            submitted_at: submitted_at
+                .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+                .map(|at| at.with_timezone(&Utc)),
+        }
+    }
+}
+
+/// How failed sends are retried, with jittered exponential backoff.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// The upper bound of the first backoff, doubled for every retry.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// How long a send may take overall. No retry is attempted if it would
+    /// start after the budget is spent.
+    pub budget: Duration,
+}
+
+impl RetryPolicy {
+    /// Give up after the first failure.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            budget: Duration::ZERO,
+        }
+    }
+
+    // how long to wait before the next attempt, if there should be one
+    fn delay(&self, retries: u32, error: &EmailError, elapsed: Duration) -> Option<Duration> {
+        if retries >= self.max_retries || !error.is_retryable() {
+            return None;
+        }
+        let delay = match error {
+            EmailError::RateLimited {
+                retry_after: Some(retry_after),
+            } => *retry_after,
+            _ => {
+                let ceiling = self
+                    .base_delay
+                    .saturating_mul(2u32.saturating_pow(retries))
+                    .min(self.max_delay);
+                // full jitter, so that clients failing together do not all
+                // come back at once
+                ceiling.mul_f64(thread_rng().gen())
+            }
+        };
+        (elapsed + delay <= self.budget).then_some(delay)
+    }
+}
+
+// an email provider speaking Postmark's API
+#[derive(Debug)]
+struct Provider {
+    name: &'static str,
+    base_url: String,
+    authorization_token: Secret<String>,
+    circuit: CircuitBreaker,
+}
+
+impl EmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        // let local_addr = std::net::IpAddr::from_str("192.168.56.1").unwrap();
+
+        // dbg!(local_addr);
+        let http_client = Client::builder()
+            .timeout(timeout)
+            // .local_address(std::net::IpAddr::from(local_addr))
+            .build()
+            .unwrap();
+        Self {
+            sender,
+            http_client,
+            primary: Provider {
+                name: "primary",
+                base_url,
+                authorization_token,
+                circuit: CircuitBreaker::new(None),
+            },
+            secondary: None,
+            metrics: EmailMetrics::default(),
+            retry_policy: RetryPolicy::none(),
+            limits: SendLimits::none(),
+            suppressions: None,
+            mailbox: None,
+        }
+    }
+
+    /// Record the outcome of every send in `metrics`.
+    pub fn with_metrics(mut self, metrics: EmailMetrics) -> Self {
+        self.metrics = metrics;
+        for provider in self.providers() {
+            self.metrics.init_circuit(provider.name);
+        }
+        self
+    }
+
+    /// Stop calling a provider that keeps failing, as `policy` says.
+    pub fn with_circuit_breaker(mut self, policy: CircuitBreakerPolicy) -> Self {
+        self.primary.circuit = CircuitBreaker::new(Some(policy.clone()));
+        if let Some(secondary) = &mut self.secondary {
+            secondary.circuit = CircuitBreaker::new(Some(policy));
+        }
+        self
+    }
+
+    /// Send through another provider while the primary's circuit is open.
+    pub fn with_secondary(mut self, base_url: String, authorization_token: Secret<String>) -> Self {
+        let secondary = Provider {
+            name: "secondary",
+            base_url,
+            authorization_token,
+            circuit: CircuitBreaker::new(self.primary.circuit.policy().cloned()),
+        };
+        self.metrics.init_circuit(secondary.name);
+        self.secondary = Some(secondary);
+        self
+    }
+
+    /// Keep every email in `mailbox` instead of sending it. Suppressions
+    /// still apply.
+    pub fn with_mailbox(mut self, mailbox: Mailbox) -> Self {
+        self.mailbox = Some(mailbox);
+        self
+    }
+
+    pub fn mailbox(&self) -> Option<&Mailbox> {
+        self.mailbox.as_ref()
+    }
+
+    /// The state of the circuit of every provider, the primary first.
+    pub fn circuits(&self) -> Vec<(&'static str, CircuitState)> {
+        self.providers()
+            .map(|provider| (provider.name, provider.circuit.state()))
+            .collect()
+    }
+
+    fn providers(&self) -> impl Iterator<Item = &Provider> {
+        std::iter::once(&self.primary).chain(&self.secondary)
+    }
+
+    /// Retry transient failures and rate limiting as `retry_policy` allows.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Throttle every call to the provider, retries included.
+    pub fn with_limits(mut self, limits: SendLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Refuse to email the addresses on the suppression list kept in `pool`.
+    pub fn with_suppressions(mut self, pool: PgPool) -> Self {
+        self.suppressions = Some(pool);
+        self
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<SentEmail, EmailError> {
+        let message = EmailMessage::new(subject, html_content, text_content);
+        self.send_message(recipient, &message).await
+    }
+
+    #[tracing::instrument(
+        name = "Sending an email",
+        skip_all,
+        fields(otel.kind = "client")
+    )]
+    pub async fn send_message(
+        &self,
+        recipient: &SubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<SentEmail, EmailError> {
+        let recipients = std::slice::from_ref(recipient);
+        let addresses: Vec<SubscriberEmail> =
+            recipients.iter().chain(message.copies()).cloned().collect();
+        let suppressed = self.suppressed(&addresses).await?;
+        if let Some(reason) = suppressed.get(&suppression_key(recipient)) {
+            return Err(EmailError::Suppressed(reason.clone()));
+        }
+        let message = without_suppressed_copies(message, &suppressed);
+        let request_body = message.request(&self.sender, recipient);
+        if let Some(mailbox) = &self.mailbox {
+            return catch(mailbox, &request_body).await;
+        }
+        let response = self
+            .with_retries(|| self.post("email", &request_body, recipients))
+            .await?;
+        // the email is out, a body we cannot read is no reason to fail
+        let response = response
+            .json::<SendEmailResponse>()
+            .await
+            .unwrap_or_default();
+        Ok(SentEmail::from_response(
+            response.message_id,
+            response.submitted_at,
+        ))
+    }
+
+    /// Send the same email to every recipient, `MAX_BATCH_SIZE` of them per
+    /// call to the provider.
+    ///
+    /// The outcome of every message is returned next to its recipient, in
+    /// order: a batch can partly fail. Only failures of a whole call are
+    /// retried, individual rejections are final. Suppressed recipients are
+    /// left out of the calls, suppressed copies out of the messages.
+    #[tracing::instrument(
+        name = "Sending a batch of emails",
+        skip_all,
+        fields(otel.kind = "client", recipients = recipients.len())
+    )]
+    pub async fn send_batch<'a>(
+        &self,
+        recipients: &'a [SubscriberEmail],
+        message: &EmailMessage,
+    ) -> Vec<(&'a SubscriberEmail, Result<SentEmail, EmailError>)> {
+        let addresses: Vec<SubscriberEmail> =
+            recipients.iter().chain(message.copies()).cloned().collect();
+        let suppressed = match self.suppressed(&addresses).await {
+            Ok(suppressed) => suppressed,
+            Err(error) => {
+                return recipients
+                    .iter()
+                    .map(|recipient| (recipient, Err(error.duplicate())))
+                    .collect()
+            }
+        };
+        let allowed: Vec<SubscriberEmail> = recipients
+            .iter()
+            .filter(|recipient| !suppressed.contains_key(&suppression_key(recipient)))
+            .cloned()
+            .collect();
+        let message = without_suppressed_copies(message, &suppressed);
+        // the chunks go out concurrently, as far as the limits allow
+        let chunks = allowed
+            .chunks(MAX_BATCH_SIZE)
+            .map(|chunk| self.send_chunk(chunk, &message));
+        let mut outcomes = join_all(chunks).await.into_iter().flatten();
+        recipients
+            .iter()
+            .map(|recipient| {
+                let outcome = match suppressed.get(&suppression_key(recipient)) {
+                    Some(reason) => Err(EmailError::Suppressed(reason.clone())),
+                    // one outcome per allowed recipient, in order
+                    None => outcomes.next().expect("An outcome for every message sent."),
+                };
+                (recipient, outcome)
+            })
+            .collect()
+    }
+
+    // the outcome of each message, in the order of the chunk
+    async fn send_chunk(
+        &self,
+        chunk: &[SubscriberEmail],
+        message: &EmailMessage,
+    ) -> Vec<Result<SentEmail, EmailError>> {
+        let request_body: Vec<_> = chunk
+            .iter()
+            .map(|recipient| message.request(&self.sender, recipient))
+            .collect();
+        if let Some(mailbox) = &self.mailbox {
+            let caught = request_body.iter().map(|request| catch(mailbox, request));
+            return join_all(caught).await;
+        }
+        let results = match self
+            .with_retries(|| self.post("email/batch", &request_body, chunk))
+            .await
+        {
+            // parsing failures are not retried, the emails may have gone out
+            Ok(response) => response
+                .json::<Vec<BatchMessageResult>>()
+                .await
+                .map_err(|e| EmailError::Transient(e.into())),
+            Err(error) => Err(error),
+        };
+        match results {
+            Ok(results) => {
+                let mut results = results.into_iter();
+                chunk
+                    .iter()
+                    .map(|_| match results.next() {
+                        Some(result) => result.into_outcome(),
+                        None => Err(EmailError::Transient(anyhow::anyhow!(
+                            "The email provider did not report on this message."
+                        ))),
+                    })
+                    .collect()
+            }
+            Err(error) => chunk.iter().map(|_| Err(error.duplicate())).collect(),
+        }
+    }
+
+    // the reason of every suppressed recipient, by `suppression_key`
+    async fn suppressed(
+        &self,
+        recipients: &[SubscriberEmail],
+    ) -> Result<HashMap<String, String>, EmailError> {
+        match &self.suppressions {
+            Some(pool) => find_suppressed(pool, recipients)
+                .await
+                .map_err(EmailError::SuppressionCheck),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    // retry `attempt` as the retry policy allows
+    async fn with_retries<T, F, Fut>(&self, mut attempt: F) -> Result<T, EmailError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, EmailError>>,
+    {
+        let started = Instant::now();
+        let mut retries = 0;
+        loop {
+            let error = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let delay = match self.retry_policy.delay(retries, &error, started.elapsed()) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+            tracing::warn!(
+                error.cause_chain = ?error,
+                retries,
+                delay_ms = delay.as_millis() as u64,
+                "Failed to send an email, retrying"
+            );
+            tokio::time::sleep(delay).await;
+            retries += 1;
+        }
+    }
+
+    // a single call to the provider, successful responses are returned as is
+    async fn post(
+        &self,
+        path: &str,
+        request_body: &impl serde::Serialize,
+        recipients: &[SubscriberEmail],
+    ) -> Result<reqwest::Response, EmailError> {
+        let _permit = {
+            let _waiting = self.metrics.start_waiting();
+            self.limits.acquire(recipients).await
+        };
+        let provider = self.pick_provider()?;
+        let url = format!("{}/{}", provider.base_url, path);
+        let started = Instant::now();
+        let outcome = self
+            .http_client
+            .post(url)
+            .headers(trace_context_headers())
+            .header(
+                "X-Postmark-Server-Token",
+                provider.authorization_token.expose_secret(),
+            )
+            .json(request_body)
+            .send()
+            .await;
+        let status = match &outcome {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(_) => "error".into(),
+        };
+        let outcome = match outcome {
+            Ok(response) => classify_response(response).await,
+            Err(e) => Err(EmailError::Transient(e.into())),
+        };
+        self.metrics
+            .record(outcome.is_ok(), &status, started.elapsed());
+        // refusals are answers, only a provider failing on its side counts
+        // against its circuit
+        if matches!(outcome, Err(EmailError::Transient(_))) {
+            if provider.circuit.record_failure() {
+                tracing::error!(
+                    provider = provider.name,
+                    "The email provider's circuit opened"
+                );
+                self.metrics.record_circuit(provider.name, true);
+            }
+        } else if provider.circuit.record_success() {
+            tracing::info!(
+                provider = provider.name,
+                "The email provider's circuit closed"
+            );
+            self.metrics.record_circuit(provider.name, false);
+        }
+        outcome
+    }
+
+    // the primary unless its circuit is open
+    fn pick_provider(&self) -> Result<&Provider, EmailError> {
+        if self.primary.circuit.allow() {
+            return Ok(&self.primary);
+        }
+        match &self.secondary {
+            Some(secondary) if secondary.circuit.allow() => {
+                self.metrics.record_failover();
+                Ok(secondary)
+            }
+            _ => Err(EmailError::Unavailable),
+        }
+    }
+
+    /// Whether the provider in use can be reached, without sending anything.
+    /// Any response short of a server error counts as healthy.
+    pub async fn check_health(&self) -> Result<(), reqwest::Error> {
+        if self.mailbox.is_some() {
+            return Ok(());
+        }
+        let provider = match &self.secondary {
+            Some(secondary) if self.primary.circuit.state() == CircuitState::Open => secondary,
+            _ => &self.primary,
+        };
+        let response = self.http_client.get(&provider.base_url).send().await?;
+        if response.status().is_server_error() {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+// `message` without the copies to suppressed addresses, the recipients still
+// get it
+fn without_suppressed_copies<'a>(
+    message: &'a EmailMessage,
+    suppressed: &HashMap<String, String>,
+) -> Cow<'a, EmailMessage> {
+    let is_suppressed =
+        |address: &SubscriberEmail| suppressed.contains_key(&suppression_key(address));
+    if !message.copies().any(is_suppressed) {
+        return Cow::Borrowed(message);
+    }
+    tracing::warn!("Suppressed addresses are left out of the copies");
+    Cow::Owned(
+        message
+            .clone()
+            .retain_copies(|address| !is_suppressed(address)),
+    )
+}
+
+// keep an email in the mailbox, as if the provider had accepted it
+async fn catch(
+    mailbox: &Mailbox,
+    request_body: &impl serde::Serialize,
+) -> Result<SentEmail, EmailError> {
+    let message =
+        serde_json::to_value(request_body).map_err(|e| EmailError::Transient(e.into()))?;
+    let caught = mailbox
+        .catch(message)
+        .await
+        .map_err(EmailError::Transient)?;
+    Ok(SentEmail {
+        message_id: Some(caught.email_id.to_string()),
+        submitted_at: Some(caught.caught_at),
+    })
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkError {
+    error_code: i64,
+    message: String,
+}
+
+impl PostmarkError {
+    fn into_email_error(self, status: StatusCode) -> EmailError {
+        let names_a_recipient = RECIPIENT_FIELDS
+            .iter()
+            .any(|field| self.message.contains(field));
+        match self.error_code {
+            INACTIVE_RECIPIENT => EmailError::InactiveRecipient(self.message),
+            INVALID_EMAIL_REQUEST if names_a_recipient => EmailError::InvalidRecipient {
+                error_code: self.error_code,
+                message: self.message,
+            },
+            PENDING_ACCOUNT_RECIPIENT => EmailError::InvalidRecipient {
+                error_code: self.error_code,
+                message: self.message,
+            },
+            error_code => EmailError::Rejected {
+                status: status.as_u16(),
+                error_code: Some(error_code),
+                message: self.message,
+            },
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    submitted_at: Option<String>,
+}
+
+// one entry of the answer to a batch, in the order of the request
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResult {
+    error_code: i64,
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    submitted_at: Option<String>,
+}
+
+impl BatchMessageResult {
+    fn into_outcome(self) -> Result<SentEmail, EmailError> {
+        if self.error_code == 0 {
+            return Ok(SentEmail::from_response(self.message_id, self.submitted_at));
+        }
+        // the batch call succeeds as a whole, report the status a single
+        // send would have been refused with
+        let error = PostmarkError {
+            error_code: self.error_code,
+            message: self.message,
+        };
+        Err(error.into_email_error(StatusCode::UNPROCESSABLE_ENTITY))
+    }
+}
+
+async fn classify_response(response: reqwest::Response) -> Result<reqwest::Response, EmailError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(EmailError::RateLimited {
+            retry_after: retry_after(response.headers()),
+        });
+    }
+    if status.is_server_error() {
+        return Err(EmailError::Transient(anyhow::anyhow!(
+            "The email provider answered with {}.",
+            status
+        )));
+    }
+    // client errors are explained in the body
+    let error = match response.json::<PostmarkError>().await {
+        Ok(error) => error,
+        Err(_) => {
+            return Err(EmailError::Rejected {
+                status: status.as_u16(),
+                error_code: None,
+                message: status.to_string(),
+            })
+        }
+    };
+    Err(error.into_email_error(status))
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past means now
+    Some(
+        (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        retry_after, CircuitBreakerPolicy, CircuitState, EmailClient, EmailError, EmailMessage,
+        RetryPolicy, SendLimits, SentEmail, MAX_BATCH_SIZE,
+    };
+    use crate::metrics::Metrics;
+    use claim::{assert_err, assert_none, assert_ok, assert_some_eq};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use secrecy::Secret;
+    use std::time::{Duration, Instant};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::Request;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    // test helpers
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage::new(subject(), content(), content())
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            budget: Duration::from_secs(5),
+        }
+    }
+
+    fn postmark_error(status: u16, error_code: i64) -> ResponseTemplate {
+        postmark_error_saying(status, error_code, "Postmark says no.")
+    }
+
+    fn postmark_error_saying(status: u16, error_code: i64, message: &str) -> ResponseTemplate {
+        ResponseTemplate::new(status).set_body_json(serde_json::json!({
+            "ErrorCode": error_code,
+            "Message": message
+        }))
+    }
+
+    async fn send_with_retries(mock_server: &MockServer) -> Result<SentEmail, EmailError> {
+        email_client(mock_server.uri())
+            .with_retry_policy(retry_policy())
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        //act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Mock expectations are checked on drop
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_500() {
+        // arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_server_takes_too_long() {
+        // arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn check_health_succeeds_if_the_server_answers_with_a_client_error() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(email_client.check_health().await);
+    }
+
+    #[tokio::test]
+    async fn check_health_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(email_client.check_health().await);
+    }
+
+    #[tokio::test]
+    async fn a_server_error_is_retried_until_the_send_succeeds() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send_with_retries(&mock_server).await);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_up_to_the_maximum() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = send_with_retries(&mock_server).await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn a_timeout_is_a_transient_failure() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn an_invalid_request_is_not_retried() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(postmark_error(422, 300))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = send_with_retries(&mock_server).await;
+
+        // not taken for a problem with the recipient
+        assert!(matches!(
+            outcome,
+            Err(EmailError::Rejected { status: 422, error_code: Some(300), message })
+                if message == "Postmark says no."
+        ));
+    }
+
+    #[tokio::test]
+    async fn an_invalid_recipient_is_not_retried() {
+        for (error_code, message) in [
+            (300, "Invalid 'To' address: 'not an address'."),
+            (300, "Error parsing 'Cc': Illegal email address 'x@'."),
+            (412, "Recipients must be within the sender's domain."),
+        ] {
+            let mock_server = MockServer::start().await;
+            Mock::given(any())
+                .respond_with(postmark_error_saying(422, error_code, message))
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+
+            let outcome = send_with_retries(&mock_server).await;
+
+            assert!(
+                matches!(&outcome, Err(EmailError::InvalidRecipient { error_code: code, .. }) if *code == error_code),
+                "{:?}",
+                outcome
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn an_inactive_recipient_is_not_retried() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(postmark_error(422, 406))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = send_with_retries(&mock_server).await;
+
+        assert!(matches!(outcome, Err(EmailError::InactiveRecipient(_))));
+    }
+
+    #[tokio::test]
+    async fn other_client_errors_are_rejections() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(postmark_error(401, 10))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = send_with_retries(&mock_server).await;
+
+        assert!(matches!(
+            outcome,
+            Err(EmailError::Rejected {
+                status: 401,
+                error_code: Some(10),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rate_limiting_waits_as_long_as_retry_after_asks() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let started = Instant::now();
+
+        assert_ok!(send_with_retries(&mock_server).await);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn rate_limiting_beyond_the_budget_is_not_waited_for() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "60"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = send_with_retries(&mock_server).await;
+
+        assert!(matches!(
+            outcome,
+            Err(EmailError::RateLimited {
+                retry_after: Some(d)
+            }) if d == Duration::from_secs(60)
+        ));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            budget: Duration::from_secs(60),
+        };
+        let error = EmailError::Transient(anyhow::anyhow!("Try again"));
+        for _ in 0..100 {
+            assert!(policy.delay(0, &error, Duration::ZERO).unwrap() <= Duration::from_millis(100));
+            assert!(policy.delay(2, &error, Duration::ZERO).unwrap() <= Duration::from_millis(400));
+            assert!(
+                policy.delay(8, &error, Duration::ZERO).unwrap() <= Duration::from_millis(1000)
+            );
+        }
+        assert_none!(policy.delay(10, &error, Duration::ZERO));
+        assert_none!(policy.delay(0, &error, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert_some_eq!(retry_after(&headers("120")), Duration::from_secs(120));
+        assert_some_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Duration::ZERO
+        );
+        let in_a_minute = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        assert!(retry_after(&headers(&in_a_minute)).unwrap() > Duration::from_secs(50));
+        assert_none!(retry_after(&headers("soon")));
+        assert_none!(retry_after(&HeaderMap::new()));
+    }
+
+    fn batch_response(error_codes: &[i64]) -> ResponseTemplate {
+        let results: Vec<_> = error_codes
+            .iter()
+            .map(|&error_code| {
+                serde_json::json!({
+                    "ErrorCode": error_code,
+                    "Message": if error_code == 0 { "OK" } else { "Postmark says no." },
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_recipients_into_batches_the_provider_accepts() {
+        let mock_server = MockServer::start().await;
+        let recipients: Vec<_> = (0..MAX_BATCH_SIZE + 1).map(|_| email()).collect();
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .and(body_partial_json(
+                serde_json::json!([{"To": recipients[0].as_ref()}]),
+            ))
+            .respond_with(batch_response(&[0; MAX_BATCH_SIZE]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .and(body_partial_json(serde_json::json!([{
+                "To": recipients[MAX_BATCH_SIZE].as_ref()
+            }])))
+            .respond_with(batch_response(&[0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, &message())
+            .await;
+
+        assert_eq!(outcomes.len(), recipients.len());
+        assert!(outcomes.iter().all(|(_, outcome)| outcome.is_ok()));
+        for ((recipient, _), expected) in outcomes.iter().zip(&recipients) {
+            assert_eq!(recipient.as_ref(), expected.as_ref());
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_each_message_individually() {
+        let mock_server = MockServer::start().await;
+        let recipients = [email(), email(), email()];
+        Mock::given(any())
+            .respond_with(batch_response(&[0, 406, 300]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client(mock_server.uri())
+            .with_retry_policy(retry_policy())
+            .send_batch(&recipients, &message())
+            .await;
+
+        assert!(outcomes[0].1.is_ok());
+        assert!(matches!(
+            outcomes[1].1,
+            Err(EmailError::InactiveRecipient(_))
+        ));
+        assert!(matches!(
+            outcomes[2].1,
+            Err(EmailError::Rejected {
+                error_code: Some(300),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_a_failed_call_as_a_whole() {
+        let mock_server = MockServer::start().await;
+        let recipients = [email(), email()];
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(batch_response(&[0, 0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client(mock_server.uri())
+            .with_retry_policy(retry_policy())
+            .send_batch(&recipients, &message())
+            .await;
+
+        assert!(outcomes.iter().all(|(_, outcome)| outcome.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_message_of_a_failed_call() {
+        let mock_server = MockServer::start().await;
+        let recipients = [email(), email()];
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, &message())
+            .await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Err(EmailError::Transient(_)))));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_messages_the_provider_did_not_report_on() {
+        let mock_server = MockServer::start().await;
+        let recipients = [email(), email()];
+        Mock::given(any())
+            .respond_with(batch_response(&[0]))
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, &message())
+            .await;
+
+        assert!(outcomes[0].1.is_ok());
+        assert_err!(&outcomes[1].1);
+    }
+
+    #[tokio::test]
+    async fn sends_wait_for_a_free_slot_when_too_many_are_in_flight() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let email_client =
+            email_client(mock_server.uri()).with_limits(SendLimits::none().max_in_flight(1));
+        let recipient = email();
+        let send = || email_client.send_email(&recipient, "Subject", "html", "text");
+        let started = Instant::now();
+
+        let outcomes = futures_util::future::join_all([send(), send(), send()]).await;
+
+        assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn sends_held_back_by_the_limits_are_counted_as_waiting() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let metrics = Metrics::new().unwrap();
+        let email_client = email_client(mock_server.uri())
+            .with_limits(SendLimits::none().max_in_flight(1))
+            .with_metrics(metrics.email.clone());
+        let recipient = email();
+        let send = || email_client.send_email(&recipient, "Subject", "html", "text");
+        let sample = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            metrics.encode().unwrap()
+        };
+
+        let (outcomes, during) = tokio::join!(
+            futures_util::future::join_all([send(), send(), send()]),
+            sample
+        );
+
+        assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+        assert!(during.contains("email_sends_waiting 2"));
+        assert!(metrics.encode().unwrap().contains("email_sends_waiting 0"));
+    }
+
+    #[tokio::test]
+    async fn send_message_sends_the_message_options() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "ReplyTo": "support@example.com",
+                "Tag": "welcome",
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<mailto:unsubscribe@example.com>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let message = message()
+            .reply_to(SubscriberEmail::parse("support@example.com".into()).unwrap())
+            .tag("welcome")
+            .header("List-Unsubscribe", "<mailto:unsubscribe@example.com>");
+
+        let outcome = email_client(mock_server.uri())
+            .send_message(&email(), &message)
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_provider_message_id() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "SubmittedAt": "2014-02-17T07:25:01.4178645-05:00",
+                "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+                "ErrorCode": 0,
+                "Message": "OK"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let sent = email_client(mock_server.uri())
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sent.message_id.as_deref(),
+            Some("0a129aee-e1cd-480d-b08d-4f48548ff48d")
+        );
+        assert_eq!(
+            sent.submitted_at.unwrap().to_rfc3339(),
+            "2014-02-17T12:25:01.417864500+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_batch_returns_the_message_id_of_each_message() {
+        let mock_server = MockServer::start().await;
+        let recipients = [email(), email()];
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "first"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"},
+            ])))
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client(mock_server.uri())
+            .send_batch(&recipients, &message())
+            .await;
+
+        let sent = outcomes[0].1.as_ref().unwrap();
+        assert_eq!(sent.message_id.as_deref(), Some("first"));
+        assert_eq!(outcomes[1].1.as_ref().unwrap_err().error_code(), Some(406));
+    }
+
+    fn circuit_breaker() -> CircuitBreakerPolicy {
+        CircuitBreakerPolicy {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_fail_fast_once_the_circuit_is_open() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri()).with_circuit_breaker(circuit_breaker());
+        let recipient = email();
+        let send = || email_client.send_email(&recipient, "Subject", "html", "text");
+
+        assert!(matches!(send().await, Err(EmailError::Transient(_))));
+        assert!(matches!(send().await, Err(EmailError::Transient(_))));
+        assert!(matches!(send().await, Err(EmailError::Unavailable)));
+
+        assert_eq!(email_client.circuits(), [("primary", CircuitState::Open)]);
+    }
+
+    #[tokio::test]
+    async fn rejections_do_not_open_the_circuit() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(postmark_error(422, 300))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri()).with_circuit_breaker(circuit_breaker());
+
+        for _ in 0..3 {
+            let outcome = email_client
+                .send_email(&email(), "Subject", "html", "text")
+                .await;
+            assert!(matches!(outcome, Err(EmailError::Rejected { .. })));
+        }
+
+        assert_eq!(email_client.circuits(), [("primary", CircuitState::Closed)]);
+    }
+
+    #[tokio::test]
+    async fn the_secondary_takes_over_while_the_primary_circuit_is_open() {
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&primary)
+            .await;
+        Mock::given(path("/email"))
+            .and(header("X-Postmark-Server-Token", "secondary-token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&secondary)
+            .await;
+        let email_client = email_client(primary.uri())
+            .with_retry_policy(retry_policy())
+            .with_circuit_breaker(circuit_breaker())
+            .with_secondary(secondary.uri(), Secret::new("secondary-token".into()));
+
+        // the second retry goes to the secondary
+        let outcome = email_client
+            .send_email(&email(), "Subject", "html", "text")
+            .await;
+
+        assert_ok!(outcome);
+        assert_eq!(
+            email_client.circuits(),
+            [
+                ("primary", CircuitState::Open),
+                ("secondary", CircuitState::Closed)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn check_health_checks_the_secondary_while_it_is_in_use() {
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&secondary)
+            .await;
+        let email_client = email_client(primary.uri())
+            .with_circuit_breaker(circuit_breaker())
+            .with_secondary(secondary.uri(), Secret::new(Faker.fake()));
+        assert_err!(email_client.check_health().await);
+
+        for _ in 0..2 {
+            let _ = email_client
+                .send_email(&email(), "Subject", "html", "text")
+                .await;
+        }
+
+        assert_ok!(email_client.check_health().await);
+    }
+}
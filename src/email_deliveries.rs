@@ -0,0 +1,89 @@
+//! What happened to every email sent to a subscriber, kept in the
+//! `email_deliveries` table.
+use crate::email_client::{EmailError, SentEmail};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeliveryKind {
+    Confirmation,
+    NewsletterIssue(Uuid),
+}
+
+impl DeliveryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryKind::Confirmation => "confirmation",
+            DeliveryKind::NewsletterIssue(_) => "newsletter_issue",
+        }
+    }
+
+    fn newsletter_issue_id(&self) -> Option<Uuid> {
+        match self {
+            DeliveryKind::Confirmation => None,
+            DeliveryKind::NewsletterIssue(id) => Some(*id),
+        }
+    }
+}
+
+/// Record the outcome of sending `kind` to each subscriber, in one statement.
+#[tracing::instrument(name = "Record email deliveries", skip(pool, outcomes))]
+pub async fn record_deliveries(
+    pool: &PgPool,
+    kind: DeliveryKind,
+    outcomes: &[(Uuid, &Result<SentEmail, EmailError>)],
+) -> Result<(), anyhow::Error> {
+    let mut subscriber_ids = Vec::with_capacity(outcomes.len());
+    let mut statuses = Vec::with_capacity(outcomes.len());
+    let mut message_ids = Vec::with_capacity(outcomes.len());
+    let mut submitted_at = Vec::with_capacity(outcomes.len());
+    let mut error_codes = Vec::with_capacity(outcomes.len());
+    let mut errors = Vec::with_capacity(outcomes.len());
+    for (subscriber_id, outcome) in outcomes {
+        subscriber_ids.push(*subscriber_id);
+        match outcome {
+            Ok(sent) => {
+                statuses.push("sent".to_string());
+                message_ids.push(sent.message_id.clone());
+                submitted_at.push(sent.submitted_at);
+                error_codes.push(None);
+                errors.push(None);
+            }
+            Err(e) => {
+                let status = match e {
+                    EmailError::Suppressed(_) => "suppressed",
+                    _ => "failed",
+                };
+                statuses.push(status.to_string());
+                message_ids.push(None);
+                submitted_at.push(None);
+                error_codes.push(e.error_code());
+                errors.push(Some(e.to_string()));
+            }
+        }
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO email_deliveries (
+            subscriber_id, kind, newsletter_issue_id, status,
+            provider_message_id, submitted_at, error_code, error, created_at
+        )
+        SELECT subscriber_id, $1, $2, status, provider_message_id, submitted_at, error_code, error, now()
+        FROM UNNEST($3::uuid[], $4::text[], $5::text[], $6::timestamptz[], $7::bigint[], $8::text[])
+            AS t(subscriber_id, status, provider_message_id, submitted_at, error_code, error)
+        "#,
+        kind.as_str(),
+        kind.newsletter_issue_id(),
+        &subscriber_ids,
+        &statuses,
+        &message_ids as &[Option<String>],
+        &submitted_at as &[Option<chrono::DateTime<chrono::Utc>>],
+        &error_codes as &[Option<i64>],
+        &errors as &[Option<String>],
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record email deliveries.")?;
+    Ok(())
+}
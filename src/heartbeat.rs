@@ -0,0 +1,105 @@
+//! Background workers report that they are alive here, for the readiness check.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Default)]
+pub struct Heartbeats {
+    workers: Arc<Mutex<BTreeMap<&'static str, Worker>>>,
+}
+
+struct Worker {
+    last_beat: Instant,
+    max_interval: Duration,
+}
+
+/// Held by a worker, which calls [`Heartbeat::beat`] at least every
+/// `max_interval`.
+#[derive(Clone)]
+pub struct Heartbeat {
+    name: &'static str,
+    heartbeats: Heartbeats,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct WorkerStatus {
+    pub name: &'static str,
+    pub seconds_since_last_beat: f64,
+    pub stale: bool,
+}
+
+impl Heartbeats {
+    /// Start tracking a worker. It counts as alive until `max_interval` has
+    /// passed without a beat.
+    pub fn register(&self, name: &'static str, max_interval: Duration) -> Heartbeat {
+        self.workers.lock().unwrap().insert(
+            name,
+            Worker {
+                last_beat: Instant::now(),
+                max_interval,
+            },
+        );
+        Heartbeat {
+            name,
+            heartbeats: self.clone(),
+        }
+    }
+
+    pub fn status(&self) -> Vec<WorkerStatus> {
+        let now = Instant::now();
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, worker)| {
+                let elapsed = now.duration_since(worker.last_beat);
+                WorkerStatus {
+                    name,
+                    seconds_since_last_beat: elapsed.as_secs_f64(),
+                    stale: elapsed > worker.max_interval,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        if let Some(worker) = self.heartbeats.workers.lock().unwrap().get_mut(self.name) {
+            worker.last_beat = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Heartbeats;
+    use std::time::Duration;
+
+    #[test]
+    fn a_worker_is_fresh_right_after_registering() {
+        let heartbeats = Heartbeats::default();
+        heartbeats.register("delivery", Duration::from_secs(60));
+        let status = heartbeats.status();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].name, "delivery");
+        assert!(!status[0].stale);
+    }
+
+    #[test]
+    fn a_worker_without_a_recent_beat_is_stale() {
+        let heartbeats = Heartbeats::default();
+        heartbeats.register("delivery", Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(heartbeats.status()[0].stale);
+    }
+
+    #[test]
+    fn beating_resets_the_time_since_the_last_beat() {
+        let heartbeats = Heartbeats::default();
+        let heartbeat = heartbeats.register("delivery", Duration::from_secs(60));
+        std::thread::sleep(Duration::from_millis(100));
+        heartbeat.beat();
+        assert!(heartbeats.status()[0].seconds_since_last_beat < 0.1);
+    }
+}
@@ -1,7 +1,17 @@
+pub mod audit;
 pub mod authentication;
+pub mod cli;
 pub mod configuration;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod email_deliveries;
+pub mod heartbeat;
+pub mod metrics;
+pub mod migrations;
 pub mod routes;
+pub mod session_state;
 pub mod startup;
+pub mod suppressions;
 pub mod telemetry;
+pub mod utils;
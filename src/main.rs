@@ -1,14 +1,43 @@
+use anyhow::Context;
+use clap::Parser;
+use opentelemetry::trace::TracerProvider;
+use zero2prod::cli::{Cli, Command};
 use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{get_subscriber, get_tracer_provider, init_subscriber};
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-    // panic if we cannot get a config file for the database
-    let configuration = get_configuration().expect("Failed to read configuration file");
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
-    Ok(())
+async fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let configuration = get_configuration().context("Failed to read configuration.")?;
+    // the server logs to stdout, other commands keep stdout for their output
+    let (tracer_provider, log_filter) = if matches!(cli.command, None | Some(Command::Serve)) {
+        let tracer_provider = get_tracer_provider("zero2prod", &configuration.telemetry)
+            .context("Failed to set up the span exporter.")?;
+        let tracer = tracer_provider
+            .as_ref()
+            .map(|provider| provider.tracer("zero2prod"));
+        let (subscriber, log_filter) = get_subscriber(
+            "zero2prod".into(),
+            "info".into(),
+            std::io::stdout,
+            tracer,
+            &configuration.telemetry.pii,
+        );
+        init_subscriber(subscriber);
+        (tracer_provider, log_filter)
+    } else {
+        let (subscriber, log_filter) = get_subscriber(
+            "zero2prod".into(),
+            "warn".into(),
+            std::io::stderr,
+            None,
+            &configuration.telemetry.pii,
+        );
+        init_subscriber(subscriber);
+        (None, log_filter)
+    };
+    let outcome = cli.run(configuration, log_filter).await;
+    // flush the spans that have not been exported yet
+    drop(tracer_provider);
+    outcome
 }
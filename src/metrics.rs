@@ -0,0 +1,280 @@
+//! Prometheus metrics, served as text on `/metrics`.
+use crate::heartbeat::Heartbeat;
+use crate::utils::constant_time_eq;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+
+/// How often [`sample_pool`] samples the connection pool.
+pub const POOL_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    pub subscriptions: IntCounter,
+    pub subscription_confirmations: IntCounter,
+    pub email: EmailMetrics,
+}
+
+/// Outcomes of the calls to the email providers, the calls held back by the
+/// send limits, and the state of their circuit breakers.
+///
+/// The default instance is not registered anywhere, for email clients built
+/// outside of the application.
+#[derive(Clone)]
+pub struct EmailMetrics {
+    sends: IntCounterVec,
+    send_duration: HistogramVec,
+    circuit_open: IntGaugeVec,
+    circuit_trips: IntCounterVec,
+    failovers: IntCounter,
+    waiting: IntGauge,
+}
+
+/// Counts a call in `email_sends_waiting` for as long as it is held.
+pub struct WaitingSend(IntGauge);
+
+impl Drop for WaitingSend {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl Default for EmailMetrics {
+    fn default() -> Self {
+        Self {
+            sends: IntCounterVec::new(
+                Opts::new("email_sends_total", "Emails handed to the email provider."),
+                &["outcome", "status"],
+            )
+            .unwrap(),
+            send_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "email_send_duration_seconds",
+                    "Time taken by the email provider to answer.",
+                ),
+                &["status"],
+            )
+            .unwrap(),
+            circuit_open: IntGaugeVec::new(
+                Opts::new(
+                    "email_circuit_open",
+                    "Whether calls to the email provider are refused, 1 if so.",
+                ),
+                &["provider"],
+            )
+            .unwrap(),
+            circuit_trips: IntCounterVec::new(
+                Opts::new(
+                    "email_circuit_trips_total",
+                    "Times the circuit breaker of the email provider opened.",
+                ),
+                &["provider"],
+            )
+            .unwrap(),
+            failovers: IntCounter::new(
+                "email_failovers_total",
+                "Calls sent to the secondary email provider while the primary's circuit was open.",
+            )
+            .unwrap(),
+            waiting: IntGauge::new(
+                "email_sends_waiting",
+                "Calls to the email provider waiting on the send rate or in-flight limit.",
+            )
+            .unwrap(),
+        }
+    }
+}
+
+impl std::fmt::Debug for EmailMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailMetrics").finish_non_exhaustive()
+    }
+}
+
+impl EmailMetrics {
+    /// `status` is the HTTP status code returned by the provider, or `error`
+    /// if there was no response.
+    pub fn record(&self, success: bool, status: &str, duration: Duration) {
+        let outcome = if success { "success" } else { "failure" };
+        self.sends.with_label_values(&[outcome, status]).inc();
+        self.send_duration
+            .with_label_values(&[status])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// The circuit of `provider` opened, or closed again.
+    pub fn record_circuit(&self, provider: &str, open: bool) {
+        self.circuit_open
+            .with_label_values(&[provider])
+            .set(open as i64);
+        if open {
+            self.circuit_trips.with_label_values(&[provider]).inc();
+        }
+    }
+
+    /// Report `provider` as closed before any call is made.
+    pub fn init_circuit(&self, provider: &str) {
+        self.circuit_open.with_label_values(&[provider]).set(0);
+        self.circuit_trips.with_label_values(&[provider]);
+    }
+
+    pub fn record_failover(&self) {
+        self.failovers.inc();
+    }
+
+    /// A call starts waiting on the send limits, until the guard is dropped.
+    pub fn start_waiting(&self) -> WaitingSend {
+        self.waiting.inc();
+        WaitingSend(self.waiting.clone())
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests.",
+            ),
+            &["method", "route"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Postgres connections in the pool."),
+            &["state"],
+        )?;
+        let subscriptions = IntCounter::new(
+            "subscriptions_total",
+            "Subscription requests that sent a confirmation email.",
+        )?;
+        let subscription_confirmations = IntCounter::new(
+            "subscription_confirmations_total",
+            "Subscriptions confirmed through their link.",
+        )?;
+        let email = EmailMetrics::default();
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(subscriptions.clone()))?;
+        registry.register(Box::new(subscription_confirmations.clone()))?;
+        registry.register(Box::new(email.sends.clone()))?;
+        registry.register(Box::new(email.send_duration.clone()))?;
+        registry.register(Box::new(email.circuit_open.clone()))?;
+        registry.register(Box::new(email.circuit_trips.clone()))?;
+        registry.register(Box::new(email.failovers.clone()))?;
+        registry.register(Box::new(email.waiting.clone()))?;
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            subscriptions,
+            subscription_confirmations,
+            email,
+        })
+    }
+
+    fn record_pool(&self, pool: &PgPool) {
+        let size = pool.size() as i64;
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+    }
+
+    /// Render every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("The text format is UTF-8"))
+    }
+}
+
+/// Sample the connection pool gauges every [`POOL_SAMPLE_INTERVAL`], beating
+/// `heartbeat` each time. Runs until the runtime shuts down.
+pub async fn sample_pool(metrics: Metrics, pool: PgPool, heartbeat: Heartbeat) {
+    let mut interval = tokio::time::interval(POOL_SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        metrics.record_pool(&pool);
+        heartbeat.beat();
+    }
+}
+
+/// Count and time every request, by route pattern rather than path so that
+/// ids in the path do not create a series each.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let result = next.call(req).await;
+    if let Some(metrics) = metrics {
+        let (route, status) = match &result {
+            Ok(response) => (
+                response.request().match_pattern(),
+                response.status().as_u16(),
+            ),
+            Err(e) => (None, e.as_response_error().status_code().as_u16()),
+        };
+        let route = route.unwrap_or_else(|| "unmatched".into());
+        metrics
+            .http_requests
+            .with_label_values(&[&method, &route, &status.to_string()])
+            .inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&[&method, &route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    result
+}
+
+/// Required in an `Authorization: Bearer` header when metrics are served on the
+/// main listener.
+#[derive(Clone)]
+pub struct MetricsToken(pub Secret<String>);
+
+pub async fn metrics_endpoint(
+    request: HttpRequest,
+    metrics: web::Data<Metrics>,
+    token: Option<web::Data<MetricsToken>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(token) = token {
+        let provided = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !constant_time_eq(provided.as_bytes(), token.0.expose_secret().as_bytes()) {
+            return Ok(HttpResponse::Unauthorized().finish());
+        }
+    }
+    let body = metrics.encode().map_err(crate::utils::e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(TextEncoder::new().format_type())
+        .body(body))
+}
@@ -0,0 +1,96 @@
+//! The `migrations/` directory, embedded into the binary.
+use crate::routes::error_chain_fmt;
+use anyhow::Context;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::PgPool;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(thiserror::Error)]
+pub enum MigrationError {
+    #[error("Migration {0} was applied to the database but has been modified since.")]
+    ChecksumMismatch(i64),
+    #[error("Migration {0} was applied to the database but is not part of this build.")]
+    UnknownMigration(i64),
+    #[error("Failed to migrate the database.")]
+    UnexpectedError(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<MigrateError> for MigrationError {
+    fn from(e: MigrateError) -> Self {
+        match e {
+            MigrateError::VersionMismatch(version) => Self::ChecksumMismatch(version),
+            MigrateError::VersionMissing(version) => Self::UnknownMigration(version),
+            e => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+/// Apply the pending migrations.
+///
+/// The migrator holds a Postgres advisory lock while it runs, so when several
+/// instances start at once one of them migrates and the others wait for it,
+/// then find nothing left to do.
+#[tracing::instrument(name = "Run database migrations", skip(pool))]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrationError> {
+    MIGRATOR.run(pool).await?;
+    Ok(())
+}
+
+/// How the database compares to the migrations embedded in this build.
+#[derive(serde::Serialize, Debug)]
+pub struct MigrationStatus {
+    /// The latest successfully applied migration, if any.
+    pub applied_version: Option<i64>,
+    /// The latest migration embedded in this build.
+    pub latest_version: Option<i64>,
+    pub pending: Vec<i64>,
+    /// Applied migrations whose contents no longer match this build.
+    pub modified: Vec<i64>,
+}
+
+#[tracing::instrument(name = "Get migration status", skip(pool))]
+pub async fn migration_status(pool: &PgPool) -> Result<MigrationStatus, anyhow::Error> {
+    // the table is created by the first migration run
+    let table_exists: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await
+            .context("Failed to look up the migrations table.")?;
+    let applied: Vec<(i64, Vec<u8>)> = if table_exists {
+        sqlx::query_as(
+            "SELECT version, checksum FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(pool)
+        .await
+        .context("Failed to retrieve the applied migrations.")?
+    } else {
+        Vec::new()
+    };
+    let mut pending = Vec::new();
+    let mut modified = Vec::new();
+    for migration in MIGRATOR.iter() {
+        match applied
+            .iter()
+            .find(|(version, _)| *version == migration.version)
+        {
+            None => pending.push(migration.version),
+            Some((_, checksum)) if checksum[..] != migration.checksum[..] => {
+                modified.push(migration.version)
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(MigrationStatus {
+        applied_version: applied.last().map(|(version, _)| *version),
+        latest_version: MIGRATOR.iter().map(|m| m.version).max(),
+        pending,
+        modified,
+    })
+}
@@ -0,0 +1,100 @@
+use crate::authentication::{list_api_tokens, ApiScope, ApiTokenSummary, UserId};
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+pub async fn api_tokens_form(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let tokens = list_api_tokens(&pool, *user_id).await.map_err(e500)?;
+    let csrf = csrf_field(&request)?;
+    let rows: String = tokens.iter().map(|t| token_row(t, &csrf)).collect();
+    let scope_checkboxes: String = ApiScope::ALL
+        .iter()
+        .map(|scope| {
+            format!(
+                r#"<label><input type="checkbox" name="scope" value="{0}" /> {0}</label><br />"#,
+                scope.as_str()
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>API tokens</title>
+  </head>
+  <body>
+    {}
+    <table>
+      <tr>
+        <th>Name</th><th>Scopes</th><th>Created</th><th>Expires</th><th>Last used</th><th>Status</th><th></th>
+      </tr>
+      {}
+    </table>
+    <h2>New token</h2>
+    <form action="/admin/api-tokens" method="post">
+      {}
+      <label>Name <input type="text" name="name" placeholder="CI pipeline" /></label>
+      <br />
+      {}
+      <label
+        >Expires in (days, empty for never)
+        <input type="number" name="expires_in_days" min="1" max="365" />
+      </label>
+      <br />
+      <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            rows,
+            csrf,
+            scope_checkboxes
+        )))
+}
+
+fn token_row(token: &ApiTokenSummary, csrf: &str) -> String {
+    let status = match (token.revoked_at, token.is_active()) {
+        (Some(_), _) => "revoked",
+        (None, false) => "expired",
+        (None, true) => "active",
+    };
+    let revoke_form = if token.revoked_at.is_none() {
+        format!(
+            r#"<form action="/admin/api-tokens/{}/revoke" method="post">{}<button type="submit">Revoke</button></form>"#,
+            token.token_id, csrf
+        )
+    } else {
+        "".into()
+    };
+    format!(
+        "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        htmlescape::encode_minimal(&token.name),
+        htmlescape::encode_minimal(&token.scopes.join(", ")),
+        format_timestamp(Some(token.created_at)),
+        format_timestamp(token.expires_at),
+        format_timestamp(token.last_used_at),
+        status,
+        revoke_form
+    )
+}
+
+fn format_timestamp(timestamp: Option<DateTime<Utc>>) -> String {
+    timestamp.map_or_else(
+        || "-".into(),
+        |t| t.format("%Y-%m-%d %H:%M UTC").to_string(),
+    )
+}
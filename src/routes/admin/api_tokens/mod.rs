@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::api_tokens_form;
+pub use post::{create_api_token_for_user, revoke_api_token_for_user};
@@ -0,0 +1,147 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    create_api_token, get_user_role, revoke_api_token, ApiScope, NewApiToken, UserId,
+};
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// the form repeats `scope` once per ticked checkbox, so it is read as raw pairs
+#[derive(serde::Deserialize)]
+#[serde(transparent)]
+pub struct FormData(Vec<(String, String)>);
+
+impl TryFrom<FormData> for NewApiToken {
+    type Error = String;
+
+    fn try_from(form: FormData) -> Result<Self, Self::Error> {
+        let mut name = None;
+        let mut scopes = Vec::new();
+        let mut expires_at = None;
+        for (key, value) in form.0 {
+            match key.as_str() {
+                "name" => name = Some(value.trim().to_string()),
+                "scope" => scopes.push(ApiScope::try_from(value.as_str())?),
+                "expires_in_days" if !value.trim().is_empty() => {
+                    let days: i64 = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("{} is not a number of days.", value))?;
+                    if !(1..=365).contains(&days) {
+                        return Err("Tokens must expire within 1 to 365 days.".into());
+                    }
+                    expires_at = Some(Utc::now() + Duration::days(days));
+                }
+                _ => {}
+            }
+        }
+        let name = name
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| "Give the token a name.".to_string())?;
+        if scopes.is_empty() {
+            return Err("Select at least one scope.".into());
+        }
+        Ok(Self {
+            name,
+            scopes,
+            expires_at,
+        })
+    }
+}
+
+#[tracing::instrument(name = "Create an API token", skip(form, pool, audit), fields(user_id=%*user_id))]
+pub async fn create_api_token_for_user(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let new_token: NewApiToken = match form.0.try_into() {
+        Ok(new_token) => new_token,
+        Err(e) => return Ok(see_other_with_flash("/admin/api-tokens", &e)),
+    };
+    // a token must not be able to do more than its owner
+    let role = get_user_role(&pool, *user_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e500("The logged in user does not exist"))?;
+    if let Some(scope) = new_token
+        .scopes
+        .iter()
+        .find(|scope| !role.can(scope.required_permission()))
+    {
+        return Ok(see_other_with_flash(
+            "/admin/api-tokens",
+            &format!(
+                "Your role does not allow creating tokens with the '{}' scope.",
+                scope.as_str()
+            ),
+        ));
+    }
+    let (token_id, token) = create_api_token(&pool, *user_id, &new_token)
+        .await
+        .map_err(e500)?;
+    let scopes: Vec<&str> = new_token.scopes.iter().map(|s| s.as_str()).collect();
+    let event = AuditEvent::new(AuditAction::ApiTokenCreate)
+        .actor(*user_id)
+        .target("api_token", token_id)
+        .changes(serde_json::json!({
+            "name": new_token.name,
+            "scopes": scopes,
+            "expires_at": new_token.expires_at.map(|t| t.to_rfc3339()),
+        }));
+    record_audit_event(&pool, &audit, event)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>API token created</title>
+  </head>
+  <body>
+    <p>Your new token for {}:</p>
+    <p><code id="api-token">{}</code></p>
+    <p>Copy it now, it will not be shown again.</p>
+    <p><a href="/admin/api-tokens">Continue</a></p>
+  </body>
+</html>"#,
+            htmlescape::encode_minimal(&new_token.name),
+            token.expose_secret()
+        )))
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(pool, audit), fields(user_id=%*user_id))]
+pub async fn revoke_api_token_for_user(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let token_id = token_id.into_inner();
+    let message = if revoke_api_token(&pool, *user_id, token_id)
+        .await
+        .map_err(e500)?
+    {
+        let event = AuditEvent::new(AuditAction::ApiTokenRevoke)
+            .actor(*user_id)
+            .target("api_token", token_id);
+        record_audit_event(&pool, &audit, event)
+            .await
+            .map_err(e500)?;
+        "The token has been revoked."
+    } else {
+        "There is no such token."
+    };
+    Ok(see_other_with_flash("/admin/api-tokens", message))
+}
@@ -0,0 +1,236 @@
+use crate::audit::{search_audit_log, AuditAction, AuditLogEntry, AuditLogFilter};
+use crate::utils::e500;
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use sqlx::PgPool;
+
+const PAGE_SIZE: i64 = 50;
+// an upper bound on the export, rather than an unbounded query
+const EXPORT_LIMIT: i64 = 100_000;
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    actor: Option<String>,
+    action: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    page: Option<i64>,
+}
+
+impl QueryParams {
+    /// Empty form fields are ignored, both dates are inclusive.
+    fn filter(&self) -> Result<AuditLogFilter, actix_web::Error> {
+        fn non_empty(s: &Option<String>) -> Option<&str> {
+            s.as_deref().map(str::trim).filter(|s| !s.is_empty())
+        }
+        fn date(s: &str) -> Result<NaiveDate, actix_web::Error> {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+                actix_web::error::ErrorBadRequest(format!("{} is not a YYYY-MM-DD date.", s))
+            })
+        }
+        let from = non_empty(&self.from).map(date).transpose()?;
+        let to = non_empty(&self.to).map(date).transpose()?;
+        Ok(AuditLogFilter {
+            actor: non_empty(&self.actor).map(str::to_owned),
+            action: non_empty(&self.action).map(str::to_owned),
+            from: from.map(|d| Utc.from_utc_datetime(&d.and_hms(0, 0, 0))),
+            to: to.map(|d| Utc.from_utc_datetime(&(d + Duration::days(1)).and_hms(0, 0, 0))),
+        })
+    }
+
+    // the filters, to be carried over to the pagination and export links
+    fn filter_query_string(&self) -> String {
+        [
+            ("actor", &self.actor),
+            ("action", &self.action),
+            ("from", &self.from),
+            ("to", &self.to),
+        ]
+        .iter()
+        .filter_map(|(key, value)| {
+            value
+                .as_deref()
+                .filter(|v| !v.is_empty())
+                .map(|v| format!("{}={}", key, urlencoding::encode(v)))
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+    }
+}
+
+pub async fn admin_audit_log(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = query.filter()?;
+    let page = query.page.unwrap_or(1).max(1);
+    let entries = search_audit_log(&pool, &filter, PAGE_SIZE, (page - 1) * PAGE_SIZE)
+        .await
+        .map_err(e500)?;
+    let has_next_page = entries.len() as i64 == PAGE_SIZE;
+    let rows: String = entries
+        .iter()
+        .map(|e| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+                e.occurred_at.format("%Y-%m-%d %H:%M:%S"),
+                htmlescape::encode_minimal(e.actor.as_deref().unwrap_or("-")),
+                htmlescape::encode_minimal(&e.action),
+                htmlescape::encode_minimal(&target(e)),
+                htmlescape::encode_minimal(e.ip.as_deref().unwrap_or("")),
+                htmlescape::encode_minimal(e.user_agent.as_deref().unwrap_or("")),
+                htmlescape::encode_minimal(&e.changes.to_string()),
+            )
+        })
+        .collect();
+    let action_options: String = AuditAction::ALL
+        .iter()
+        .map(|action| {
+            let selected = if filter.action.as_deref() == Some(action.as_str()) {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                action.as_str(),
+                selected
+            )
+        })
+        .collect();
+    let filters = query.filter_query_string();
+    let mut pagination = String::new();
+    if page > 1 {
+        pagination.push_str(&format!(
+            r#"<a href="/admin/audit-log?{}&page={}">Previous</a> "#,
+            htmlescape::encode_attribute(&filters),
+            page - 1
+        ));
+    }
+    if has_next_page {
+        pagination.push_str(&format!(
+            r#"<a href="/admin/audit-log?{}&page={}">Next</a>"#,
+            htmlescape::encode_attribute(&filters),
+            page + 1
+        ));
+    }
+    let value = |s: &Option<String>| htmlescape::encode_attribute(s.as_deref().unwrap_or(""));
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Audit log</title>
+  </head>
+  <body>
+    <form action="/admin/audit-log" method="get">
+      <label>Actor <input type="text" name="actor" value="{}"></label>
+      <label>Action
+        <select name="action"><option value="">Any</option>{}</select>
+      </label>
+      <label>From <input type="date" name="from" value="{}"></label>
+      <label>To <input type="date" name="to" value="{}"></label>
+      <button type="submit">Filter</button>
+    </form>
+    <p><a href="/admin/audit-log.csv?{}">Export as CSV</a></p>
+    <table>
+      <tr><th>When (UTC)</th><th>Actor</th><th>Action</th><th>Target</th><th>IP</th><th>User agent</th><th>Changes</th></tr>
+      {}
+    </table>
+    <p>{}</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            value(&query.actor),
+            action_options,
+            value(&query.from),
+            value(&query.to),
+            htmlescape::encode_attribute(&filters),
+            rows,
+            pagination
+        )))
+}
+
+pub async fn admin_audit_log_csv(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = query.filter()?;
+    let entries = search_audit_log(&pool, &filter, EXPORT_LIMIT, 0)
+        .await
+        .map_err(e500)?;
+    let mut csv =
+        String::from("occurred_at,actor,action,target_type,target_id,ip,user_agent,changes\r\n");
+    for e in &entries {
+        let fields = [
+            e.occurred_at.to_rfc3339(),
+            e.actor.clone().unwrap_or_default(),
+            e.action.clone(),
+            e.target_type.clone().unwrap_or_default(),
+            e.target_id.clone().unwrap_or_default(),
+            e.ip.clone().unwrap_or_default(),
+            e.user_agent.clone().unwrap_or_default(),
+            e.changes.to_string(),
+        ];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&line.join(","));
+        csv.push_str("\r\n");
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("audit-log.csv".into())],
+        })
+        .body(csv))
+}
+
+fn target(entry: &AuditLogEntry) -> String {
+    match (&entry.target_type, &entry.target_id) {
+        (Some(target_type), Some(target_id)) => format!("{} {}", target_type, target_id),
+        _ => String::new(),
+    }
+}
+
+// RFC 4180 quoting. Fields that a spreadsheet would read as a formula get a
+// leading quote, so that an attacker-controlled user agent cannot run one.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_owned()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        assert_eq!(csv_field("login"), "login");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn fields_with_separators_or_quotes_are_quoted() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn formulas_are_neutralised() {
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+    }
+}
@@ -0,0 +1,92 @@
+use crate::authentication::{get_user_role, Permission, UserId};
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn admin_dashboard(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let role = get_user_role(&pool, *user_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e500("The logged in user does not exist"))?;
+    // only link to the pages the user's role gives access to
+    let links: String = [
+        (Permission::ViewStats, "/admin/stats", "Statistics"),
+        (
+            Permission::ViewSubscribers,
+            "/admin/subscribers",
+            "Subscribers",
+        ),
+        (Permission::ManageUsers, "/admin/users", "Users"),
+        (Permission::ManageSettings, "/admin/settings", "Settings"),
+        (
+            Permission::ManageSettings,
+            "/admin/log-filter",
+            "Log filter",
+        ),
+        (Permission::ViewAuditLog, "/admin/audit-log", "Audit log"),
+    ]
+    .into_iter()
+    .filter(|(permission, _, _)| role.can(*permission))
+    .map(|(_, href, label)| format!(r#"<li><a href="{}">{}</a></li>"#, href, label))
+    .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Admin dashboard</title>
+  </head>
+  <body>
+    {}
+    <p>Welcome {}! You are signed in as {}.</p>
+    <p>Available actions:</p>
+    <ol>
+      <li><a href="/admin/two-factor">Two-factor authentication</a></li>
+      <li><a href="/admin/api-tokens">API tokens</a></li>
+      {}
+      <li>
+        <form name="logoutForm" action="/admin/logout" method="post">
+          {}
+          <input type="submit" value="Logout" />
+        </form>
+      </li>
+    </ol>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            htmlescape::encode_minimal(&username),
+            role.as_str(),
+            links,
+            csrf_field(&request)?
+        )))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
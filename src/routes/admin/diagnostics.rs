@@ -0,0 +1,18 @@
+use crate::migrations::{migration_status, MigrationStatus};
+use crate::utils::e500;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+#[derive(serde::Serialize)]
+struct Diagnostics {
+    version: &'static str,
+    migrations: MigrationStatus,
+}
+
+pub async fn admin_diagnostics(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let migrations = migration_status(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok().json(Diagnostics {
+        version: env!("CARGO_PKG_VERSION"),
+        migrations,
+    }))
+}
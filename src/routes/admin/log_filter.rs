@@ -0,0 +1,139 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::UserId;
+use crate::csrf::csrf_field;
+use crate::telemetry::{LogFilter, LogFilterError};
+use crate::utils::{clear_flash_cookie, e500, flash_message_html, see_other_with_flash};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use std::time::Duration;
+
+pub async fn admin_log_filter_form(
+    request: HttpRequest,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let reverts = match log_filter.reverts_at() {
+        Some(at) => format!(
+            "<p>Reverts to <code>{}</code> at {}.</p>",
+            htmlescape::encode_minimal(log_filter.default_directives()),
+            at.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        None => "".into(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Log filter</title>
+  </head>
+  <body>
+    {}
+    <p>Active filter: <code>{}</code></p>
+    {}
+    <form action="/admin/log-filter" method="post">
+      {}
+      <label>Directives
+        <input type="text" name="directives" value="{}" />
+      </label>
+      <br />
+      <label>Revert after (minutes, blank to keep)
+        <input type="number" name="ttl_minutes" min="1" max="1440" />
+      </label>
+      <br />
+      <button type="submit">Apply</button>
+    </form>
+    <form action="/admin/log-filter/reset" method="post">
+      {}
+      <button type="submit">Reset to <code>{}</code></button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            htmlescape::encode_minimal(&log_filter.directives()),
+            reverts,
+            csrf_field(&request)?,
+            htmlescape::encode_attribute(&log_filter.directives()),
+            csrf_field(&request)?,
+            htmlescape::encode_minimal(log_filter.default_directives()),
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct LogFilterFormData {
+    directives: String,
+    // blank when the filter should stay until changed again
+    ttl_minutes: String,
+}
+
+#[tracing::instrument(name = "Change the log filter", skip(form, pool, log_filter, audit), fields(user_id=%*user_id))]
+pub async fn update_log_filter(
+    form: web::Form<LogFilterFormData>,
+    pool: web::Data<PgPool>,
+    log_filter: web::Data<LogFilter>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ttl_minutes = match form.ttl_minutes.trim() {
+        "" => None,
+        minutes => match minutes.parse::<u64>() {
+            Ok(minutes) if minutes > 0 => Some(minutes),
+            _ => {
+                return Ok(see_other_with_flash(
+                    "/admin/log-filter",
+                    "The time before reverting must be a whole number of minutes.",
+                ))
+            }
+        },
+    };
+    let previous = log_filter.directives();
+    let ttl = ttl_minutes.map(|minutes| Duration::from_secs(minutes * 60));
+    match log_filter.set(form.directives.trim(), ttl) {
+        Ok(()) => {}
+        Err(e @ (LogFilterError::InvalidDirectives(_) | LogFilterError::TtlTooLong)) => {
+            return Ok(see_other_with_flash("/admin/log-filter", &e.to_string()))
+        }
+        Err(e) => return Err(e500(e)),
+    }
+    let event = AuditEvent::new(AuditAction::LogFilterChange)
+        .actor(**user_id)
+        .changes(serde_json::json!({
+            "directives": {"old": previous, "new": log_filter.directives()},
+            "ttl_minutes": ttl_minutes,
+        }));
+    record_audit_event(&pool, &audit, event)
+        .await
+        .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/log-filter",
+        "The log filter has been changed.",
+    ))
+}
+
+#[tracing::instrument(name = "Reset the log filter", skip(pool, log_filter, audit), fields(user_id=%*user_id))]
+pub async fn reset_log_filter(
+    pool: web::Data<PgPool>,
+    log_filter: web::Data<LogFilter>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let previous = log_filter.directives();
+    log_filter.reset().map_err(e500)?;
+    let event = AuditEvent::new(AuditAction::LogFilterChange)
+        .actor(**user_id)
+        .changes(serde_json::json!({
+            "directives": {"old": previous, "new": log_filter.directives()},
+        }));
+    record_audit_event(&pool, &audit, event)
+        .await
+        .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/log-filter",
+        "The log filter has been reset.",
+    ))
+}
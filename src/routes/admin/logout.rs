@@ -0,0 +1,23 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::UserId;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn log_out(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let event = AuditEvent::new(AuditAction::Logout).actor(**user_id);
+    record_audit_event(&pool, &audit, event)
+        .await
+        .map_err(e500)?;
+    session.log_out();
+    Ok(see_other_with_flash(
+        "/login",
+        "You have successfully logged out.",
+    ))
+}
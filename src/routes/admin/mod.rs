@@ -0,0 +1,22 @@
+mod api_tokens;
+mod audit_log;
+mod dashboard;
+mod diagnostics;
+mod log_filter;
+mod logout;
+mod settings;
+mod stats;
+mod subscribers;
+mod two_factor;
+mod users;
+pub use api_tokens::*;
+pub use audit_log::*;
+pub use dashboard::*;
+pub use diagnostics::*;
+pub use log_filter::*;
+pub use logout::*;
+pub use settings::*;
+pub use stats::*;
+pub use subscribers::*;
+pub use two_factor::*;
+pub use users::*;
@@ -0,0 +1,42 @@
+use crate::authentication::two_factor_required;
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn admin_settings_form(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let require_two_factor = two_factor_required(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Settings</title>
+  </head>
+  <body>
+    {}
+    <form action="/admin/settings" method="post">
+      {}
+      <label>
+        <input type="checkbox" name="require_two_factor" value="on" {} />
+        Require two-factor authentication for every admin user
+      </label>
+      <br />
+      <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            csrf_field(&request)?,
+            if require_two_factor { "checked" } else { "" }
+        )))
+}
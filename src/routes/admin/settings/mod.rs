@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::admin_settings_form;
+pub use post::update_admin_settings;
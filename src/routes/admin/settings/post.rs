@@ -0,0 +1,39 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{set_two_factor_required, two_factor_required, UserId};
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // unchecked checkboxes are not submitted at all
+    require_two_factor: Option<String>,
+}
+
+#[tracing::instrument(name = "Update admin settings", skip(form, pool, audit), fields(user_id=%*user_id))]
+pub async fn update_admin_settings(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let require_two_factor = form.0.require_two_factor.is_some();
+    let previous = two_factor_required(&pool).await.map_err(e500)?;
+    set_two_factor_required(&pool, require_two_factor)
+        .await
+        .map_err(e500)?;
+    if previous != require_two_factor {
+        let event = AuditEvent::new(AuditAction::SettingsUpdate)
+            .actor(**user_id)
+            .changes(serde_json::json!({
+                "require_two_factor": {"old": previous, "new": require_two_factor}
+            }));
+        record_audit_event(&pool, &audit, event)
+            .await
+            .map_err(e500)?;
+    }
+    Ok(see_other_with_flash(
+        "/admin/settings",
+        "Your changes have been saved.",
+    ))
+}
@@ -0,0 +1,57 @@
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+pub async fn admin_stats(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let rows: String = get_subscriber_counts(&pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|(status, count)| {
+            format!(
+                "<tr><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&status),
+                count
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Statistics</title>
+  </head>
+  <body>
+    <h2>Subscribers</h2>
+    <table>
+      <tr><th>Status</th><th>Count</th></tr>
+      {}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            rows
+        )))
+}
+
+#[tracing::instrument(name = "Count subscribers by status", skip(pool))]
+async fn get_subscriber_counts(pool: &PgPool) -> Result<Vec<(String, i64)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!"
+        FROM subscriptions
+        GROUP BY status
+        ORDER BY status
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count subscribers by status.")?;
+    Ok(rows.into_iter().map(|r| (r.status, r.count)).collect())
+}
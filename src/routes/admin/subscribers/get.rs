@@ -0,0 +1,108 @@
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    page: Option<i64>,
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub async fn admin_subscribers(
+    request: HttpRequest,
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = query.page.unwrap_or(1).max(1);
+    let subscribers = get_subscribers_page(&pool, page).await.map_err(e500)?;
+    let has_next_page = subscribers.len() as i64 == PAGE_SIZE;
+    let csrf = csrf_field(&request)?;
+    let rows: String = subscribers
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><form action="/admin/subscribers/{}/unsubscribe" method="post">{}<button type="submit">Unsubscribe</button></form></td></tr>"#,
+                htmlescape::encode_minimal(&s.email),
+                htmlescape::encode_minimal(&s.name),
+                htmlescape::encode_minimal(&s.status),
+                s.subscribed_at.format("%Y-%m-%d"),
+                s.id,
+                csrf
+            )
+        })
+        .collect();
+    let mut pagination = String::new();
+    if page > 1 {
+        pagination.push_str(&format!(
+            r#"<a href="/admin/subscribers?page={}">Previous</a> "#,
+            page - 1
+        ));
+    }
+    if has_next_page {
+        pagination.push_str(&format!(
+            r#"<a href="/admin/subscribers?page={}">Next</a>"#,
+            page + 1
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Subscribers</title>
+  </head>
+  <body>
+    {}
+    <table>
+      <tr><th>Email</th><th>Name</th><th>Status</th><th>Subscribed</th><th></th></tr>
+      {}
+    </table>
+    <p>{}</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            rows,
+            pagination
+        )))
+}
+
+#[tracing::instrument(name = "Get a page of subscribers", skip(pool))]
+async fn get_subscribers_page(
+    pool: &PgPool,
+    page: i64,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        ORDER BY subscribed_at DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscribers.")?;
+    Ok(rows)
+}
@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::admin_subscribers;
+pub use post::unsubscribe_subscriber;
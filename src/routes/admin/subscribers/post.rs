@@ -0,0 +1,75 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::suppressions::{add_suppression, NewSuppression, SuppressionReason, SuppressionSource};
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(pool, audit), fields(user_id=%*user_id))]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let previous = sqlx::query!(
+        r#"
+        UPDATE subscriptions s SET status = 'unsubscribed'
+        FROM (SELECT id, status FROM subscriptions WHERE id = $1 FOR UPDATE) old
+        WHERE s.id = old.id
+        RETURNING s.email, old.status AS previous_status
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to unsubscribe a subscriber.")
+    .map_err(e500)?;
+    let message = match previous {
+        Some(row) => {
+            let event = AuditEvent::new(AuditAction::SubscriberUnsubscribe)
+                .actor(**user_id)
+                .target("subscriber", subscriber_id)
+                .changes(serde_json::json!({
+                    "status": {"old": row.previous_status, "new": "unsubscribed"}
+                }));
+            record_audit_event(&pool, &audit, event)
+                .await
+                .map_err(e500)?;
+            // so that signing up again does not bring them back
+            suppress(&pool, &audit, **user_id, row.email)
+                .await
+                .map_err(e500)?;
+            "The subscriber has been unsubscribed."
+        }
+        None => "There is no such subscriber.",
+    };
+    Ok(see_other_with_flash("/admin/subscribers", message))
+}
+
+async fn suppress(
+    pool: &PgPool,
+    audit: &AuditContext,
+    user_id: Uuid,
+    email: String,
+) -> Result<(), anyhow::Error> {
+    let email = SubscriberEmail::parse(email).map_err(|e| anyhow::anyhow!(e))?;
+    let new_suppression = NewSuppression {
+        email,
+        reason: SuppressionReason::Unsubscribed,
+        source: SuppressionSource::Admin,
+        note: None,
+        created_by: Some(user_id),
+    };
+    if let Some(suppression) = add_suppression(pool, &new_suppression).await? {
+        let event = suppression
+            .audit_event(AuditAction::SuppressionAdd)
+            .actor(user_id);
+        record_audit_event(pool, audit, event).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use super::ISSUER;
+use crate::authentication::totp::TotpSecret;
+use crate::authentication::{count_unused_recovery_codes, get_stored_totp, UserId};
+use crate::csrf::csrf_field;
+use crate::routes::get_username;
+use crate::session_state::TypedSession;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+pub async fn two_factor_form(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let csrf = csrf_field(&request)?;
+    let body = if get_stored_totp(&pool, *user_id)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        let remaining = count_unused_recovery_codes(&pool, *user_id)
+            .await
+            .map_err(e500)?;
+        enabled_html(remaining, &csrf)
+    } else {
+        // keep the same secret across reloads until the enrollment is confirmed
+        let secret = match session
+            .get_totp_enrollment()
+            .map_err(e500)?
+            .and_then(|s| TotpSecret::parse(s).ok())
+        {
+            Some(secret) => secret,
+            None => {
+                let secret = TotpSecret::generate();
+                session
+                    .insert_totp_enrollment(secret.as_base32().expose_secret())
+                    .map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        enrollment_html(&secret, &username, &csrf)?
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Two-factor authentication</title>
+  </head>
+  <body>
+    {}
+    {}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            body
+        )))
+}
+
+fn enabled_html(remaining_recovery_codes: i64, csrf: &str) -> String {
+    format!(
+        r#"
+    <p>Two-factor authentication is enabled.</p>
+    <p>You have {remaining_recovery_codes} unused recovery codes left.</p>
+    <form action="/admin/two-factor/recovery-codes" method="post">
+      {csrf}
+      <label
+        >Authentication code
+        <input type="text" name="code" autocomplete="one-time-code" />
+      </label>
+      <button type="submit">Generate new recovery codes</button>
+    </form>
+    <form action="/admin/two-factor/disable" method="post">
+      {csrf}
+      <label
+        >Authentication code
+        <input type="text" name="code" autocomplete="one-time-code" />
+      </label>
+      <button type="submit">Disable two-factor authentication</button>
+    </form>"#
+    )
+}
+
+fn enrollment_html(
+    secret: &TotpSecret,
+    username: &str,
+    csrf: &str,
+) -> Result<String, actix_web::Error> {
+    let provisioning_uri = secret.provisioning_uri(ISSUER, username);
+    let qr_code = QrCode::new(provisioning_uri.as_bytes())
+        .map_err(e500)?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Ok(format!(
+        r#"
+    <p>Scan this QR code with your authenticator app:</p>
+    {}
+    <p>
+      Or <a href="{}">open it on this device</a>, or enter the key by hand:
+      <code id="totp-secret">{}</code>
+    </p>
+    <form action="/admin/two-factor/enable" method="post">
+      {}
+      <label
+        >Authentication code
+        <input type="text" name="code" autocomplete="one-time-code" />
+      </label>
+      <button type="submit">Enable two-factor authentication</button>
+    </form>"#,
+        qr_code,
+        htmlescape::encode_attribute(&provisioning_uri),
+        secret.as_base32().expose_secret(),
+        csrf
+    ))
+}
@@ -0,0 +1,7 @@
+mod get;
+mod post;
+pub use get::two_factor_form;
+pub use post::{disable_two_factor, enable_two_factor, regenerate_two_factor_recovery_codes};
+
+// shown as the account's issuer in authenticator apps
+const ISSUER: &str = "zero2prod";
@@ -0,0 +1,191 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::totp::{self, TotpSecret};
+use crate::authentication::{
+    disable_totp, enable_totp, get_stored_totp, record_failed_second_factor,
+    regenerate_recovery_codes, reset_failed_second_factors, second_factor_locked,
+    two_factor_required, verify_totp_code, UserId,
+};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(name = "Enable two-factor authentication", skip(form, pool, session, audit), fields(user_id=%*user_id))]
+pub async fn enable_two_factor(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    // enrolling again would swap the secret without knowing the current one
+    if get_stored_totp(&pool, *user_id)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        return Ok(see_other_with_flash(
+            "/admin/two-factor",
+            "Two-factor authentication is already enabled.",
+        ));
+    }
+    let secret = match session
+        .get_totp_enrollment()
+        .map_err(e500)?
+        .and_then(|s| TotpSecret::parse(s).ok())
+    {
+        Some(secret) => secret,
+        None => {
+            return Ok(see_other_with_flash(
+                "/admin/two-factor",
+                "Your enrollment has expired. Please scan the new QR code.",
+            ))
+        }
+    };
+    let step = match secret.verify(form.0.code.expose_secret(), totp::now(), None) {
+        Some(step) => step,
+        None => {
+            return Ok(see_other_with_flash(
+                "/admin/two-factor",
+                "The authentication code is invalid.",
+            ))
+        }
+    };
+    let recovery_codes = enable_totp(&pool, *user_id, &secret, step)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        &pool,
+        &audit,
+        AuditEvent::new(AuditAction::TwoFactorEnable).actor(*user_id),
+    )
+    .await
+    .map_err(e500)?;
+    session.remove_totp_enrollment();
+    Ok(recovery_codes_page(&recovery_codes))
+}
+
+#[tracing::instrument(name = "Regenerate recovery codes", skip(form, pool, audit), fields(user_id=%*user_id))]
+pub async fn regenerate_two_factor_recovery_codes(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if let Some(message) = reject_current_code(&pool, *user_id, form.0.code.expose_secret())
+        .await
+        .map_err(e500)?
+    {
+        return Ok(see_other_with_flash("/admin/two-factor", message));
+    }
+    let recovery_codes = regenerate_recovery_codes(&pool, *user_id)
+        .await
+        .map_err(e500)?;
+    record_audit_event(
+        &pool,
+        &audit,
+        AuditEvent::new(AuditAction::TwoFactorRecoveryCodes).actor(*user_id),
+    )
+    .await
+    .map_err(e500)?;
+    Ok(recovery_codes_page(&recovery_codes))
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(form, pool, audit), fields(user_id=%*user_id))]
+pub async fn disable_two_factor(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if two_factor_required(&pool).await.map_err(e500)? {
+        return Ok(see_other_with_flash(
+            "/admin/two-factor",
+            "Two-factor authentication is required and cannot be disabled.",
+        ));
+    }
+    if let Some(message) = reject_current_code(&pool, *user_id, form.0.code.expose_secret())
+        .await
+        .map_err(e500)?
+    {
+        return Ok(see_other_with_flash("/admin/two-factor", message));
+    }
+    disable_totp(&pool, *user_id).await.map_err(e500)?;
+    record_audit_event(
+        &pool,
+        &audit,
+        AuditEvent::new(AuditAction::TwoFactorDisable).actor(*user_id),
+    )
+    .await
+    .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/two-factor",
+        "Two-factor authentication has been disabled.",
+    ))
+}
+
+// Check a code from the user's authenticator app, returning why it was
+// refused if it was. Wrong codes count towards the same lockout as the login's
+// second step, or a stolen session could guess codes here without limit.
+async fn reject_current_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Option<&'static str>, anyhow::Error> {
+    const LOCKED: &str = "Too many invalid authentication codes. Please try again later.";
+    if second_factor_locked(pool, user_id).await? {
+        return Ok(Some(LOCKED));
+    }
+    if verify_totp_code(pool, user_id, code).await? {
+        reset_failed_second_factors(pool, user_id).await?;
+        return Ok(None);
+    }
+    if record_failed_second_factor(pool, user_id).await? {
+        Ok(Some(LOCKED))
+    } else {
+        Ok(Some("The authentication code is invalid."))
+    }
+}
+
+// recovery codes are only stored hashed, this page is the one chance to see them
+fn recovery_codes_page(recovery_codes: &[String]) -> HttpResponse {
+    let items: String = recovery_codes
+        .iter()
+        .map(|code| format!(r#"<li><code class="recovery-code">{}</code></li>"#, code))
+        .collect();
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Recovery codes</title>
+  </head>
+  <body>
+    <p>Two-factor authentication is enabled.</p>
+    <p>
+      Store these recovery codes somewhere safe. Each of them can be used once
+      to log in if you lose access to your authenticator app.
+    </p>
+    <ul>
+      {}
+    </ul>
+    <p><a href="/admin/dashboard">Continue</a></p>
+  </body>
+</html>"#,
+            items
+        ))
+}
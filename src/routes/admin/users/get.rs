@@ -0,0 +1,131 @@
+use crate::authentication::{list_pending_invitations, list_users, Role, UserId};
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn admin_users(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let users = list_users(&pool).await.map_err(e500)?;
+    let invitations = list_pending_invitations(&pool).await.map_err(e500)?;
+    let csrf = csrf_field(&request)?;
+    let user_rows: String = users
+        .iter()
+        .map(|u| {
+            // owners cannot lock themselves out
+            let actions = if u.user_id == *user_id {
+                "(you)".to_string()
+            } else {
+                let status_action = if u.deactivated_at.is_some() {
+                    "reactivate"
+                } else {
+                    "deactivate"
+                };
+                format!(
+                    r#"<form action="/admin/users/{id}/role" method="post">{csrf}{select}<button type="submit">Change role</button></form>
+<form action="/admin/users/{id}/{action}" method="post">{csrf}<button type="submit">{label}</button></form>"#,
+                    id = u.user_id,
+                    csrf = csrf,
+                    select = role_select(Some(&u.role)),
+                    action = status_action,
+                    label = capitalise(status_action),
+                )
+            };
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+                htmlescape::encode_minimal(&u.username),
+                htmlescape::encode_minimal(u.email.as_deref().unwrap_or("")),
+                htmlescape::encode_minimal(&u.role),
+                if u.deactivated_at.is_some() {
+                    "deactivated"
+                } else {
+                    "active"
+                },
+                actions
+            )
+        })
+        .collect();
+    let invitation_rows: String = invitations
+        .iter()
+        .map(|i| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td><form action="/admin/users/invitations/{}/revoke" method="post">{}<button type="submit">Revoke</button></form></td></tr>"#,
+                htmlescape::encode_minimal(&i.email),
+                htmlescape::encode_minimal(&i.role),
+                i.expires_at.format("%Y-%m-%d %H:%M"),
+                i.invitation_id,
+                csrf
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Users</title>
+  </head>
+  <body>
+    {}
+    <h2>Users</h2>
+    <table>
+      <tr><th>Username</th><th>Email</th><th>Role</th><th>Status</th><th></th></tr>
+      {}
+    </table>
+    <h2>Pending invitations</h2>
+    <table>
+      <tr><th>Email</th><th>Role</th><th>Expires</th><th></th></tr>
+      {}
+    </table>
+    <h2>Invite a new user</h2>
+    <form action="/admin/users/invitations" method="post">
+      {}
+      <label>Email <input type="email" name="email" /></label>
+      <label>Role {}</label>
+      <button type="submit">Send invitation</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            user_rows,
+            invitation_rows,
+            csrf,
+            role_select(None)
+        )))
+}
+
+fn role_select(selected: Option<&str>) -> String {
+    let options: String = Role::ALL
+        .iter()
+        .map(|role| {
+            format!(
+                r#"<option value="{0}"{1}>{0}</option>"#,
+                role.as_str(),
+                if selected == Some(role.as_str()) {
+                    " selected"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect();
+    format!(r#"<select name="role">{}</select>"#, options)
+}
+
+fn capitalise(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::admin_users;
+pub use post::*;
@@ -0,0 +1,254 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    create_invitation, email_belongs_to_user, revoke_invitation, set_user_deactivated,
+    set_user_role, InvitationLink, NewInvitation, Role, UserId,
+};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailError};
+use crate::startup::{ApplicaitonBaseUrl, HmacSecret};
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct InvitationFormData {
+    email: String,
+    role: String,
+}
+
+impl TryFrom<InvitationFormData> for NewInvitation {
+    type Error = String;
+
+    fn try_from(form: InvitationFormData) -> Result<Self, Self::Error> {
+        let email = SubscriberEmail::parse(form.email.trim().to_string())?;
+        let role = Role::try_from(form.role)?;
+        Ok(Self { email, role })
+    }
+}
+
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, pool, email_client, base_url, hmac_secret, audit),
+    fields(user_id=%*user_id)
+)]
+pub async fn invite_user(
+    form: web::Form<InvitationFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicaitonBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let invitation: NewInvitation = match form.0.try_into() {
+        Ok(invitation) => invitation,
+        Err(e) => return Ok(see_other_with_flash("/admin/users", &e)),
+    };
+    if email_belongs_to_user(&pool, invitation.email.as_ref())
+        .await
+        .map_err(e500)?
+    {
+        return Ok(see_other_with_flash(
+            "/admin/users",
+            "There is already a user with that email address.",
+        ));
+    }
+    let link = create_invitation(&pool, **user_id, &invitation, &hmac_secret.0)
+        .await
+        .map_err(e500)?;
+    send_invitation_email(&email_client, &invitation, &base_url.0, &link)
+        .await
+        .context("Failed to send an invitation email.")
+        .map_err(e500)?;
+    let event = AuditEvent::new(AuditAction::UserInvite)
+        .actor(**user_id)
+        .target("invitation", link.invitation_id)
+        .changes(serde_json::json!({
+            "email": invitation.email.as_ref(),
+            "role": invitation.role.as_str(),
+        }));
+    record_audit_event(&pool, &audit, event)
+        .await
+        .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/users",
+        &format!("An invitation has been sent to {}.", invitation.email),
+    ))
+}
+
+#[tracing::instrument(name = "Send invitation email", skip_all)]
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    invitation: &NewInvitation,
+    base_url: &str,
+    link: &InvitationLink,
+) -> Result<(), EmailError> {
+    let invitation_link = format!("{}/invitations/accept?{}", base_url, link.query_string());
+    let plain_body = format!(
+        "You have been invited to help run the newsletter as {}.\n\
+        Visit {} to choose a username and password. The link expires in 3 days.",
+        invitation.role.as_str(),
+        invitation_link
+    );
+    let html_body = format!(
+        "You have been invited to help run the newsletter as {}.<br />\
+        Click <a href=\"{}\">here</a> to choose a username and password. The link expires in 3 days.",
+        invitation.role.as_str(),
+        invitation_link
+    );
+    email_client
+        .send_email(
+            &invitation.email,
+            "You have been invited",
+            &html_body,
+            &plain_body,
+        )
+        .await
+        .map(|_| ())
+}
+
+#[tracing::instrument(name = "Revoke an invitation", skip(pool, audit), fields(user_id=%*user_id))]
+pub async fn revoke_user_invitation(
+    invitation_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let invitation_id = invitation_id.into_inner();
+    let message = if revoke_invitation(&pool, invitation_id)
+        .await
+        .map_err(e500)?
+    {
+        let event = AuditEvent::new(AuditAction::UserInvitationRevoke)
+            .actor(**user_id)
+            .target("invitation", invitation_id);
+        record_audit_event(&pool, &audit, event)
+            .await
+            .map_err(e500)?;
+        "The invitation has been revoked."
+    } else {
+        "There is no such invitation."
+    };
+    Ok(see_other_with_flash("/admin/users", message))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: String,
+}
+
+#[tracing::instrument(name = "Change a user's role", skip(form, pool, audit), fields(user_id=%*user_id))]
+pub async fn change_user_role(
+    target_user_id: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_user_id = target_user_id.into_inner();
+    if target_user_id == **user_id {
+        return Ok(see_other_with_flash(
+            "/admin/users",
+            "You cannot change your own role.",
+        ));
+    }
+    let role = match Role::try_from(form.0.role) {
+        Ok(role) => role,
+        Err(e) => return Ok(see_other_with_flash("/admin/users", &e)),
+    };
+    let message = match set_user_role(&pool, target_user_id, role)
+        .await
+        .map_err(e500)?
+    {
+        Some(previous_role) => {
+            let event = AuditEvent::new(AuditAction::UserRoleChange)
+                .actor(**user_id)
+                .target("user", target_user_id)
+                .changes(serde_json::json!({
+                    "role": {"old": previous_role, "new": role.as_str()}
+                }));
+            record_audit_event(&pool, &audit, event)
+                .await
+                .map_err(e500)?;
+            "The user's role has been updated."
+        }
+        None => "There is no such user.",
+    };
+    Ok(see_other_with_flash("/admin/users", message))
+}
+
+#[tracing::instrument(name = "Deactivate a user", skip(pool, audit), fields(user_id=%*user_id))]
+pub async fn deactivate_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    update_user_status(
+        target_user_id.into_inner(),
+        *user_id.into_inner(),
+        &pool,
+        &audit,
+        true,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Reactivate a user", skip(pool, audit), fields(user_id=%*user_id))]
+pub async fn reactivate_user(
+    target_user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    update_user_status(
+        target_user_id.into_inner(),
+        *user_id.into_inner(),
+        &pool,
+        &audit,
+        false,
+    )
+    .await
+}
+
+async fn update_user_status(
+    target_user_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+    audit: &AuditContext,
+    deactivated: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+    if target_user_id == user_id {
+        return Ok(see_other_with_flash(
+            "/admin/users",
+            "You cannot change the status of your own account.",
+        ));
+    }
+    if !set_user_deactivated(pool, target_user_id, deactivated)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(see_other_with_flash(
+            "/admin/users",
+            "There is no such user.",
+        ));
+    }
+    let (action, message) = if deactivated {
+        (
+            AuditAction::UserDeactivate,
+            "The user has been deactivated.",
+        )
+    } else {
+        (
+            AuditAction::UserReactivate,
+            "The user has been reactivated.",
+        )
+    };
+    let event = AuditEvent::new(action)
+        .actor(user_id)
+        .target("user", target_user_id);
+    record_audit_event(pool, audit, event).await.map_err(e500)?;
+    Ok(see_other_with_flash("/admin/users", message))
+}
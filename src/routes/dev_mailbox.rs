@@ -0,0 +1,149 @@
+use crate::email_client::{CaughtEmail, Mailbox, MAILBOX_SIZE};
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use uuid::Uuid;
+
+// shown as they are, in this order
+const ADDRESS_FIELDS: [(&str, &str); 6] = [
+    ("From", "From"),
+    ("To", "To"),
+    ("Cc", "Cc"),
+    ("Bcc", "Bcc"),
+    ("ReplyTo", "Reply-To"),
+    ("Subject", "Subject"),
+];
+
+/// The emails caught instead of being sent, in local development.
+pub async fn dev_mailbox(mailbox: web::Data<Mailbox>) -> Result<HttpResponse, actix_web::Error> {
+    let emails = mailbox.list().await.map_err(e500)?;
+    let rows: String = emails
+        .iter()
+        .map(|email| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td><a href="/dev/mailbox/{}">{}</a></td></tr>"#,
+                email.caught_at.format("%Y-%m-%d %H:%M:%S"),
+                htmlescape::encode_minimal(&email.recipient),
+                email.email_id,
+                htmlescape::encode_minimal(&email.subject),
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Mailbox</title>
+  </head>
+  <body>
+    <h1>Mailbox</h1>
+    <p>Emails are kept here instead of being sent. The {} most recent are listed.</p>
+    <table>
+      <tr><th>When (UTC)</th><th>To</th><th>Subject</th></tr>
+      {}
+    </table>
+  </body>
+</html>"#,
+            MAILBOX_SIZE, rows
+        )))
+}
+
+/// A caught email: its headers, links, HTML rendering and text.
+pub async fn dev_mailbox_email(
+    email_id: web::Path<Uuid>,
+    mailbox: web::Data<Mailbox>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match mailbox.get(email_id.into_inner()).await.map_err(e500)? {
+        Some(email) => email,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let links: String = email
+        .links()
+        .iter()
+        .map(|link| {
+            format!(
+                r#"<li><a href="{}">{}</a></li>"#,
+                htmlescape::encode_attribute(link),
+                htmlescape::encode_minimal(link)
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>{}</title>
+  </head>
+  <body>
+    <table>
+      {}
+    </table>
+    <h2>Links</h2>
+    <ul>{}</ul>
+    <h2>HTML</h2>
+    <iframe sandbox srcdoc="{}" style="width: 100%; height: 400px"></iframe>
+    <h2>Text</h2>
+    <pre>{}</pre>
+    <p><a href="/dev/mailbox">&lt;- Back</a></p>
+  </body>
+</html>"#,
+            htmlescape::encode_minimal(&email.subject),
+            header_rows(&email),
+            links,
+            htmlescape::encode_attribute(email.field("HtmlBody").unwrap_or_default()),
+            htmlescape::encode_minimal(email.field("TextBody").unwrap_or_default()),
+        )))
+}
+
+// the addresses, extra headers, tag, metadata and attachments, as table rows
+fn header_rows(email: &CaughtEmail) -> String {
+    let mut rows = vec![(
+        "Date".to_string(),
+        email.caught_at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+    )];
+    for (field, label) in ADDRESS_FIELDS {
+        if let Some(value) = email.field(field) {
+            rows.push((label.to_string(), value.to_string()));
+        }
+    }
+    let message = &email.message;
+    for header in message["Headers"].as_array().into_iter().flatten() {
+        let text = |name: &str| header[name].as_str().unwrap_or_default().to_string();
+        rows.push((text("Name"), text("Value")));
+    }
+    for field in ["Tag", "MessageStream"] {
+        if let Some(value) = email.field(field) {
+            rows.push((field.to_string(), value.to_string()));
+        }
+    }
+    for (key, value) in message["Metadata"].as_object().into_iter().flatten() {
+        rows.push((
+            format!("Metadata {}", key),
+            value.as_str().unwrap_or_default().to_string(),
+        ));
+    }
+    for attachment in message["Attachments"].as_array().into_iter().flatten() {
+        let text = |name: &str| attachment[name].as_str().unwrap_or_default();
+        rows.push((
+            "Attachment".to_string(),
+            format!("{} ({})", text("Name"), text("ContentType")),
+        ));
+    }
+    rows.iter()
+        .map(|(name, value)| {
+            format!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                htmlescape::encode_minimal(name),
+                htmlescape::encode_minimal(value)
+            )
+        })
+        .collect()
+}
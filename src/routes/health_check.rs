@@ -1,5 +1,179 @@
-use actix_web::HttpResponse;
+use crate::email_client::{CircuitState, EmailClient};
+use crate::heartbeat::Heartbeats;
+use crate::migrations::migration_status;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+// a readiness probe must answer even if a dependency hangs
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// The process is up and serving requests. Dependencies are not checked, a
+/// failing database should not get the instance restarted.
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+}
+
+#[derive(serde::Serialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    /// Requests can be served, but some features will not work.
+    Degraded,
+    Unavailable,
+}
+
+#[derive(serde::Serialize)]
+struct Check {
+    status: Status,
+    duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    details: serde_json::Value,
+}
+
+impl Check {
+    // a failed check that does not stop the instance from serving requests
+    fn non_critical(mut self) -> Self {
+        if self.status == Status::Unavailable {
+            self.status = Status::Degraded;
+        }
+        self
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    status: Status,
+    checks: Checks,
+}
+
+#[derive(serde::Serialize)]
+struct Checks {
+    database: Check,
+    migrations: Check,
+    workers: Check,
+    email: Check,
+}
+
+/// Whether the instance should receive traffic: the database is reachable
+/// and fully migrated. Stale workers, an unreachable email provider or an
+/// open circuit are reported as degraded, with a 200.
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    heartbeats: web::Data<Heartbeats>,
+) -> HttpResponse {
+    let (database, migrations, mut email) = tokio::join!(
+        timed(async {
+            sqlx::query("SELECT 1")
+                .execute(pool.get_ref())
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(serde_json::Value::Null)
+        }),
+        timed(async {
+            let status = migration_status(&pool)
+                .await
+                .map_err(|e| format!("{:#}", e))?;
+            if !status.pending.is_empty() || !status.modified.is_empty() {
+                return Err(format!(
+                    "{} pending and {} modified migration(s)",
+                    status.pending.len(),
+                    status.modified.len()
+                ));
+            }
+            Ok(serde_json::json!({"applied_version": status.applied_version}))
+        }),
+        timed(async {
+            let circuits = email_client.circuits();
+            if circuits
+                .iter()
+                .all(|(_, state)| *state == CircuitState::Open)
+            {
+                return Err("the circuit of every email provider is open".into());
+            }
+            // the secondary sends, but the primary is down all the same
+            if circuits[0].1 == CircuitState::Open {
+                return Err("the circuit of the primary email provider is open".into());
+            }
+            email_client
+                .check_health()
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(serde_json::Value::Null)
+        }),
+    );
+    let circuits: serde_json::Map<_, _> = email_client
+        .circuits()
+        .into_iter()
+        .map(|(provider, state)| (provider.to_string(), serde_json::json!(state)))
+        .collect();
+    email.details = serde_json::json!({ "circuits": circuits });
+    let workers = timed(async {
+        let workers = heartbeats.status();
+        let stale: Vec<_> = workers.iter().filter(|w| w.stale).map(|w| w.name).collect();
+        if !stale.is_empty() {
+            return Err(format!("stale workers: {}", stale.join(", ")));
+        }
+        Ok(serde_json::json!(workers))
+    })
+    .await;
+
+    let checks = Checks {
+        database,
+        migrations,
+        workers: workers.non_critical(),
+        email: email.non_critical(),
+    };
+    let statuses = [
+        checks.database.status,
+        checks.migrations.status,
+        checks.workers.status,
+        checks.email.status,
+    ];
+    let status = if statuses.contains(&Status::Unavailable) {
+        Status::Unavailable
+    } else if statuses.contains(&Status::Degraded) {
+        Status::Degraded
+    } else {
+        Status::Ok
+    };
+    let body = Readiness { status, checks };
+    if status == Status::Unavailable {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+async fn timed<F>(check: F) -> Check
+where
+    F: Future<Output = Result<serde_json::Value, String>>,
+{
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err("timed out".into()));
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+    match outcome {
+        Ok(details) => Check {
+            status: Status::Ok,
+            duration_ms,
+            error: None,
+            details,
+        },
+        Err(error) => Check {
+            status: Status::Unavailable,
+            duration_ms,
+            error: Some(error),
+            details: serde_json::Value::Null,
+        },
+    }
+}
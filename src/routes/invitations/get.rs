@@ -0,0 +1,68 @@
+use crate::authentication::{get_open_invitation_email, InvitationLink};
+use crate::csrf::csrf_field;
+use crate::startup::HmacSecret;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html, see_other_with_flash};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn accept_invitation_form(
+    request: HttpRequest,
+    link: web::Query<InvitationLink>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let link = link.into_inner();
+    let email = if link.verify(&hmac_secret.0) {
+        get_open_invitation_email(&pool, link.invitation_id)
+            .await
+            .map_err(e500)?
+    } else {
+        None
+    };
+    let email = match email {
+        Some(email) => email,
+        None => {
+            return Ok(see_other_with_flash(
+                "/login",
+                "This invitation is invalid or has expired.",
+            ))
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Accept invitation</title>
+  </head>
+  <body>
+    {}
+    <p>Choose a username and password for {}.</p>
+    <form action="/invitations/accept" method="post">
+      {}
+      <input hidden type="text" name="invitation_id" value="{}" />
+      <input hidden type="text" name="expires" value="{}" />
+      <input hidden type="text" name="tag" value="{}" />
+      <label>Username <input type="text" name="username" /></label>
+      <br />
+      <label>Password <input type="password" name="password" /></label>
+      <br />
+      <label>Confirm password <input type="password" name="password_check" /></label>
+      <br />
+      <button type="submit">Create account</button>
+    </form>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            htmlescape::encode_minimal(&email),
+            csrf_field(&request)?,
+            link.invitation_id,
+            link.expires,
+            htmlescape::encode_attribute(&link.tag)
+        )))
+}
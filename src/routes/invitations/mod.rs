@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::accept_invitation_form;
+pub use post::accept_invitation_for_user;
@@ -0,0 +1,98 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    accept_invitation, compute_password_hash, validate_new_password, AcceptInvitationError,
+    InvitationLink,
+};
+use crate::configuration::AuthenticationSettings;
+use crate::startup::HmacSecret;
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use secrecy::Secret;
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    invitation_id: Uuid,
+    expires: i64,
+    tag: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Accept an invitation", skip(form, pool, hmac_secret, auth_settings, audit), fields(username=%form.username))]
+pub async fn accept_invitation_for_user(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    auth_settings: web::Data<AuthenticationSettings>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        invitation_id,
+        expires,
+        tag,
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let link = InvitationLink {
+        invitation_id,
+        expires,
+        tag,
+    };
+    if !link.verify(&hmac_secret.0) {
+        return Ok(see_other_with_flash(
+            "/login",
+            "This invitation is invalid or has expired.",
+        ));
+    }
+    let form_url = format!("/invitations/accept?{}", link.query_string());
+    let username = username.trim();
+    if let Err(e) = validate_new_account(username, &password, &password_check) {
+        return Ok(see_other_with_flash(&form_url, &e));
+    }
+    let password_hash = compute_password_hash(password, &auth_settings.password_hashing)
+        .await
+        .map_err(e500)?;
+    match accept_invitation(&pool, invitation_id, username, password_hash).await {
+        Ok(user_id) => {
+            let event = AuditEvent::new(AuditAction::UserInvitationAccept)
+                .actor(user_id)
+                .target("invitation", invitation_id)
+                .changes(serde_json::json!({"username": username}));
+            record_audit_event(&pool, &audit, event)
+                .await
+                .map_err(e500)?;
+            Ok(see_other_with_flash(
+                "/login",
+                "Your account has been created. You can now log in.",
+            ))
+        }
+        Err(e @ AcceptInvitationError::UsernameTaken) => {
+            Ok(see_other_with_flash(&form_url, &e.to_string()))
+        }
+        Err(e @ AcceptInvitationError::InvalidInvitation) => {
+            Ok(see_other_with_flash("/login", &e.to_string()))
+        }
+        Err(AcceptInvitationError::UnexpectedError(e)) => Err(e500(e)),
+    }
+}
+
+fn validate_new_account(
+    username: &str,
+    password: &Secret<String>,
+    password_check: &Secret<String>,
+) -> Result<(), String> {
+    if username.is_empty() || username.graphemes(true).count() > MAX_USERNAME_LENGTH {
+        return Err(format!(
+            "Usernames must be between 1 and {} characters long.",
+            MAX_USERNAME_LENGTH
+        ));
+    }
+    validate_new_password(password, password_check)
+}
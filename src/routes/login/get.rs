@@ -1,15 +1,13 @@
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, flash_message_html};
 use actix_web::http::header::ContentType;
 use actix_web::{HttpRequest, HttpResponse};
 
-pub async fn login_form(request: HttpRequest) -> HttpResponse {
-    let error_html = match request.cookie("_flash") {
-        None => "".into(),
-        Some(cookie) => {
-            format!("<p><i>{}</i></p>", cookie.value())
-        }
-    };
-    HttpResponse::Ok()
+pub async fn login_form(request: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
+    let error_html = flash_message_html(&request);
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
         .body(format!(
             r#"
 <!DOCTYPE html>
@@ -21,6 +19,7 @@ pub async fn login_form(request: HttpRequest) -> HttpResponse {
   <body>
     {}
     <form action="/login" method="POST">
+      {}
       <label
         >Username
         <input type="text" placeholder="Enter Username" name="username" />
@@ -32,8 +31,10 @@ pub async fn login_form(request: HttpRequest) -> HttpResponse {
       </label>
       <button type="submit">Login</button>
     </form>
+    <p><a href="/password-reset">Forgot your password?</a></p>
   </body>
 </html>"#,
-            error_html
-        ))
+            error_html,
+            csrf_field(&request)?
+        )))
 }
@@ -1,5 +1,11 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    get_session_generation, get_stored_totp, validate_credentials, AuthError, Credentials,
+};
+use crate::configuration::AuthenticationSettings;
 use crate::routes::error_chain_fmt;
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
 use actix_web::cookie::Cookie;
 use actix_web::error::InternalError;
 use actix_web::http::header::LOCATION;
@@ -29,36 +35,79 @@ impl std::fmt::Debug for LoginError {
 }
 
 #[tracing::instrument(
-    skip(form, pool),
+    skip(form, pool, session, auth_settings, audit),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    session: TypedSession,
+    auth_settings: web::Data<AuthenticationSettings>,
+    audit: AuditContext,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
 
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &pool, &auth_settings.password_hashing).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-            Ok(HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/"))
-                .finish())
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            // the password is only the first step for users enrolled in two-factor authentication
+            let two_factor = get_stored_totp(&pool, user_id)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let generation = get_session_generation(&pool, user_id)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            session.renew();
+            // recorded before the second factor, so that a password reset also
+            // voids half-finished logins
+            session
+                .insert_session_generation(generation)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            if two_factor.is_some() {
+                session
+                    .insert_pending_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(see_other("/login/two-factor"));
+            }
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            record_audit_event(
+                &pool,
+                &audit,
+                AuditEvent::new(AuditAction::Login).actor(user_id),
+            )
+            .await
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            Ok(see_other("/admin/dashboard"))
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) | AuthError::Forbidden(_) => {
+                    // the attempted username is kept as the target, there is no actor
+                    let event = AuditEvent::new(AuditAction::LoginFailed).target("user", &username);
+                    record_audit_event(&pool, &audit, event)
+                        .await
+                        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
-            let response = HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/login"))
-                .cookie(Cookie::new("_flash", e.to_string()))
-                .finish();
-            Err(InternalError::from_response(e, response))
+            Err(login_redirect(e))
         }
     }
 }
+
+// Redirect to the login page with an error message.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    let response = HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(Cookie::new("_flash", e.to_string()))
+        .finish();
+    InternalError::from_response(e, response)
+}
@@ -0,0 +1,46 @@
+use crate::csrf::csrf_field;
+use crate::session_state::TypedSession;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse};
+
+pub async fn login_two_factor_form(
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    let error_html = flash_message_html(&request);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Two-factor authentication</title>
+  </head>
+  <body>
+    {}
+    <form action="/login/two-factor" method="POST">
+      {}
+      <label
+        >Authentication code
+        <input
+          type="text"
+          placeholder="6-digit code or recovery code"
+          name="code"
+          autocomplete="one-time-code"
+        />
+      </label>
+      <button type="submit">Verify</button>
+    </form>
+  </body>
+</html>"#,
+            error_html,
+            csrf_field(&request)?
+        )))
+}
@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::login_two_factor_form;
+pub use post::login_two_factor;
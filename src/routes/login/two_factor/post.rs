@@ -0,0 +1,78 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    record_failed_second_factor, reset_failed_second_factors, second_factor_locked,
+    verify_second_factor,
+};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(skip(form, pool, session, audit), fields(user_id=tracing::field::Empty))]
+pub async fn login_two_factor(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_pending_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    // not even a valid code is accepted while locked
+    if second_factor_locked(&pool, user_id).await.map_err(e500)? {
+        session.log_out();
+        return Ok(see_other_with_flash(
+            "/login",
+            "Too many invalid authentication codes. Please try again later.",
+        ));
+    }
+
+    if verify_second_factor(&pool, user_id, form.0.code.expose_secret())
+        .await
+        .map_err(e500)?
+    {
+        reset_failed_second_factors(&pool, user_id)
+            .await
+            .map_err(e500)?;
+        session.remove_pending_user_id();
+        session.renew();
+        session.insert_user_id(user_id).map_err(e500)?;
+        let event = AuditEvent::new(AuditAction::Login)
+            .actor(user_id)
+            .changes(serde_json::json!({"second_factor": true}));
+        record_audit_event(&pool, &audit, event)
+            .await
+            .map_err(e500)?;
+        return Ok(see_other("/admin/dashboard"));
+    }
+
+    let locked = record_failed_second_factor(&pool, user_id)
+        .await
+        .map_err(e500)?;
+    let event = AuditEvent::new(AuditAction::LoginFailed)
+        .target("user", user_id)
+        .changes(serde_json::json!({"second_factor": true}));
+    record_audit_event(&pool, &audit, event)
+        .await
+        .map_err(e500)?;
+    if locked {
+        session.log_out();
+        return Ok(see_other_with_flash(
+            "/login",
+            "Too many invalid authentication codes. Please try again later.",
+        ));
+    }
+    Ok(see_other_with_flash(
+        "/login/two-factor",
+        "The authentication code is invalid.",
+    ))
+}
@@ -1,12 +1,24 @@
+mod admin;
+mod dev_mailbox;
 mod health_check;
 mod home;
+mod invitations;
 mod login;
 mod newsletters;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod suppressions;
+mod webhooks;
+pub use admin::*;
+pub use dev_mailbox::*;
 pub use health_check::*;
 pub use home::*;
+pub use invitations::*;
 pub use login::*;
 pub use newsletters::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use suppressions::*;
+pub use webhooks::*;
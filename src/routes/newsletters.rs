@@ -1,20 +1,30 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    api_credentials, authenticate_api_request, record_denial, ApiCredentials, ApiScope, AuthError,
+};
+use crate::configuration::AuthenticationSettings;
+use crate::domain::{Attachment, SubscriberEmail, MAX_TOTAL_ATTACHMENT_SIZE};
+use crate::email_client::{EmailClient, EmailError, EmailMessage};
+use crate::email_deliveries::{record_deliveries, DeliveryKind};
 use crate::routes::error_chain_fmt;
-use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::header::HeaderValue;
 use actix_web::http::{header, StatusCode};
 use actix_web::HttpRequest;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
-use secrecy::Secret;
 use sqlx::PgPool;
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
 
 // error handling
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+    #[error("Insufficient permissions.")]
+    Forbidden(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -33,24 +43,62 @@ impl ResponseError for PublishError {
             }
             PublishError::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
+                for challenge in [r#"Basic realm="publish""#, r#"Bearer realm="publish""#] {
+                    response.headers_mut().append(
+                        header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static(challenge),
+                    );
+                }
                 response
             }
+            PublishError::Forbidden(_) => HttpResponse::new(StatusCode::FORBIDDEN),
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
         }
     }
 }
 
 struct ConfirmedSubscriber {
+    id: Uuid,
     email: SubscriberEmail,
 }
 
+/// Room for the largest attachments once base64 encoded, and the body.
+pub const NEWSLETTER_PAYLOAD_SIZE: usize = MAX_TOTAL_ATTACHMENT_SIZE / 3 * 4 + 1024 * 1024;
+
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    #[serde(default)]
+    attachments: Vec<AttachmentData>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AttachmentData {
+    name: String,
+    content_type: String,
+    /// base64 encoded
+    content: String,
+    /// Set to show an image inline, as `<img src="cid:...">`.
+    content_id: Option<String>,
+}
+
+impl BodyData {
+    fn message(&self) -> Result<EmailMessage, String> {
+        let mut message = EmailMessage::new(&self.title, &self.content.html, &self.content.text);
+        for attachment in &self.attachments {
+            let content = base64::decode(&attachment.content)
+                .map_err(|_| format!("{} is not base64 encoded.", attachment.name))?;
+            let attachment = Attachment::parse(
+                attachment.name.clone(),
+                attachment.content_type.clone(),
+                content,
+                attachment.content_id.clone(),
+            )?;
+            message = message.attach(attachment)?;
+        }
+        Ok(message)
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -65,7 +113,7 @@ async fn get_confirmed_subscribers(
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
     let confirmed_subscribers = sqlx::query!(
         r#"
-        SELECT email
+        SELECT id, email
         FROM subscriptions
         WHERE status = 'confirmed'
         "#
@@ -74,7 +122,7 @@ async fn get_confirmed_subscribers(
     .await?
     .into_iter()
     .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
+        Ok(email) => Ok(ConfirmedSubscriber { id: r.id, email }),
         Err(error) => Err(anyhow::anyhow!(error)),
     })
     .collect();
@@ -83,7 +131,7 @@ async fn get_confirmed_subscribers(
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, email_client, request, auth_settings, root_span),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
@@ -91,31 +139,33 @@ pub async fn publish_newsletter(
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     request: HttpRequest,
+    auth_settings: web::Data<AuthenticationSettings>,
+    root_span: RootSpan,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool)
+    let credentials = api_credentials(request.headers()).map_err(PublishError::AuthError)?;
+    if let ApiCredentials::Basic(credentials) = &credentials {
+        tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    }
+    let scope = ApiScope::NewslettersPublish;
+    let user_id = authenticate_api_request(credentials, &pool, scope, &auth_settings)
         .await
         .map_err(|e| match e {
             AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::Forbidden(_) => {
+                record_denial(&root_span, scope.required_permission());
+                PublishError::Forbidden(e.into())
+            }
             AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
         })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let message = body.message().map_err(PublishError::ValidationError)?;
+    let mut subscriber_ids = Vec::new();
+    let mut recipients = Vec::new();
+    for subscriber in get_confirmed_subscribers(&pool).await? {
         match subscriber {
             Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}.", subscriber.email)
-                    })?;
+                subscriber_ids.push(subscriber.id);
+                recipients.push(subscriber.email);
             }
             Err(error) => {
                 tracing::warn!(
@@ -126,39 +176,86 @@ pub async fn publish_newsletter(
             }
         }
     }
-    Ok(HttpResponse::Ok().finish())
+    let newsletter_issue_id = insert_newsletter_issue(&pool, &body.title, user_id).await?;
+    let outcomes = email_client.send_batch(&recipients, &message).await;
+    let deliveries: Vec<_> = subscriber_ids
+        .iter()
+        .zip(&outcomes)
+        .map(|(subscriber_id, (_, outcome))| (*subscriber_id, outcome))
+        .collect();
+    record_deliveries(
+        &pool,
+        DeliveryKind::NewsletterIssue(newsletter_issue_id),
+        &deliveries,
+    )
+    .await?;
+    let mut delivered = 0;
+    let mut failures = Vec::new();
+    let mut suppressed = 0;
+    for (subscriber_id, (recipient, outcome)) in subscriber_ids.iter().zip(&outcomes) {
+        match outcome {
+            Ok(_) => delivered += 1,
+            // left out on purpose, not a failure
+            Err(EmailError::Suppressed(_)) => suppressed += 1,
+            Err(error) => {
+                // the address goes in a PII field, not in the error message
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    recipient = %recipient,
+                    "Failed to send newsletter issue"
+                );
+                failures.push(serde_json::json!({
+                    "subscriber_id": subscriber_id,
+                    "error": error.to_string(),
+                    "error_code": error.error_code(),
+                    "retryable": error.is_retryable()
+                }));
+            }
+        }
+    }
+    let event = AuditEvent::new(AuditAction::NewsletterPublish)
+        .actor(user_id)
+        .target("newsletter_issue", newsletter_issue_id)
+        .changes(serde_json::json!({
+            "title": body.title,
+            "recipients": delivered,
+            "failures": failures.len(),
+            "suppressed": suppressed
+        }));
+    record_audit_event(&pool, &AuditContext::from_request(&request), event).await?;
+    // the issue is out, even if not to everyone: which sends failed is
+    // reported rather than failing the whole request
+    let status = if failures.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+    Ok(HttpResponse::build(status).json(serde_json::json!({
+        "newsletter_issue_id": newsletter_issue_id,
+        "delivered": delivered,
+        "suppressed": suppressed,
+        "failed": failures
+    })))
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    // header value if present must be a utf-8 string
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 String")?;
-
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic '.")?;
-
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .context("Failed to base64-decode 'Basic' credentials")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credentials string is not valid UTF8.")?;
-
-    // splitting the decoded credentials into two segments, username and password via : delimiter
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
+#[tracing::instrument(name = "Save a newsletter issue", skip(pool, title))]
+async fn insert_newsletter_issue(
+    pool: &PgPool,
+    title: &str,
+    published_by: Uuid,
+) -> Result<Uuid, anyhow::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (newsletter_issue_id, title, published_by, published_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        newsletter_issue_id,
+        title,
+        published_by
+    )
+    .execute(pool)
+    .await
+    .context("Failed to save the newsletter issue.")?;
+    Ok(newsletter_issue_id)
 }
@@ -0,0 +1,84 @@
+use crate::authentication::is_valid_password_reset_token;
+use crate::csrf::csrf_field;
+use crate::utils::{clear_flash_cookie, e500, flash_message_html, see_other_with_flash};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+pub async fn password_reset_form(request: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Forgot your password?</title>
+  </head>
+  <body>
+    {}
+    <p>Enter the email address of your account and we will send you a link to choose a new password.</p>
+    <form action="/password-reset" method="post">
+      {}
+      <label>Email <input type="email" name="email" /></label>
+      <button type="submit">Send reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            csrf_field(&request)?
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    token: Secret<String>,
+}
+
+pub async fn new_password_form(
+    request: HttpRequest,
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !is_valid_password_reset_token(&pool, &query.token)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(see_other_with_flash(
+            "/password-reset",
+            "This password reset link is invalid or has expired.",
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .cookie(clear_flash_cookie())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Choose a new password</title>
+  </head>
+  <body>
+    {}
+    <form action="/password-reset/confirm" method="post">
+      {}
+      <input hidden type="text" name="token" value="{}" />
+      <label>New password <input type="password" name="password" /></label>
+      <br />
+      <label>Confirm new password <input type="password" name="password_check" /></label>
+      <br />
+      <button type="submit">Change password</button>
+    </form>
+  </body>
+</html>"#,
+            flash_message_html(&request),
+            csrf_field(&request)?,
+            htmlescape::encode_attribute(query.token.expose_secret())
+        )))
+}
@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::{new_password_form, password_reset_form};
+pub use post::{confirm_password_reset, request_password_reset};
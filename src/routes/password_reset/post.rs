@@ -0,0 +1,139 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext, AuditEvent};
+use crate::authentication::{
+    compute_password_hash, create_password_reset_token, reset_password, validate_new_password,
+};
+use crate::configuration::AuthenticationSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::startup::ApplicaitonBaseUrl;
+use crate::utils::{e500, see_other_with_flash};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use tracing::Instrument;
+
+#[derive(serde::Deserialize)]
+pub struct RequestFormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Request a password reset", skip_all)]
+pub async fn request_password_reset(
+    form: web::Form<RequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicaitonBaseUrl>,
+) -> HttpResponse {
+    // the lookup and the email happen in the background, so that neither the
+    // response nor its timing tell whether the address belongs to a user
+    tokio::spawn(
+        async move {
+            if let Err(e) =
+                send_password_reset_link(&pool, &email_client, &base_url.0, form.0.email).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a password reset link"
+                );
+            }
+        }
+        .in_current_span(),
+    );
+    see_other_with_flash(
+        "/login",
+        "If an account exists for that email address, you will receive a link to reset your password shortly.",
+    )
+}
+
+async fn send_password_reset_link(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    email: String,
+) -> Result<(), anyhow::Error> {
+    let email = match SubscriberEmail::parse(email.trim().to_string()) {
+        Ok(email) => email,
+        Err(_) => return Ok(()),
+    };
+    let token = match create_password_reset_token(pool, email.as_ref()).await? {
+        Some(token) => token,
+        None => return Ok(()),
+    };
+    let reset_link = format!(
+        "{}/password-reset/confirm?token={}",
+        base_url,
+        token.expose_secret()
+    );
+    let plain_body = format!(
+        "Someone asked to reset the password of your newsletter account.\n\
+        Visit {} to choose a new password. The link expires in an hour.\n\
+        If it was not you, you can ignore this email.",
+        reset_link
+    );
+    let html_body = format!(
+        "Someone asked to reset the password of your newsletter account.<br />\
+        Click <a href=\"{}\">here</a> to choose a new password. The link expires in an hour.<br />\
+        If it was not you, you can ignore this email.",
+        reset_link
+    );
+    email_client
+        .send_email(&email, "Reset your password", &html_body, &plain_body)
+        .await
+        .context("Failed to send a password reset email.")?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmFormData {
+    token: Secret<String>,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Reset a password", skip_all, fields(user_id=tracing::field::Empty))]
+pub async fn confirm_password_reset(
+    form: web::Form<ConfirmFormData>,
+    pool: web::Data<PgPool>,
+    auth_settings: web::Data<AuthenticationSettings>,
+    audit: AuditContext,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ConfirmFormData {
+        token,
+        password,
+        password_check,
+    } = form.0;
+    if let Err(e) = validate_new_password(&password, &password_check) {
+        let form_url = format!(
+            "/password-reset/confirm?token={}",
+            urlencoding::encode(token.expose_secret())
+        );
+        return Ok(see_other_with_flash(&form_url, &e));
+    }
+    let password_hash = compute_password_hash(password, &auth_settings.password_hashing)
+        .await
+        .map_err(e500)?;
+    match reset_password(&pool, &token, password_hash)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            let event = AuditEvent::new(AuditAction::PasswordReset)
+                .actor(user_id)
+                .target("user", user_id);
+            record_audit_event(&pool, &audit, event)
+                .await
+                .map_err(e500)?;
+            Ok(see_other_with_flash(
+                "/login",
+                "Your password has been reset. You can now log in.",
+            ))
+        }
+        None => Ok(see_other_with_flash(
+            "/password-reset",
+            "This password reset link is invalid or has expired.",
+        )),
+    }
+}
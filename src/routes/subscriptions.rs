@@ -1,5 +1,7 @@
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailClient, EmailError, SentEmail};
+use crate::email_deliveries::{record_deliveries, DeliveryKind};
+use crate::metrics::Metrics;
 use crate::startup::ApplicaitonBaseUrl;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -92,7 +94,7 @@ pub fn error_chain_fmt(
 // handler for the route
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, metrics),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -103,6 +105,7 @@ pub async fn subscribe(
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicaitonBaseUrl>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, SubscribeError> {
     let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
     // start sqlx transaction
@@ -137,14 +140,32 @@ pub async fn subscribe(
         .context("Failed to commit SQL transaction to store a new subscriber")?;
 
     // send the confirmaiton email
-    send_confirmation_email(
+    let outcome = send_confirmation_email(
         &email_client,
         new_subscriber,
         &base_url.0,
         &subscription_token,
     )
-    .await
-    .context("Failed to send confirmation email")?;
+    .await;
+    record_deliveries(
+        &pool,
+        DeliveryKind::Confirmation,
+        &[(subscriber_id, &outcome)],
+    )
+    .await?;
+    match outcome {
+        // the response must not tell whether an address is suppressed
+        Err(EmailError::Suppressed(reason)) => {
+            tracing::info!(
+                reason,
+                "Not sending a confirmation email to a suppressed address"
+            )
+        }
+        outcome => {
+            outcome.context("Failed to send confirmation email")?;
+        }
+    }
+    metrics.subscriptions.inc();
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -158,7 +179,7 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<SentEmail, EmailError> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
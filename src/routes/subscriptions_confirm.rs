@@ -1,3 +1,4 @@
+use crate::metrics::Metrics;
 use crate::routes::error_chain_fmt;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -33,10 +34,11 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool, metrics))]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, ConfirmationError> {
     let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
         .await
@@ -45,6 +47,7 @@ pub async fn confirm(
     confirm_subscriber(&pool, subscriber_id)
         .await
         .context("Failed to update the subscriber status to `confirmed`.")?;
+    metrics.subscription_confirmations.inc();
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -0,0 +1,212 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext};
+use crate::authentication::{
+    api_credentials, authenticate_api_request, record_denial, ApiScope, AuthError,
+};
+use crate::configuration::AuthenticationSettings;
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{
+    add_suppression, list_suppressions, remove_suppression, NewSuppression, Suppression,
+    SuppressionReason, SuppressionSource,
+};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use sqlx::PgPool;
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum SuppressionsError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Insufficient permissions.")]
+    Forbidden(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The address is suppressed already.")]
+    AlreadySuppressed,
+    #[error("There is no such suppression.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SuppressionsError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SuppressionsError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                for challenge in [
+                    r#"Basic realm="suppressions""#,
+                    r#"Bearer realm="suppressions""#,
+                ] {
+                    response.headers_mut().append(
+                        header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static(challenge),
+                    );
+                }
+                response
+            }
+            SuppressionsError::Forbidden(_) => HttpResponse::new(StatusCode::FORBIDDEN),
+            SuppressionsError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            SuppressionsError::AlreadySuppressed => HttpResponse::new(StatusCode::CONFLICT),
+            SuppressionsError::NotFound => HttpResponse::new(StatusCode::NOT_FOUND),
+            SuppressionsError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionsQuery {
+    page: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionData {
+    email: String,
+    /// One of `SuppressionReason`, e.g. `erasure_request`.
+    reason: String,
+    note: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "List suppressions through the API",
+    skip(query, pool, request, auth_settings, root_span),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn get_suppressions(
+    query: web::Query<SuppressionsQuery>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    auth_settings: web::Data<AuthenticationSettings>,
+    root_span: RootSpan,
+) -> Result<HttpResponse, SuppressionsError> {
+    authenticate(
+        &request,
+        &pool,
+        ApiScope::SubscribersRead,
+        &auth_settings,
+        &root_span,
+    )
+    .await?;
+    let page = query.page.unwrap_or(1).max(1);
+    let suppressions: Vec<_> = list_suppressions(&pool, page)
+        .await?
+        .iter()
+        .map(suppression_json)
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "suppressions": suppressions })))
+}
+
+#[tracing::instrument(
+    name = "Add a suppression through the API",
+    skip(body, pool, request, auth_settings, root_span),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn post_suppression(
+    body: web::Json<SuppressionData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    auth_settings: web::Data<AuthenticationSettings>,
+    root_span: RootSpan,
+) -> Result<HttpResponse, SuppressionsError> {
+    let user_id = authenticate(
+        &request,
+        &pool,
+        ApiScope::SubscribersManage,
+        &auth_settings,
+        &root_span,
+    )
+    .await?;
+    let body = body.into_inner();
+    let new_suppression = NewSuppression {
+        email: SubscriberEmail::parse(body.email.trim().to_string())
+            .map_err(SuppressionsError::ValidationError)?,
+        reason: SuppressionReason::try_from(body.reason.as_str())
+            .map_err(SuppressionsError::ValidationError)?,
+        source: SuppressionSource::Admin,
+        note: body.note.filter(|note| !note.trim().is_empty()),
+        created_by: Some(user_id),
+    };
+    let suppression = add_suppression(&pool, &new_suppression)
+        .await?
+        .ok_or(SuppressionsError::AlreadySuppressed)?;
+    let event = suppression
+        .audit_event(AuditAction::SuppressionAdd)
+        .actor(user_id);
+    record_audit_event(&pool, &AuditContext::from_request(&request), event).await?;
+    Ok(HttpResponse::Created().json(suppression_json(&suppression)))
+}
+
+#[tracing::instrument(
+    name = "Remove a suppression through the API",
+    skip(pool, request, auth_settings, root_span),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn delete_suppression(
+    suppression_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    auth_settings: web::Data<AuthenticationSettings>,
+    root_span: RootSpan,
+) -> Result<HttpResponse, SuppressionsError> {
+    let user_id = authenticate(
+        &request,
+        &pool,
+        ApiScope::SubscribersManage,
+        &auth_settings,
+        &root_span,
+    )
+    .await?;
+    let suppression = remove_suppression(&pool, suppression_id.into_inner())
+        .await?
+        .ok_or(SuppressionsError::NotFound)?;
+    let event = suppression
+        .audit_event(AuditAction::SuppressionRemove)
+        .actor(user_id);
+    record_audit_event(&pool, &AuditContext::from_request(&request), event).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+    scope: ApiScope,
+    auth_settings: &AuthenticationSettings,
+    root_span: &RootSpan,
+) -> Result<Uuid, SuppressionsError> {
+    let credentials = api_credentials(request.headers()).map_err(SuppressionsError::AuthError)?;
+    let user_id = authenticate_api_request(credentials, pool, scope, auth_settings)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => SuppressionsError::AuthError(e.into()),
+            AuthError::Forbidden(_) => {
+                record_denial(root_span, scope.required_permission());
+                SuppressionsError::Forbidden(e.into())
+            }
+            AuthError::UnexpectedError(_) => SuppressionsError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    Ok(user_id)
+}
+
+fn suppression_json(suppression: &Suppression) -> serde_json::Value {
+    serde_json::json!({
+        "suppression_id": suppression.suppression_id,
+        "email": suppression.email,
+        "reason": suppression.reason,
+        "source": suppression.source,
+        "note": suppression.note,
+        "created_by": suppression.created_by,
+        "created_at": suppression.created_at.to_rfc3339(),
+    })
+}
@@ -0,0 +1,341 @@
+use crate::audit::{record_audit_event, AuditAction, AuditContext};
+use crate::authentication::{api_credentials, ApiCredentials};
+use crate::configuration::WebhookCredentials;
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{
+    add_suppression, lift_suppression, NewSuppression, SuppressionReason, SuppressionSource,
+};
+use crate::utils::constant_time_eq;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("The payload is not a webhook we understand.")]
+    InvalidPayload(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="webhooks""#),
+                );
+                response
+            }
+            WebhookError::InvalidPayload(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// The webhooks Postmark calls us with, told apart by their `RecordType`.
+/// https://postmarkapp.com/developer/webhooks/webhooks-overview
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    Bounce(Bounce),
+    SpamComplaint(SpamComplaint),
+    SubscriptionChange(SubscriptionChange),
+    // deliveries, opens and clicks
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Bounce {
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    email: String,
+    #[serde(rename = "Type")]
+    bounce_type: String,
+    /// Whether Postmark stopped sending to the address because of it.
+    #[serde(default)]
+    inactive: bool,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SpamComplaint {
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    email: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SubscriptionChange {
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    recipient: String,
+    suppress_sending: bool,
+    suppression_reason: Option<String>,
+}
+
+// what an event means for the subscriber it is about
+enum StatusChange {
+    Suppress(SuppressionReason),
+    // the address was taken off the provider's suppression list
+    Reactivate,
+}
+
+#[tracing::instrument(
+    name = "Handle a Postmark webhook",
+    skip(body, request, pool, credentials, audit),
+    fields(record_type = tracing::field::Empty, subscriber_id = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    body: web::Bytes,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    credentials: web::Data<WebhookCredentials>,
+    audit: AuditContext,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate(&request, &credentials).map_err(WebhookError::AuthError)?;
+    let event: PostmarkEvent =
+        serde_json::from_slice(&body).map_err(WebhookError::InvalidPayload)?;
+    tracing::Span::current().record("record_type", record_type(&event));
+    let (message_id, email, delivery_status, change) = match &event {
+        PostmarkEvent::Bounce(bounce) => {
+            let is_permanent = bounce.inactive || bounce.bounce_type == "HardBounce";
+            (
+                &bounce.message_id,
+                &bounce.email,
+                Some("bounced"),
+                is_permanent.then_some(StatusChange::Suppress(SuppressionReason::Bounced)),
+            )
+        }
+        PostmarkEvent::SpamComplaint(complaint) => (
+            &complaint.message_id,
+            &complaint.email,
+            Some("complained"),
+            Some(StatusChange::Suppress(SuppressionReason::Complained)),
+        ),
+        PostmarkEvent::SubscriptionChange(change) => {
+            let status_change = if !change.suppress_sending {
+                StatusChange::Reactivate
+            } else {
+                match change.suppression_reason.as_deref() {
+                    Some("HardBounce") => StatusChange::Suppress(SuppressionReason::Bounced),
+                    Some("SpamComplaint") => StatusChange::Suppress(SuppressionReason::Complained),
+                    // the recipient unsubscribed through the provider
+                    _ => StatusChange::Suppress(SuppressionReason::Unsubscribed),
+                }
+            };
+            (
+                &change.message_id,
+                &change.recipient,
+                None,
+                Some(status_change),
+            )
+        }
+        PostmarkEvent::Other => return Ok(HttpResponse::Ok().finish()),
+    };
+    // the address stays suppressed whether or not it belongs to a subscriber
+    match &change {
+        Some(StatusChange::Suppress(reason)) => {
+            suppress_address(&pool, &audit, email, *reason).await?
+        }
+        Some(StatusChange::Reactivate) => unsuppress_address(&pool, &audit, email).await?,
+        None => {}
+    }
+
+    let subscriber_id = find_subscriber(&pool, message_id.as_deref(), email).await?;
+    let subscriber_id = match subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            // not one of ours, e.g. a user's password reset email
+            tracing::info!("The webhook is about no known subscriber");
+            return Ok(HttpResponse::Ok().finish());
+        }
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(subscriber_id));
+    if let (Some(message_id), Some(status)) = (message_id, delivery_status) {
+        update_delivery_status(&pool, message_id, status).await?;
+    }
+    match change {
+        Some(StatusChange::Suppress(reason)) => {
+            suppress_subscriber(&pool, subscriber_id, reason.as_str()).await?
+        }
+        Some(StatusChange::Reactivate) => reactivate_subscriber(&pool, subscriber_id).await?,
+        None => {}
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn record_type(event: &PostmarkEvent) -> &'static str {
+    match event {
+        PostmarkEvent::Bounce(_) => "Bounce",
+        PostmarkEvent::SpamComplaint(_) => "SpamComplaint",
+        PostmarkEvent::SubscriptionChange(_) => "SubscriptionChange",
+        PostmarkEvent::Other => "Other",
+    }
+}
+
+#[tracing::instrument(name = "Suppress a webhook's address", skip(pool, audit, email))]
+async fn suppress_address(
+    pool: &PgPool,
+    audit: &AuditContext,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<(), anyhow::Error> {
+    let email = match SubscriberEmail::parse(email.to_string()) {
+        Ok(email) => email,
+        // nothing could be sent to it anyway
+        Err(e) => {
+            tracing::warn!(error = %e, "The webhook is about an invalid address");
+            return Ok(());
+        }
+    };
+    let new_suppression = NewSuppression {
+        email,
+        reason,
+        source: SuppressionSource::Postmark,
+        note: None,
+        created_by: None,
+    };
+    if let Some(suppression) = add_suppression(pool, &new_suppression).await? {
+        let event = suppression.audit_event(AuditAction::SuppressionAdd);
+        record_audit_event(pool, audit, event).await?;
+    }
+    Ok(())
+}
+
+// only lifts what the provider's webhooks suppressed
+#[tracing::instrument(name = "Unsuppress a webhook's address", skip(pool, audit, email))]
+async fn unsuppress_address(
+    pool: &PgPool,
+    audit: &AuditContext,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let reasons = [SuppressionReason::Bounced, SuppressionReason::Complained];
+    if let Some(suppression) =
+        lift_suppression(pool, email, SuppressionSource::Postmark, &reasons).await?
+    {
+        let event = suppression.audit_event(AuditAction::SuppressionRemove);
+        record_audit_event(pool, audit, event).await?;
+    }
+    Ok(())
+}
+
+fn authenticate(request: &HttpRequest, expected: &WebhookCredentials) -> Result<(), anyhow::Error> {
+    let credentials = match api_credentials(request.headers())? {
+        ApiCredentials::Basic(credentials) => credentials,
+        ApiCredentials::Bearer(_) => anyhow::bail!("Webhooks use 'Basic' credentials."),
+    };
+    let username_matches = constant_time_eq(
+        credentials.username.as_bytes(),
+        expected.username.as_bytes(),
+    );
+    let password_matches = constant_time_eq(
+        credentials.password.expose_secret().as_bytes(),
+        expected.password.expose_secret().as_bytes(),
+    );
+    if !(username_matches && password_matches) {
+        anyhow::bail!("Invalid webhook credentials.");
+    }
+    Ok(())
+}
+
+// by the email that was sent to them if we know it, by address otherwise
+#[tracing::instrument(name = "Find the subscriber of a webhook", skip(pool, email))]
+async fn find_subscriber(
+    pool: &PgPool,
+    message_id: Option<&str>,
+    email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let subscriber_id = sqlx::query_scalar!(
+        r#"
+        SELECT subscriber_id AS "subscriber_id!" FROM (
+            SELECT subscriber_id, 1 AS priority FROM email_deliveries
+            WHERE provider_message_id = $1
+            UNION ALL
+            SELECT id, 2 FROM subscriptions WHERE lower(email) = lower($2)
+        ) AS candidates
+        ORDER BY priority
+        LIMIT 1
+        "#,
+        message_id,
+        email
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscriber of a webhook.")?;
+    Ok(subscriber_id)
+}
+
+async fn update_delivery_status(
+    pool: &PgPool,
+    message_id: &str,
+    status: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE email_deliveries SET status = $1 WHERE provider_message_id = $2",
+        status,
+        message_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the status of a delivery.")?;
+    Ok(())
+}
+
+// only subscribers who confirmed: a pending one stays pending, so that a later
+// reactivation cannot confirm an address that never was
+#[tracing::instrument(name = "Stop sending to a subscriber", skip(pool))]
+async fn suppress_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    status: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $1
+        WHERE id = $2 AND status IN ('confirmed', 'bounced', 'complained')
+        "#,
+        status,
+        subscriber_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the status of a subscriber.")?;
+    Ok(())
+}
+
+// only undoes what the provider's webhooks did, people who unsubscribed stay so
+#[tracing::instrument(name = "Resume sending to a subscriber", skip(pool))]
+async fn reactivate_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status IN ('bounced', 'complained')
+        "#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reactivate a subscriber.")?;
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+// typed wrapper around the session so that keys are not sprinkled across handlers
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    const PENDING_USER_ID_KEY: &'static str = "pending_two_factor_user_id";
+    const TOTP_ENROLLMENT_KEY: &'static str = "totp_enrollment_secret";
+    const SESSION_GENERATION_KEY: &'static str = "session_generation";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
+
+    /// Rotate the session key, to be called whenever the privilege level changes.
+    ///
+    /// The CSRF token is rotated along with it.
+    pub fn renew(&self) {
+        self.0.remove(Self::CSRF_TOKEN_KEY);
+        self.0.renew();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    /// The user's session generation when they logged in. The session stops
+    /// being valid once the generation stored against the user moves on.
+    pub fn insert_session_generation(&self, generation: i32) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_GENERATION_KEY, generation)
+    }
+
+    pub fn get_session_generation(&self) -> Result<Option<i32>, SessionGetError> {
+        self.0.get(Self::SESSION_GENERATION_KEY)
+    }
+
+    /// A user who passed the password check but still owes us a second factor.
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    /// The second factor has been checked (or the user gave up), forget about the pending login.
+    pub fn remove_pending_user_id(&self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+    }
+
+    pub fn insert_totp_enrollment(&self, secret: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::TOTP_ENROLLMENT_KEY, secret)
+    }
+
+    pub fn get_totp_enrollment(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::TOTP_ENROLLMENT_KEY)
+    }
+
+    pub fn remove_totp_enrollment(&self) {
+        self.0.remove(Self::TOTP_ENROLLMENT_KEY);
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    /// The session's CSRF token, generated on first use.
+    pub fn get_or_insert_csrf_token(&self) -> Result<String, anyhow::Error> {
+        if let Some(token) = self.get_csrf_token()? {
+            return Ok(token);
+        }
+        let token: String = std::iter::repeat_with(|| thread_rng().sample(Alphanumeric))
+            .map(char::from)
+            .take(CSRF_TOKEN_LENGTH)
+            .collect();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    pub fn log_out(self) {
+        self.0.purge()
+    }
+}
+
+impl From<&HttpRequest> for TypedSession {
+    fn from(req: &HttpRequest) -> Self {
+        TypedSession(req.get_session())
+    }
+}
+
+impl FromRequest for TypedSession {
+    // returning the same error as the implementation of FromRequest for Session
+    type Error = <Session as FromRequest>::Error;
+    // Session extraction is synchronous, so there is no need to box a future
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
@@ -1,38 +1,90 @@
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::authentication::{
+    reject_anonymous_users, require_two_factor_enrollment, Permission, RequirePermission,
+};
+use crate::configuration::{
+    AuthenticationSettings, DatabaseSettings, Settings, WebhookCredentials,
+};
+use crate::csrf::reject_forged_requests;
+use crate::email_client::{EmailClient, Mailbox};
+use crate::heartbeat::Heartbeats;
+use crate::metrics::{
+    metrics_endpoint, record_http_metrics, sample_pool, Metrics, MetricsToken, POOL_SAMPLE_INTERVAL,
+};
+use crate::migrations::run_migrations;
 use crate::routes::{
-    confirm, health_check, home, login, login_form, publish_newsletter, subscribe,
+    accept_invitation_for_user, accept_invitation_form, admin_audit_log, admin_audit_log_csv,
+    admin_dashboard, admin_diagnostics, admin_log_filter_form, admin_settings_form, admin_stats,
+    admin_subscribers, admin_users, api_tokens_form, change_user_role, confirm,
+    confirm_password_reset, create_api_token_for_user, deactivate_user, delete_suppression,
+    dev_mailbox, dev_mailbox_email, disable_two_factor, enable_two_factor, get_suppressions,
+    health_check, health_live, health_ready, home, invite_user, log_out, login, login_form,
+    login_two_factor, login_two_factor_form, new_password_form, password_reset_form,
+    post_suppression, postmark_webhook, publish_newsletter, reactivate_user,
+    regenerate_two_factor_recovery_codes, request_password_reset, reset_log_filter,
+    revoke_api_token_for_user, revoke_user_invitation, subscribe, two_factor_form,
+    unsubscribe_subscriber, update_admin_settings, update_log_filter, NEWSLETTER_PAYLOAD_SIZE,
 };
+use crate::telemetry::{AppRootSpanBuilder, LogFilter};
+use actix_session::storage::CookieSessionStore;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
-use secrecy::Secret;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
+/// `actix_web::cookie::Key` needs at least this many bytes.
+pub const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(
+        configuration: Settings,
+        log_filter: LogFilter,
+    ) -> Result<Self, anyhow::Error> {
         // building the database
         let connection_pool = get_connection_pool(&configuration.database);
-        // let email_client = configuration.email_client.client();
-
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        // before anything else, a mailbox enabled in production is refused
+        let email_client = configuration
+            .email_client(connection_pool.clone())
+            .map_err(anyhow::Error::msg)?;
+        // the session key would panic on it further down
+        if configuration.application.hmac_secret.expose_secret().len() < MIN_HMAC_SECRET_LENGTH {
+            anyhow::bail!(
+                "application.hmac_secret must be at least {} bytes long.",
+                MIN_HMAC_SECRET_LENGTH
+            );
+        }
+        if configuration.application.run_migrations_on_startup {
+            run_migrations(&connection_pool).await?;
+        }
+        configuration
+            .authentication
+            .password_hashing
+            .params()
+            .expect("Invalid password hashing parameters.");
+        let metrics = Metrics::new().context("Failed to register the metrics.")?;
+        let email_client = email_client.with_metrics(metrics.email.clone());
+        let mailbox = email_client.mailbox().cloned();
+        let heartbeats = Heartbeats::default();
+        // stale once it has missed a couple of samples
+        let heartbeat = heartbeats.register("pool_sampler", POOL_SAMPLE_INTERVAL * 3);
+        tokio::spawn(sample_pool(
+            metrics.clone(),
+            connection_pool.clone(),
+            heartbeat,
+        ));
 
         // address coming from config file
         let address = format!(
@@ -41,22 +93,53 @@ impl Application {
         );
         let listener = TcpListener::bind(&address)?;
         let port = listener.local_addr().unwrap().port();
+        let (metrics_port, metrics_server) = match &configuration.metrics.listen_address {
+            Some(address) => {
+                let listener = TcpListener::bind(address).with_context(|| {
+                    format!("Failed to bind the metrics listener to {}.", address)
+                })?;
+                let port = listener.local_addr().unwrap().port();
+                let server = run_metrics(listener, metrics.clone())?;
+                (Some(port), Some(server))
+            }
+            None => (None, None),
+        };
         let server = run(
             listener,
             connection_pool,
             email_client,
             configuration.application.base_url,
             configuration.application.hmac_secret,
+            configuration.authentication,
+            heartbeats,
+            metrics,
+            configuration.metrics.bearer_token.map(MetricsToken),
+            configuration.webhooks.postmark,
+            mailbox,
+            log_filter,
         )?;
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The port of the private metrics listener, if there is one.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        match self.metrics_server {
+            Some(metrics_server) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+            None => self.server.await,
+        }
     }
 }
 
@@ -64,31 +147,204 @@ pub struct ApplicaitonBaseUrl(pub String);
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
 
+// the private listener, serving nothing but the metrics
+fn run_metrics(listener: TcpListener, metrics: Metrics) -> Result<Server, std::io::Error> {
+    let metrics = web::Data::new(metrics);
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .app_data(metrics.clone())
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
     base_url: String,
     hmac_secret: Secret<String>,
+    authentication: AuthenticationSettings,
+    heartbeats: Heartbeats,
+    metrics: Metrics,
+    metrics_token: Option<MetricsToken>,
+    postmark_webhook_credentials: Option<WebhookCredentials>,
+    mailbox: Option<Mailbox>,
+    log_filter: LogFilter,
 ) -> Result<Server, std::io::Error> {
     // creates an Arc around the connection to giv cloneable trait to our connection
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
+    // secure cookies are only sent back over https, which we do not have locally
+    let secure_cookies = base_url.starts_with("https://");
     let base_url = web::Data::new(ApplicaitonBaseUrl(base_url));
+    let authentication = web::Data::new(authentication);
+    let heartbeats = web::Data::new(heartbeats);
+    let metrics = web::Data::new(metrics);
+    let metrics_token = metrics_token.map(web::Data::new);
+    let postmark_webhook_credentials = postmark_webhook_credentials.map(web::Data::new);
+    let mailbox = mailbox.map(web::Data::new);
+    let log_filter = web::Data::new(log_filter);
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let server = HttpServer::new(move || {
-        App::new()
-            .wrap(TracingLogger::default())
+        let app = App::new()
+            .wrap(from_fn(reject_forged_requests))
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
+                    .cookie_secure(secure_cookies)
+                    .build(),
+            )
+            .wrap(TracingLogger::<AppRootSpanBuilder>::new())
+            .wrap(from_fn(record_http_metrics))
             .route("/", web::get().to(home))
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            .route("/login/two-factor", web::get().to(login_two_factor_form))
+            .route("/login/two-factor", web::post().to(login_two_factor))
+            .route("/password-reset", web::get().to(password_reset_form))
+            .route("/password-reset", web::post().to(request_password_reset))
+            .route("/password-reset/confirm", web::get().to(new_password_form))
+            .route(
+                "/password-reset/confirm",
+                web::post().to(confirm_password_reset),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(require_two_factor_enrollment))
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/two-factor", web::get().to(two_factor_form))
+                    .route("/two-factor/enable", web::post().to(enable_two_factor))
+                    .route(
+                        "/two-factor/recovery-codes",
+                        web::post().to(regenerate_two_factor_recovery_codes),
+                    )
+                    .route("/two-factor/disable", web::post().to(disable_two_factor))
+                    .route("/api-tokens", web::get().to(api_tokens_form))
+                    .route("/api-tokens", web::post().to(create_api_token_for_user))
+                    .route(
+                        "/api-tokens/{token_id}/revoke",
+                        web::post().to(revoke_api_token_for_user),
+                    )
+                    .service(
+                        web::resource("/settings")
+                            .wrap(RequirePermission(Permission::ManageSettings))
+                            .route(web::get().to(admin_settings_form))
+                            .route(web::post().to(update_admin_settings)),
+                    )
+                    .service(
+                        web::resource("/diagnostics")
+                            .wrap(RequirePermission(Permission::ManageSettings))
+                            .route(web::get().to(admin_diagnostics)),
+                    )
+                    .service(
+                        web::resource("/log-filter")
+                            .wrap(RequirePermission(Permission::ManageSettings))
+                            .route(web::get().to(admin_log_filter_form))
+                            .route(web::post().to(update_log_filter)),
+                    )
+                    .service(
+                        web::resource("/log-filter/reset")
+                            .wrap(RequirePermission(Permission::ManageSettings))
+                            .route(web::post().to(reset_log_filter)),
+                    )
+                    .service(
+                        web::resource("/stats")
+                            .wrap(RequirePermission(Permission::ViewStats))
+                            .route(web::get().to(admin_stats)),
+                    )
+                    .service(
+                        web::resource("/subscribers")
+                            .wrap(RequirePermission(Permission::ViewSubscribers))
+                            .route(web::get().to(admin_subscribers)),
+                    )
+                    .service(
+                        web::resource("/subscribers/{subscriber_id}/unsubscribe")
+                            .wrap(RequirePermission(Permission::ManageSubscribers))
+                            .route(web::post().to(unsubscribe_subscriber)),
+                    )
+                    .service(
+                        web::resource("/audit-log")
+                            .wrap(RequirePermission(Permission::ViewAuditLog))
+                            .route(web::get().to(admin_audit_log)),
+                    )
+                    .service(
+                        web::resource("/audit-log.csv")
+                            .wrap(RequirePermission(Permission::ViewAuditLog))
+                            .route(web::get().to(admin_audit_log_csv)),
+                    )
+                    .service(
+                        web::scope("/users")
+                            .wrap(RequirePermission(Permission::ManageUsers))
+                            .route("", web::get().to(admin_users))
+                            .route("/invitations", web::post().to(invite_user))
+                            .route(
+                                "/invitations/{invitation_id}/revoke",
+                                web::post().to(revoke_user_invitation),
+                            )
+                            .route("/{user_id}/role", web::post().to(change_user_role))
+                            .route("/{user_id}/deactivate", web::post().to(deactivate_user))
+                            .route("/{user_id}/reactivate", web::post().to(reactivate_user)),
+                    )
+                    .route("/logout", web::post().to(log_out)),
+            )
+            .route("/invitations/accept", web::get().to(accept_invitation_form))
+            .route(
+                "/invitations/accept",
+                web::post().to(accept_invitation_for_user),
+            )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
-            .route("/newsletters", web::post().to(publish_newsletter))
+            .service(
+                web::resource("/newsletters")
+                    .app_data(web::JsonConfig::default().limit(NEWSLETTER_PAYLOAD_SIZE))
+                    .route(web::post().to(publish_newsletter)),
+            )
+            .route("/suppressions", web::get().to(get_suppressions))
+            .route("/suppressions", web::post().to(post_suppression))
+            .route(
+                "/suppressions/{suppression_id}",
+                web::delete().to(delete_suppression),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(authentication.clone())
+            .app_data(heartbeats.clone())
             .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
+            .app_data(metrics.clone())
+            .app_data(log_filter.clone());
+        // without a token the metrics are only served on the private listener
+        let app = match &metrics_token {
+            Some(token) => app
+                .app_data(token.clone())
+                .route("/metrics", web::get().to(metrics_endpoint)),
+            None => app,
+        };
+        let app = match &postmark_webhook_credentials {
+            Some(credentials) => app.service(
+                web::resource("/webhooks/postmark")
+                    .app_data(credentials.clone())
+                    .route(web::post().to(postmark_webhook)),
+            ),
+            None => app,
+        };
+        // only configured outside of production
+        match &mailbox {
+            Some(mailbox) => app.service(
+                web::scope("/dev/mailbox")
+                    .app_data(mailbox.clone())
+                    .route("", web::get().to(dev_mailbox))
+                    .route("/{email_id}", web::get().to(dev_mailbox_email)),
+            ),
+            None => app,
+        }
     })
     .listen(listener)?
     .run();
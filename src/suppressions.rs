@@ -0,0 +1,259 @@
+//! Addresses we must not email anymore, kept in the `suppressions` table and
+//! checked by the `EmailClient` before every send.
+use crate::audit::{AuditAction, AuditEvent};
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const PAGE_SIZE: i64 = 100;
+
+/// Why an address is suppressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SuppressionReason {
+    Unsubscribed,
+    Bounced,
+    Complained,
+    /// The person asked for their data to be erased.
+    ErasureRequest,
+    /// Anything else, explained in the note.
+    Manual,
+}
+
+impl SuppressionReason {
+    pub const ALL: [SuppressionReason; 5] = [
+        SuppressionReason::Unsubscribed,
+        SuppressionReason::Bounced,
+        SuppressionReason::Complained,
+        SuppressionReason::ErasureRequest,
+        SuppressionReason::Manual,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Unsubscribed => "unsubscribed",
+            SuppressionReason::Bounced => "bounced",
+            SuppressionReason::Complained => "complained",
+            SuppressionReason::ErasureRequest => "erasure_request",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+}
+
+impl TryFrom<&str> for SuppressionReason {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        SuppressionReason::ALL
+            .into_iter()
+            .find(|reason| reason.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known suppression reason.", s))
+    }
+}
+
+/// Who suppressed an address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SuppressionSource {
+    Admin,
+    /// The email provider's webhooks.
+    Postmark,
+}
+
+impl SuppressionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionSource::Admin => "admin",
+            SuppressionSource::Postmark => "postmark",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewSuppression {
+    pub email: SubscriberEmail,
+    pub reason: SuppressionReason,
+    pub source: SuppressionSource,
+    pub note: Option<String>,
+    /// The admin who added the address, if one did.
+    pub created_by: Option<Uuid>,
+}
+
+#[derive(Debug)]
+pub struct Suppression {
+    pub suppression_id: Uuid,
+    pub email: String,
+    pub reason: String,
+    pub source: String,
+    pub note: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Suppression {
+    /// An audit event for adding or removing the suppression.
+    pub fn audit_event(&self, action: AuditAction) -> AuditEvent {
+        AuditEvent::new(action)
+            .target("suppression", self.suppression_id)
+            .changes(serde_json::json!({
+                "email": self.email,
+                "reason": self.reason,
+                "source": self.source,
+                "note": self.note,
+            }))
+    }
+}
+
+/// Suppress an address, returning the new entry. Returns `None` if the
+/// address is suppressed already: the first reason given is the one kept.
+#[tracing::instrument(
+    name = "Add a suppression",
+    skip(pool, new_suppression),
+    fields(
+        email = %new_suppression.email,
+        reason = new_suppression.reason.as_str(),
+        source = new_suppression.source.as_str()
+    )
+)]
+pub async fn add_suppression(
+    pool: &PgPool,
+    new_suppression: &NewSuppression,
+) -> Result<Option<Suppression>, anyhow::Error> {
+    let suppression = sqlx::query_as!(
+        Suppression,
+        r#"
+        INSERT INTO suppressions (suppression_id, email, reason, source, note, created_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        ON CONFLICT ((lower(email))) DO NOTHING
+        RETURNING suppression_id, email, reason, source, note, created_by, created_at
+        "#,
+        Uuid::new_v4(),
+        new_suppression.email.as_ref(),
+        new_suppression.reason.as_str(),
+        new_suppression.source.as_str(),
+        new_suppression.note,
+        new_suppression.created_by,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to add a suppression.")?;
+    Ok(suppression)
+}
+
+/// Remove a suppression, returning it if there was one.
+#[tracing::instrument(name = "Remove a suppression", skip(pool))]
+pub async fn remove_suppression(
+    pool: &PgPool,
+    suppression_id: Uuid,
+) -> Result<Option<Suppression>, anyhow::Error> {
+    let suppression = sqlx::query_as!(
+        Suppression,
+        r#"
+        DELETE FROM suppressions WHERE suppression_id = $1
+        RETURNING suppression_id, email, reason, source, note, created_by, created_at
+        "#,
+        suppression_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to remove a suppression.")?;
+    Ok(suppression)
+}
+
+/// Remove the suppression of `email` if `source` added it for one of
+/// `reasons`, e.g. once the provider takes the address off its own list.
+#[tracing::instrument(name = "Lift a suppression", skip(pool, email), fields(email = %email))]
+pub async fn lift_suppression(
+    pool: &PgPool,
+    email: &str,
+    source: SuppressionSource,
+    reasons: &[SuppressionReason],
+) -> Result<Option<Suppression>, anyhow::Error> {
+    let reasons: Vec<String> = reasons.iter().map(|r| r.as_str().to_string()).collect();
+    let suppression = sqlx::query_as!(
+        Suppression,
+        r#"
+        DELETE FROM suppressions
+        WHERE lower(email) = lower($1) AND source = $2 AND reason = ANY($3)
+        RETURNING suppression_id, email, reason, source, note, created_by, created_at
+        "#,
+        email,
+        source.as_str(),
+        &reasons,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to lift a suppression.")?;
+    Ok(suppression)
+}
+
+/// A page of suppressions, the most recent first.
+#[tracing::instrument(name = "List suppressions", skip(pool))]
+pub async fn list_suppressions(
+    pool: &PgPool,
+    page: i64,
+) -> Result<Vec<Suppression>, anyhow::Error> {
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT suppression_id, email, reason, source, note, created_by, created_at
+        FROM suppressions
+        ORDER BY created_at DESC, suppression_id
+        LIMIT $1 OFFSET $2
+        "#,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list suppressions.")?;
+    Ok(suppressions)
+}
+
+/// The reason each suppressed address among `recipients` is suppressed for,
+/// keyed by the lowercase address.
+#[tracing::instrument(name = "Check the suppression list", skip_all)]
+pub async fn find_suppressed(
+    pool: &PgPool,
+    recipients: &[SubscriberEmail],
+) -> Result<HashMap<String, String>, anyhow::Error> {
+    let emails: Vec<String> = recipients.iter().map(suppression_key).collect();
+    let suppressed = sqlx::query!(
+        r#"
+        SELECT lower(email) AS "email!", reason FROM suppressions
+        WHERE lower(email) = ANY($1)
+        "#,
+        &emails
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to check the suppression list.")?
+    .into_iter()
+    .map(|r| (r.email, r.reason))
+    .collect();
+    Ok(suppressed)
+}
+
+/// Addresses are suppressed whatever their case.
+pub fn suppression_key(email: &SubscriberEmail) -> String {
+    email.as_ref().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SuppressionReason;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn reasons_round_trip_through_their_names() {
+        for reason in SuppressionReason::ALL {
+            assert_ok_eq!(SuppressionReason::try_from(reason.as_str()), reason);
+        }
+    }
+
+    #[test]
+    fn unknown_reasons_are_rejected() {
+        assert_err!(SuppressionReason::try_from("Bounced"));
+    }
+}
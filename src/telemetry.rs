@@ -1,8 +1,36 @@
+use crate::configuration::{PiiPolicy, PiiSettings, TelemetrySettings};
+use crate::routes::error_chain_fmt;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceError;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use reqwest::header::HeaderMap;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use std::any::TypeId;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::task::JoinHandle;
-use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use tracing::field::{DisplayValue, Field, FieldSet, Value, ValueSet, Visit};
+use tracing::metadata::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::{set_global_default, Interest};
+use tracing::{Event, Metadata, Span, Subscriber};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::reload;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
 /// Compose multiple layers into a `tracing`'s subscriber.
@@ -14,28 +42,91 @@ use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Regis
 /// indeed quite complex.
 /// We need to explicitly call out that the returned subscriber is
 /// `Send` and `Sync`
+///
+/// Spans are also handed to `tracer`, if there is one, to be exported. Both
+/// only see PII fields once `pii` has been applied to them.
+///
+/// The returned [`LogFilter`] changes the filter while the subscriber runs.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+    tracer: Option<Tracer>,
+    pii: &PiiSettings,
+) -> (impl Subscriber + Send + Sync, LogFilter)
 where
     Sink: for<'a> MakeWriter<'a> + Sync + Send + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let default_directives = env_filter.to_string();
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
-    Registry::default()
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+    let subscriber = Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(PiiRedactionLayer::new(
+            pii,
+            JsonStorageLayer
+                .and_then(formatting_layer)
+                .and_then(otel_layer),
+        ));
+    let log_filter = LogFilter {
+        handle,
+        default_directives: Arc::new(default_directives),
+        pending_revert: Arc::default(),
+    };
+    (subscriber, log_filter)
+}
+
+/// Export spans over OTLP/HTTP to the collector in `settings`, if there is one.
+///
+/// Spans are exported in batches in the background. Dropping the provider
+/// flushes the spans that are still buffered.
+pub fn get_tracer_provider(
+    name: &str,
+    settings: &TelemetrySettings,
+) -> Result<Option<TracerProvider>, TraceError> {
+    let endpoint = match &settings.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint),
+    )
+    .build_span_exporter()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+        .with_config(
+            opentelemetry::sdk::trace::config().with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                name.to_owned(),
+            )])),
+        )
+        .build();
+    Ok(Some(provider))
 }
 /// Register a subscriber as global default to process span data.
 ///
 /// It should only be called once!
+///
+/// Trace context is read from and written to W3C `traceparent` headers.
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// Headers that continue the current span's trace in an outgoing request.
+pub fn trace_context_headers() -> HeaderMap {
+    let context = Span::current().context();
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
 }
 
 // helper for spawning a block with tracing
@@ -47,3 +138,574 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+/// Root span for every request: the HTTP properties captured by
+/// `DefaultRootSpanBuilder`, plus fields filled in by our own middleware.
+pub struct AppRootSpanBuilder;
+
+impl RootSpanBuilder for AppRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        tracing_actix_web::root_span!(
+            request,
+            user_id = tracing::field::Empty,
+            permission_denied = tracing::field::Empty
+        )
+    }
+
+    fn on_request_end<B>(span: Span, outcome: &Result<ServiceResponse<B>, actix_web::Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// The filter directives of a running subscriber, e.g. `info,sqlx=trace`.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default_directives: Arc<String>,
+    pending_revert: Arc<Mutex<Option<PendingRevert>>>,
+}
+
+struct PendingRevert {
+    at: DateTime<Utc>,
+    task: JoinHandle<()>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LogFilterError {
+    #[error("Invalid filter directives.")]
+    InvalidDirectives(#[from] ParseError),
+    #[error("A temporary filter cannot last longer than a day.")]
+    TtlTooLong,
+    #[error("The subscriber is gone.")]
+    SubscriberGone(#[from] reload::Error),
+}
+
+impl std::fmt::Debug for LogFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl LogFilter {
+    pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// The directives in effect.
+    pub fn directives(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    /// The directives the subscriber started with.
+    pub fn default_directives(&self) -> &str {
+        &self.default_directives
+    }
+
+    /// When a temporary filter goes back to the defaults.
+    pub fn reverts_at(&self) -> Option<DateTime<Utc>> {
+        self.pending_revert.lock().unwrap().as_ref().map(|r| r.at)
+    }
+
+    /// Replace the directives in effect. With a `ttl`, the defaults come back
+    /// once it has passed, so that a noisy filter set during an incident
+    /// cannot be forgotten.
+    ///
+    /// Must be called from within a Tokio runtime if there is a `ttl`.
+    pub fn set(&self, directives: &str, ttl: Option<Duration>) -> Result<(), LogFilterError> {
+        let filter = EnvFilter::try_new(directives)?;
+        let at = match ttl {
+            Some(ttl) if ttl > Self::MAX_TTL => return Err(LogFilterError::TtlTooLong),
+            Some(ttl) => Some(Utc::now() + chrono::Duration::from_std(ttl).unwrap()),
+            None => None,
+        };
+        let mut pending_revert = self.pending_revert.lock().unwrap();
+        self.handle.reload(filter)?;
+        if let Some(previous) = pending_revert.take() {
+            previous.task.abort();
+        }
+        if let (Some(ttl), Some(at)) = (ttl, at) {
+            let log_filter = self.clone();
+            let task = tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                tracing::info!("Reverting the temporary log filter");
+                if let Err(e) = log_filter.reset() {
+                    tracing::error!(error.cause_chain = ?e, "Failed to revert the log filter");
+                }
+            });
+            *pending_revert = Some(PendingRevert { at, task });
+        }
+        Ok(())
+    }
+
+    /// Go back to the default directives.
+    pub fn reset(&self) -> Result<(), LogFilterError> {
+        let mut pending_revert = self.pending_revert.lock().unwrap();
+        self.handle
+            .reload(EnvFilter::new(self.default_directives.as_str()))?;
+        // when called by the revert task this aborts the caller, which has
+        // nothing left to do
+        if let Some(pending) = pending_revert.take() {
+            pending.task.abort();
+        }
+        Ok(())
+    }
+}
+
+/// Fields that hold personal data. Record personal data under one of these
+/// names only, never in a message or under another name, so that the
+/// [`PiiPolicy`] applies to it.
+pub const PII_FIELDS: &[&str] = &[
+    "subscriber_email",
+    "subscriber_name",
+    "recipient",
+    "email",
+    "username",
+    "http.client_ip",
+];
+
+fn is_pii(field: &Field) -> bool {
+    PII_FIELDS.contains(&field.name())
+}
+
+/// Applies a [`PiiPolicy`] to the [`PII_FIELDS`] of spans and events, before
+/// the wrapped layer sees them.
+pub struct PiiRedactionLayer<L> {
+    inner: L,
+    redactor: Redactor,
+}
+
+struct Redactor {
+    policy: PiiPolicy,
+    salt: Secret<String>,
+}
+
+impl Redactor {
+    fn redact(&self, value: &str) -> Option<String> {
+        match self.policy {
+            PiiPolicy::Plain => Some(value.to_owned()),
+            PiiPolicy::Mask => Some(mask(value)),
+            PiiPolicy::Hash => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.expose_secret().as_bytes())
+                    .expect("HMAC can take a key of any size");
+                mac.update(value.as_bytes());
+                // enough to tell people apart, too short to be worth reversing
+                Some(HEXLOWER.encode(&mac.finalize().into_bytes()[..8]))
+            }
+            PiiPolicy::Drop => None,
+        }
+    }
+}
+
+fn mask(value: &str) -> String {
+    let (name, domain) = match value.split_once('@') {
+        Some((name, domain)) => (name, Some(domain)),
+        None => (value, None),
+    };
+    let first: String = name.chars().take(1).collect();
+    match domain {
+        Some(domain) => format!("{}***@{}", first, domain),
+        None => format!("{}***", first),
+    }
+}
+
+// a recorded value, kept so that it can be recorded again for the inner layer
+enum Captured {
+    I64(i64),
+    U64(u64),
+    Bool(bool),
+    F64(f64),
+    Text(DisplayValue<String>),
+}
+
+impl Captured {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            Captured::I64(v) => v,
+            Captured::U64(v) => v,
+            Captured::Bool(v) => v,
+            Captured::F64(v) => v,
+            Captured::Text(v) => v,
+        }
+    }
+}
+
+struct CaptureVisitor<'a> {
+    redactor: &'a Redactor,
+    values: Vec<(Field, Option<Captured>)>,
+}
+
+impl CaptureVisitor<'_> {
+    fn push(&mut self, field: &Field, value: Captured, text: impl FnOnce() -> String) {
+        let value = if is_pii(field) {
+            self.redactor
+                .redact(&text())
+                .map(|v| Captured::Text(tracing::field::display(v)))
+        } else {
+            Some(value)
+        };
+        self.values.push((field.clone(), value));
+    }
+}
+
+impl Visit for CaptureVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, Captured::I64(value), || value.to_string());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, Captured::U64(value), || value.to_string());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, Captured::Bool(value), || value.to_string());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, Captured::F64(value), || value.to_string());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = value.to_owned();
+        self.push(
+            field,
+            Captured::Text(tracing::field::display(value.clone())),
+            || value,
+        );
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let value = format!("{:?}", value);
+        self.push(
+            field,
+            Captured::Text(tracing::field::display(value.clone())),
+            || value,
+        );
+    }
+}
+
+impl<L> PiiRedactionLayer<L> {
+    pub fn new(settings: &PiiSettings, inner: L) -> Self {
+        Self {
+            inner,
+            redactor: Redactor {
+                policy: settings.policy,
+                salt: settings.salt.clone(),
+            },
+        }
+    }
+
+    /// The values recorded by `record`, redacted, if `metadata` has PII fields.
+    fn capture(
+        &self,
+        metadata: &Metadata<'_>,
+        record: impl FnOnce(&mut dyn Visit),
+    ) -> Option<Vec<(Field, Option<Captured>)>> {
+        if self.redactor.policy == PiiPolicy::Plain || !metadata.fields().iter().any(|f| is_pii(&f))
+        {
+            return None;
+        }
+        let mut visitor = CaptureVisitor {
+            redactor: &self.redactor,
+            values: Vec::new(),
+        };
+        record(&mut visitor);
+        Some(visitor.values)
+    }
+}
+
+// calls `f` with the captured values as a `ValueSet` of `fields`
+fn with_value_set<R>(
+    fields: &FieldSet,
+    values: &[(Field, Option<Captured>)],
+    f: impl FnOnce(&ValueSet<'_>) -> R,
+) -> R {
+    // a `ValueSet` is built from an array of at most 32 entries, the most
+    // fields a callsite can have; the unused entries have no value
+    let padding = fields.iter().next().expect("A field was recorded");
+    let mut entries: [(&Field, Option<&dyn Value>); 32] = [(&padding, None); 32];
+    for (entry, (field, value)) in entries.iter_mut().zip(values) {
+        *entry = (field, value.as_ref().map(Captured::as_value));
+    }
+    f(&fields.value_set(&entries))
+}
+
+impl<S, L> Layer<S> for PiiRedactionLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        let values = match self.capture(metadata, |v| attrs.record(v)) {
+            Some(values) => values,
+            None => return self.inner.on_new_span(attrs, id, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let attrs = match attrs.parent() {
+                Some(parent) => Attributes::child_of(parent.clone(), metadata, values),
+                None if attrs.is_root() => Attributes::new_root(metadata, values),
+                None => Attributes::new(metadata, values),
+            };
+            self.inner.on_new_span(&attrs, id, ctx)
+        })
+    }
+
+    fn on_record(&self, span: &Id, record: &Record<'_>, ctx: Context<'_, S>) {
+        let metadata = match ctx.metadata(span) {
+            Some(metadata) => metadata,
+            None => return self.inner.on_record(span, record, ctx),
+        };
+        let values = match self.capture(metadata, |v| record.record(v)) {
+            Some(values) if !values.is_empty() => values,
+            _ => return self.inner.on_record(span, record, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            self.inner.on_record(span, &Record::new(values), ctx)
+        })
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let values = match self.capture(metadata, |v| event.record(v)) {
+            Some(values) => values,
+            None => return self.inner.on_event(event, ctx),
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let event = if event.is_contextual() {
+                Event::new(metadata, values)
+            } else {
+                Event::new_child_of(event.parent().cloned(), metadata, values)
+            };
+            self.inner.on_event(&event, ctx)
+        })
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx);
+    }
+
+    // lets the OpenTelemetry layer be found through this one, to attach
+    // remote parents and read trace contexts
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_subscriber, mask, LogFilter, LogFilterError};
+    use crate::configuration::{PiiPolicy, PiiSettings};
+    use claim::{assert_err, assert_none, assert_ok, assert_some};
+    use secrecy::Secret;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tracing::Subscriber;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    // the log records written while subscribing someone, as JSON
+    fn log_subscription(policy: PiiPolicy, salt: &str) -> Vec<serde_json::Value> {
+        let buffer = Buffer::default();
+        let pii = PiiSettings {
+            policy,
+            salt: Secret::new(salt.into()),
+        };
+        let (subscriber, _) =
+            get_subscriber("test".into(), "info".into(), buffer.clone(), None, &pii);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "Adding a new subscriber",
+                subscriber_email = %"ursula@example.com",
+                subscriber_name = "Ursula",
+                username = tracing::field::Empty,
+                attempt = 1
+            );
+            let _guard = span.enter();
+            span.record("username", "ursula");
+            tracing::info!(recipient = %"ursula@example.com", "Sent a confirmation email");
+        });
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn find_event(records: &[serde_json::Value]) -> &serde_json::Value {
+        records
+            .iter()
+            .find(|r| {
+                r["msg"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with("Sent a confirmation email")
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn plain_leaves_pii_as_it_is() {
+        let records = log_subscription(PiiPolicy::Plain, "salt");
+        let event = find_event(&records);
+        assert_eq!(event["subscriber_email"], "ursula@example.com");
+        assert_eq!(event["subscriber_name"], "Ursula");
+        assert_eq!(event["username"], "ursula");
+        assert_eq!(event["recipient"], "ursula@example.com");
+    }
+
+    #[test]
+    fn mask_hides_all_but_the_first_character_and_the_domain() {
+        let records = log_subscription(PiiPolicy::Mask, "salt");
+        let event = find_event(&records);
+        assert_eq!(event["subscriber_email"], "u***@example.com");
+        assert_eq!(event["subscriber_name"], "U***");
+        assert_eq!(event["username"], "u***");
+        assert_eq!(event["recipient"], "u***@example.com");
+        assert_eq!(event["attempt"], 1);
+    }
+
+    #[test]
+    fn hash_gives_the_same_value_the_same_salted_hash() {
+        let records = log_subscription(PiiPolicy::Hash, "salt");
+        let event = find_event(&records);
+        let hash = event["subscriber_email"].as_str().unwrap();
+        assert_eq!(hash.len(), 16);
+        assert_eq!(event["recipient"], hash);
+        assert_ne!(event["subscriber_name"], hash);
+
+        let resalted = log_subscription(PiiPolicy::Hash, "another salt");
+        assert_ne!(find_event(&resalted)["subscriber_email"], hash);
+    }
+
+    #[test]
+    fn drop_leaves_pii_fields_out() {
+        let records = log_subscription(PiiPolicy::Drop, "salt");
+        for record in &records {
+            let record = record.to_string();
+            assert!(!record.contains("ursula"), "{}", record);
+            assert!(!record.contains("Ursula"), "{}", record);
+        }
+        let event = find_event(&records);
+        assert!(event.get("subscriber_email").is_none());
+        assert!(event.get("recipient").is_none());
+        assert_eq!(event["attempt"], 1);
+    }
+
+    #[test]
+    fn mask_keeps_the_domain_of_email_addresses_only() {
+        assert_eq!(mask("ursula@example.com"), "u***@example.com");
+        assert_eq!(mask("127.0.0.1"), "1***");
+        assert_eq!(mask(""), "***");
+    }
+
+    // the filter only works while its subscriber is alive
+    fn log_filter() -> (impl Subscriber, LogFilter) {
+        let pii = PiiSettings {
+            policy: PiiPolicy::Plain,
+            salt: Secret::new("salt".into()),
+        };
+        get_subscriber("test".into(), "info".into(), std::io::sink, None, &pii)
+    }
+
+    #[tokio::test]
+    async fn a_temporary_filter_reverts_after_its_ttl() {
+        let (_subscriber, log_filter) = log_filter();
+        let default = log_filter.default_directives().to_owned();
+
+        assert_ok!(log_filter.set("sqlx=trace", Some(Duration::from_millis(50))));
+        assert_eq!(log_filter.directives(), "sqlx=trace");
+        assert_some!(log_filter.reverts_at());
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(log_filter.directives(), default);
+        assert_none!(log_filter.reverts_at());
+    }
+
+    #[tokio::test]
+    async fn a_new_filter_cancels_the_pending_revert() {
+        let (_subscriber, log_filter) = log_filter();
+
+        assert_ok!(log_filter.set("sqlx=trace", Some(Duration::from_millis(50))));
+        assert_ok!(log_filter.set("debug", None));
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(log_filter.directives(), "debug");
+        assert_none!(log_filter.reverts_at());
+    }
+
+    #[test]
+    fn invalid_directives_leave_the_filter_unchanged() {
+        let (_subscriber, log_filter) = log_filter();
+        let before = log_filter.directives();
+
+        let result = log_filter.set("sqlx=loud", None);
+
+        assert!(matches!(result, Err(LogFilterError::InvalidDirectives(_))));
+        assert_eq!(log_filter.directives(), before);
+    }
+
+    #[test]
+    fn a_temporary_filter_cannot_outlive_a_day() {
+        let (_subscriber, log_filter) = log_filter();
+
+        assert_err!(log_filter.set("trace", Some(LogFilter::MAX_TTL * 2)));
+    }
+}
@@ -0,0 +1,53 @@
+use actix_web::cookie::Cookie;
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpRequest, HttpResponse};
+
+// Return an opaque 500 while preserving the error root's cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// Redirect to `location`, leaving a message to be shown on the next page.
+pub fn see_other_with_flash(location: &str, message: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .cookie(
+            Cookie::build("_flash", message.to_owned())
+                .path("/")
+                .finish(),
+        )
+        .finish()
+}
+
+/// Render the message left behind by `see_other_with_flash`, escaped for HTML.
+pub fn flash_message_html(request: &HttpRequest) -> String {
+    match request.cookie("_flash") {
+        None => "".into(),
+        Some(cookie) if cookie.value().is_empty() => "".into(),
+        Some(cookie) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(cookie.value())
+        ),
+    }
+}
+
+/// Clears the flash message once it has been displayed.
+pub fn clear_flash_cookie() -> Cookie<'static> {
+    let mut cookie = Cookie::build("_flash", "").path("/").finish();
+    cookie.make_removal();
+    cookie
+}
+
+/// Compare secrets without leaking, through timing, how much of them matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
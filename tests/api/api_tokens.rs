@@ -0,0 +1,178 @@
+use crate::utils::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+// Create a token through the admin UI, returning it in clear text
+async fn create_token(app: &TestApp, form: &[(&str, &str)]) -> String {
+    let response = app.post_api_tokens(&form).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    html_page
+        .split(r#"<code id="api-token">"#)
+        .nth(1)
+        .and_then(|s| s.split("</code>").next())
+        .expect("The page did not contain the new token")
+        .to_string()
+}
+
+#[tokio::test]
+async fn a_token_with_the_publish_scope_can_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = create_token(&app, &[("name", "CI"), ("scope", "newsletters:publish")]).await;
+
+    // Act
+    let response = app
+        .post_newsletters_with_token(newsletter_request_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_token_is_stored() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let token = create_token(&app, &[("name", "CI"), ("scope", "newsletters:publish")]).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash, token);
+    assert!(!saved.token_hash.contains(&token));
+}
+
+#[tokio::test]
+async fn a_token_without_the_publish_scope_is_forbidden() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = create_token(&app, &[("name", "CI"), ("scope", "subscribers:read")]).await;
+
+    // Act
+    let response = app
+        .post_newsletters_with_token(newsletter_request_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn a_revoked_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = create_token(&app, &[("name", "CI"), ("scope", "newsletters:publish")]).await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/api-tokens/{}/revoke", token_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let response = app
+        .post_newsletters_with_token(newsletter_request_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("revoked"));
+}
+
+#[tokio::test]
+async fn an_expired_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = create_token(
+        &app,
+        &[
+            ("name", "CI"),
+            ("scope", "newsletters:publish"),
+            ("expires_in_days", "1"),
+        ],
+    )
+    .await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_newsletters_with_token(newsletter_request_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_unknown_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters_with_token(newsletter_request_body(), "z2p_not-a-real-token")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_token_needs_at_least_one_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_api_tokens(&[("name", "CI")]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>Select at least one scope.</i></p>"));
+}
+
+#[tokio::test]
+async fn basic_auth_is_rejected_when_disabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.authentication.basic_auth_enabled = false).await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,189 @@
+use crate::utils::{assert_is_redirect_to, csrf_token_from, spawn_app, TestApp, TestUser};
+
+async fn get_audit_log(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn logins_are_recorded_in_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "not-the-password"
+    }))
+    .await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    let entries = sqlx::query!("SELECT actor_id, action, target_id, ip FROM audit_log ORDER BY id")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].action, "login_failed");
+    assert_eq!(entries[0].actor_id, None);
+    assert_eq!(
+        entries[0].target_id.as_deref(),
+        Some(app.test_user.username.as_str())
+    );
+    assert_eq!(entries[1].action, "login");
+    assert_eq!(entries[1].actor_id, Some(app.test_user.user_id));
+    assert_eq!(entries[1].ip.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn forwarding_headers_do_not_change_the_recorded_ip() {
+    // Arrange
+    let app = spawn_app().await;
+    let csrf_token = csrf_token_from(&app.api_client, &format!("{}/login", &app.address)).await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", "203.0.113.7")
+        .header("Forwarded", "for=203.0.113.7")
+        .form(&[
+            ("username", app.test_user.username.as_str()),
+            ("password", "not-the-password"),
+            ("csrf_token", &csrf_token),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let entry = sqlx::query!("SELECT action, ip FROM audit_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(entry.action, "login_failed");
+    assert_eq!(entry.ip.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn role_changes_are_recorded_with_the_old_and_new_role() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/users/{}/role", other_user.user_id),
+            &serde_json::json!({"role": "editor"}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let entry = sqlx::query!(
+        "SELECT actor_id, target_type, target_id, changes FROM audit_log WHERE action = 'user.role_change'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(entry.actor_id, Some(app.test_user.user_id));
+    assert_eq!(entry.target_type.as_deref(), Some("user"));
+    assert_eq!(entry.target_id, Some(other_user.user_id.to_string()));
+    assert_eq!(
+        entry.changes,
+        serde_json::json!({"role": {"old": "owner", "new": "editor"}})
+    );
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered_by_action() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_api_tokens(&[("name", "CI"), ("scope", "newsletters:publish")])
+        .await;
+
+    // Act
+    let html_page = get_audit_log(&app, "/admin/audit-log?action=api_token.create")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("<td>api_token.create</td>"));
+    assert!(html_page.contains("newsletters:publish"));
+    assert!(!html_page.contains("<td>login</td>"));
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = get_audit_log(
+        &app,
+        &format!(
+            "/admin/audit-log.csv?actor={}&action=login",
+            app.test_user.username
+        ),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "occurred_at,actor,action,target_type,target_id,ip,user_agent,changes"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(&format!(",{},login,", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn invalid_dates_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = get_audit_log(&app, "/admin/audit-log?from=yesterday").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_owners_can_see_the_audit_log() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET role = 'editor' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let page = get_audit_log(&app, "/admin/audit-log").await;
+    let export = get_audit_log(&app, "/admin/audit-log.csv").await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 403);
+    assert_eq!(export.status().as_u16(), 403);
+}
@@ -0,0 +1,264 @@
+use crate::utils::{assert_is_redirect_to, log_filter, spawn_app};
+use claim::{assert_err, assert_ok};
+use secrecy::Secret;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::cli::{
+    check_config, create_admin, list_subscribers, reset_password, send_test_email,
+};
+use zero2prod::configuration::{
+    get_configuration, EmailCircuitBreakerSettings, Environment, MailboxSettings, MailboxStorage,
+};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::EmailClient;
+use zero2prod::startup::{get_connection_pool, Application};
+
+fn password_hashing() -> zero2prod::configuration::PasswordHashingSettings {
+    get_configuration().unwrap().authentication.password_hashing
+}
+
+#[tokio::test]
+async fn create_admin_creates_an_owner_who_can_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_logout().await;
+
+    // Act
+    let user_id = create_admin(
+        &app.db_pool,
+        &password_hashing(),
+        "bootstrap",
+        Some("ops@example.com"),
+        Secret::new("a-long-enough-password".into()),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "bootstrap",
+            "password": "a-long-enough-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You are signed in as owner."));
+    let entry = sqlx::query!(
+        "SELECT actor_id, target_id, user_agent, changes FROM audit_log WHERE action = 'user.create'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(entry.actor_id, None);
+    assert_eq!(entry.target_id, Some(user_id.to_string()));
+    assert_eq!(entry.user_agent.as_deref(), Some("zero2prod-cli"));
+    assert_eq!(entry.changes["username"], "bootstrap");
+    assert_eq!(entry.changes["role"], "owner");
+}
+
+#[tokio::test]
+async fn create_admin_rejects_a_taken_username() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let result = create_admin(
+        &app.db_pool,
+        &password_hashing(),
+        &app.test_user.username,
+        None,
+        Secret::new("a-long-enough-password".into()),
+    )
+    .await;
+
+    // Assert
+    assert_err!(result);
+}
+
+#[tokio::test]
+async fn reset_password_replaces_the_password_and_ends_existing_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let user_id = reset_password(
+        &app.db_pool,
+        &password_hashing(),
+        &app.test_user.username,
+        Secret::new("a-brand-new-password".into()),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "a-brand-new-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert_eq!(user_id, app.test_user.user_id);
+    let entry = sqlx::query!(
+        "SELECT actor_id, target_id, user_agent FROM audit_log WHERE action = 'password.reset'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(entry.actor_id, None);
+    assert_eq!(entry.target_id, Some(user_id.to_string()));
+    assert_eq!(entry.user_agent.as_deref(), Some("zero2prod-cli"));
+}
+
+#[tokio::test]
+async fn reset_password_fails_for_an_unknown_user() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let result = reset_password(
+        &app.db_pool,
+        &password_hashing(),
+        "nobody",
+        Secret::new("a-brand-new-password".into()),
+    )
+    .await;
+
+    // Assert
+    assert_err!(result);
+}
+
+#[tokio::test]
+async fn list_subscribers_can_filter_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let mut output = Vec::new();
+
+    // Act
+    list_subscribers(&app.db_pool, Some("pending_confirmation"), &mut output)
+        .await
+        .unwrap();
+    let pending = String::from_utf8(output).unwrap();
+    let mut output = Vec::new();
+    list_subscribers(&app.db_pool, Some("confirmed"), &mut output)
+        .await
+        .unwrap();
+    let confirmed = String::from_utf8(output).unwrap();
+
+    // Assert
+    assert_eq!(pending.lines().count(), 2);
+    assert!(pending.contains("ursula_le_guin@gmail.com\tle guin\tpending_confirmation\t"));
+    assert_eq!(confirmed.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn send_test_email_goes_through_the_email_provider() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let email_client = EmailClient::new(
+        app.email_server.uri(),
+        SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_secs(1),
+    );
+    let recipient = SubscriberEmail::parse("ops@example.com".into()).unwrap();
+
+    // Act
+    let result = send_test_email(&email_client, &recipient).await;
+
+    // Assert
+    assert_ok!(result);
+}
+
+#[tokio::test]
+async fn invalid_email_limits_are_refused_when_building_the_email_client() {
+    for setting in ["max_in_flight", "rate_per_second", "failure_threshold"] {
+        // Arrange
+        let mut configuration = get_configuration().unwrap();
+        configuration.application.port = 0;
+        match setting {
+            "max_in_flight" => configuration.email_client.limits.max_in_flight = Some(0),
+            "rate_per_second" => configuration.email_client.limits.rate_per_second = Some(0.0),
+            _ => {
+                configuration.email_client.circuit_breaker = Some(EmailCircuitBreakerSettings {
+                    failure_threshold: 0,
+                    open_duration_milliseconds: 1000,
+                })
+            }
+        }
+        let pool = get_connection_pool(&configuration.database);
+
+        // Act
+        let client_error = configuration
+            .email_client(pool)
+            .expect_err("The email client was built.");
+        let build_error = Application::build(configuration, log_filter())
+            .await
+            .err()
+            .expect("The application was built.");
+
+        // Assert
+        assert!(client_error.contains(setting), "{}", client_error);
+        assert!(build_error.to_string().contains(setting), "{}", build_error);
+    }
+}
+
+#[tokio::test]
+async fn a_short_hmac_secret_is_refused_at_startup() {
+    // Arrange
+    let mut configuration = get_configuration().unwrap();
+    configuration.application.port = 0;
+    configuration.application.hmac_secret = Secret::new("too-short".into());
+
+    // Act
+    let error = Application::build(configuration, log_filter())
+        .await
+        .err()
+        .expect("The application was built.");
+
+    // Assert
+    assert!(error.to_string().contains("hmac_secret"), "{}", error);
+}
+
+#[tokio::test]
+async fn check_config_reports_every_invalid_setting() {
+    // Arrange
+    let mut configuration = get_configuration().unwrap();
+    configuration.application.hmac_secret = Secret::new("too-short".into());
+    configuration.email_client.sender_email = "not-an-email".into();
+    configuration.email_client.limits.rate_per_second = Some(0.0);
+    configuration.environment = Environment::Production;
+    configuration.email_client.mailbox = Some(MailboxSettings {
+        storage: MailboxStorage::Memory,
+    });
+    let mut output = Vec::new();
+
+    // Act
+    let result = check_config(&configuration, &mut output).await;
+
+    // Assert
+    assert_err!(result);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("error  application.hmac_secret"));
+    assert!(output.contains("error  email_client.sender_email"));
+    assert!(output.contains("error  email_client.limits"));
+    assert!(output.contains("error  email_client.mailbox"));
+    assert!(output.contains("ok     authentication.password_hashing"));
+}
@@ -0,0 +1,122 @@
+use crate::utils::{anonymous_client, csrf_token_from, spawn_app};
+
+#[tokio::test]
+async fn a_login_without_a_csrf_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Submit the login form without a token
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+
+    // Act - Part 2 - The reason is shown on the next page
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your form has expired or could not be verified."));
+
+    // Act - Part 3 - The user is not logged in
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn a_csrf_token_from_another_session_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let attacker_token =
+        csrf_token_from(&anonymous_client(), &format!("{}/login", &app.address)).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/settings", &app.address))
+        .form(&serde_json::json!({
+            "require_two_factor": "on",
+            "csrf_token": attacker_token
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let row = sqlx::query!("SELECT require_two_factor FROM admin_settings")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!row.require_two_factor);
+}
+
+#[tokio::test]
+async fn the_csrf_token_can_be_sent_as_a_header() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = csrf_token_from(&app.api_client, &format!("{}/login", &app.address)).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .header("X-CSRF-Token", token)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn the_csrf_token_changes_when_logging_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_url = format!("{}/login", &app.address);
+    let anonymous_token = csrf_token_from(&app.api_client, &login_url).await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    let token = csrf_token_from(&app.api_client, &login_url).await;
+    assert_ne!(token, anonymous_token);
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .header("X-CSRF-Token", anonymous_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_publishing_api_does_not_need_a_csrf_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
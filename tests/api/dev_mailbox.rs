@@ -0,0 +1,170 @@
+use crate::utils::{log_filter, spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::cli::send_test_email;
+use zero2prod::configuration::{get_configuration, Environment, MailboxSettings, MailboxStorage};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::startup::{get_connection_pool, Application};
+
+async fn spawn_app_with_mailbox(storage: MailboxStorage) -> TestApp {
+    let app = spawn_app_with(|c| c.email_client.mailbox = Some(MailboxSettings { storage })).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+async fn get_page(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+// the path of every email listed in the mailbox
+async fn listed_emails(app: &TestApp) -> Vec<String> {
+    let page = get_page(app, "/dev/mailbox").await.text().await.unwrap();
+    page.split(r#"href=""#)
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[tokio::test]
+async fn the_mailbox_is_not_served_unless_configured() {
+    let app = spawn_app().await;
+
+    let response = get_page(&app, "/dev/mailbox").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_confirmed_through_the_mailbox() {
+    // Arrange
+    let app = spawn_app_with_mailbox(MailboxStorage::Memory).await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 1 - List
+    let listing = get_page(&app, "/dev/mailbox").await.text().await.unwrap();
+    assert!(listing.contains("ursula_le_guin@gmail.com"));
+    assert!(listing.contains("Welcome!"));
+
+    // Act - Part 2 - Read
+    let emails = listed_emails(&app).await;
+    assert_eq!(emails.len(), 1);
+    let page = get_page(&app, &emails[0]).await.text().await.unwrap();
+    assert!(page.contains("<th>To</th><td>ursula_le_guin@gmail.com</td>"));
+    assert!(page.contains("Welcome to my newsletter!"));
+
+    // Act - Part 3 - Confirm
+    let link = linkify::LinkFinder::new()
+        .links(&page)
+        .map(|link| link.as_str())
+        .find(|link| link.contains("/subscriptions/confirm"))
+        .expect("No confirmation link.");
+    let mut link = reqwest::Url::parse(link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "confirmed");
+}
+
+#[tokio::test]
+async fn caught_emails_can_be_kept_in_postgres() {
+    // Arrange
+    let app = spawn_app_with_mailbox(MailboxStorage::Postgres).await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let caught = sqlx::query!("SELECT email_id, recipient, subject FROM caught_emails")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(caught.recipient, "ursula_le_guin@gmail.com");
+    assert_eq!(caught.subject, "Welcome!");
+    // recorded as if the provider had accepted it
+    let delivery = sqlx::query!("SELECT status, provider_message_id FROM email_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(
+        delivery.provider_message_id,
+        Some(caught.email_id.to_string())
+    );
+    assert_eq!(
+        listed_emails(&app).await,
+        [format!("/dev/mailbox/{}", caught.email_id)]
+    );
+}
+
+#[tokio::test]
+async fn an_unknown_email_returns_404() {
+    let app = spawn_app_with_mailbox(MailboxStorage::Memory).await;
+
+    let response = get_page(&app, &format!("/dev/mailbox/{}", uuid::Uuid::new_v4())).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_cli_test_email_is_caught_by_the_mailbox() {
+    // Arrange
+    let app = spawn_app_with_mailbox(MailboxStorage::Memory).await;
+    let mut configuration = get_configuration().unwrap();
+    configuration.email_client.base_url = app.email_server.uri();
+    configuration.email_client.mailbox = Some(MailboxSettings {
+        storage: MailboxStorage::Memory,
+    });
+    let email_client = configuration.email_client(app.db_pool.clone()).unwrap();
+    let recipient = SubscriberEmail::parse("ops@example.com".into()).unwrap();
+
+    // Act
+    let result = send_test_email(&email_client, &recipient).await;
+
+    // Assert
+    assert!(result.is_ok());
+    let caught = email_client.mailbox().unwrap().list().await.unwrap();
+    assert_eq!(caught.len(), 1);
+    assert_eq!(caught[0].recipient, "ops@example.com");
+}
+
+#[tokio::test]
+async fn the_mailbox_cannot_be_enabled_in_production() {
+    let mut configuration = get_configuration().unwrap();
+    configuration.application.port = 0;
+    configuration.environment = Environment::Production;
+    configuration.email_client.mailbox = Some(MailboxSettings {
+        storage: MailboxStorage::Memory,
+    });
+
+    let error = Application::build(configuration.clone(), log_filter())
+        .await
+        .err()
+        .expect("The application was built.");
+
+    assert!(error.to_string().contains("production"));
+    // nor for the CLI
+    let pool = get_connection_pool(&configuration.database);
+    let error = configuration
+        .email_client(pool)
+        .expect_err("The email client was built.");
+    assert!(error.contains("production"));
+}
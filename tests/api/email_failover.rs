@@ -0,0 +1,153 @@
+use crate::utils::{spawn_app_with, TestApp};
+use secrecy::Secret;
+use wiremock::matchers::{any, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::{EmailCircuitBreakerSettings, EmailProviderSettings};
+
+// the primary's circuit opens on its first failure, the secondary (if any)
+// answering at `secondary`
+async fn spawn_app(secondary: Option<&MockServer>) -> TestApp {
+    let secondary = secondary.map(|server| EmailProviderSettings {
+        base_url: server.uri(),
+        authorization_token: Secret::new("secondary-token".into()),
+    });
+    spawn_app_with(|c| {
+        c.email_client.circuit_breaker = Some(EmailCircuitBreakerSettings {
+            failure_threshold: 1,
+            open_duration_milliseconds: 60_000,
+        });
+        c.email_client.secondary = secondary;
+        c.metrics.bearer_token = Some(Secret::new("metrics-token".into()));
+    })
+    .await
+}
+
+async fn primary_fails(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn subscribe(app: &TestApp, name: &str) -> reqwest::Response {
+    app.post_subscriptions(format!("name={}&email={}%40example.com", name, name))
+        .await
+}
+
+async fn get_readiness(app: &TestApp) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn get_metrics(app: &TestApp) -> String {
+    reqwest::Client::new()
+        .get(format!("{}/metrics", &app.address))
+        .bearer_auth("metrics-token")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn subscribing_fails_fast_while_the_circuit_is_open() {
+    // Arrange
+    let app = spawn_app(None).await;
+    primary_fails(&app).await;
+    assert_eq!(subscribe(&app, "ursula").await.status().as_u16(), 500);
+    let calls = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    let response = subscribe(&app, "octavia").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let calls_after = app.email_server.received_requests().await.unwrap().len();
+    assert_eq!(calls_after, calls, "The open circuit was called.");
+}
+
+#[tokio::test]
+async fn the_secondary_provider_sends_while_the_primary_circuit_is_open() {
+    // Arrange
+    let secondary = MockServer::start().await;
+    let app = spawn_app(Some(&secondary)).await;
+    primary_fails(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Postmark-Server-Token", "secondary-token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&secondary)
+        .await;
+
+    // Act
+    // the first call to the primary fails, its retry goes to the secondary
+    let first = subscribe(&app, "ursula").await;
+    let second = subscribe(&app, "octavia").await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn readiness_reports_the_state_of_every_circuit() {
+    // Arrange
+    let secondary = MockServer::start().await;
+    let app = spawn_app(Some(&secondary)).await;
+    let body = get_readiness(&app).await;
+    assert_eq!(
+        body["checks"]["email"]["details"]["circuits"],
+        serde_json::json!({"primary": "closed", "secondary": "closed"})
+    );
+    primary_fails(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&secondary)
+        .await;
+
+    // Act
+    subscribe(&app, "ursula").await;
+    let body = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(body["status"], "degraded");
+    let email = &body["checks"]["email"];
+    assert_eq!(email["status"], "degraded");
+    assert!(email["error"].as_str().unwrap().contains("primary"));
+    assert_eq!(
+        email["details"]["circuits"],
+        serde_json::json!({"primary": "open", "secondary": "closed"})
+    );
+}
+
+#[tokio::test]
+async fn circuit_trips_and_failovers_are_counted() {
+    // Arrange
+    let secondary = MockServer::start().await;
+    let app = spawn_app(Some(&secondary)).await;
+    primary_fails(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&secondary)
+        .await;
+
+    // Act
+    subscribe(&app, "ursula").await;
+    let body = get_metrics(&app).await;
+
+    // Assert
+    assert!(body.contains(r#"email_circuit_open{provider="primary"} 1"#));
+    assert!(body.contains(r#"email_circuit_open{provider="secondary"} 0"#));
+    assert!(body.contains(r#"email_circuit_trips_total{provider="primary"} 1"#));
+    assert!(body.contains("email_failovers_total 1"));
+}
@@ -1,4 +1,9 @@
-use crate::utils::spawn_app;
+use crate::utils::{log_filter, spawn_app, TestApp};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::Application;
+
 #[tokio::test]
 async fn health_check_works() {
     let app = spawn_app().await;
@@ -8,7 +13,7 @@ async fn health_check_works() {
 
     let response = client
         // Use the returned application address
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -16,3 +21,97 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+async fn get_health(app: &TestApp, path: &str) -> (u16, serde_json::Value) {
+    let response = reqwest::Client::new()
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn liveness_is_reported_as_json() {
+    let app = spawn_app().await;
+
+    let (status, body) = get_health(&app, "/health/live").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn readiness_reports_every_component() {
+    let app = spawn_app().await;
+
+    let (status, body) = get_health(&app, "/health/ready").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "ok");
+    for component in ["database", "migrations", "workers", "email"] {
+        let check = &body["checks"][component];
+        assert_eq!(check["status"], "ok", "{} is not ok: {}", component, check);
+        assert!(check["duration_ms"].is_f64());
+    }
+    let workers = &body["checks"]["workers"]["details"];
+    assert_eq!(workers[0]["name"], "pool_sampler");
+    assert_eq!(workers[0]["stale"], false);
+}
+
+#[tokio::test]
+async fn an_unreachable_email_provider_degrades_readiness() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let (status, body) = get_health(&app, "/health/ready").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["checks"]["email"]["status"], "degraded");
+    assert!(body["checks"]["email"]["error"].is_string());
+}
+
+#[tokio::test]
+async fn pending_migrations_make_the_instance_unready() {
+    let app = spawn_app().await;
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, body) = get_health(&app, "/health/ready").await;
+
+    assert_eq!(status, 503);
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["migrations"]["status"], "unavailable");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+}
+
+#[tokio::test]
+async fn an_unreachable_database_makes_the_instance_unready() {
+    // the pool connects lazily, so the application starts anyway
+    let mut configuration = get_configuration().unwrap();
+    configuration.application.port = 0;
+    configuration.database.port = 1;
+    let application = Application::build(configuration, log_filter())
+        .await
+        .unwrap();
+    let address = format!("http://127.0.0.1:{}", application.port());
+    tokio::spawn(application.run_until_stopped());
+
+    let response = reqwest::get(format!("{}/health/ready", address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "unavailable");
+    assert!(body["checks"]["database"]["error"].is_string());
+}
@@ -0,0 +1,102 @@
+use crate::utils::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn get_log_filter_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/log-filter", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn only_owners_can_change_the_log_filter() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET role = 'editor' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let page = app
+        .api_client
+        .get(format!("{}/admin/log-filter", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let change = app
+        .post_form(
+            "/admin/log-filter",
+            &serde_json::json!({"directives": "trace", "ttl_minutes": ""}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 403);
+    assert_eq!(change.status().as_u16(), 403);
+}
+
+// the filter belongs to the test suite's subscriber, so this is the only test
+// that changes it
+#[tokio::test]
+async fn an_owner_can_change_the_log_filter_for_a_while_and_reset_it() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Change the filter for five minutes
+    let response = app
+        .post_form(
+            "/admin/log-filter",
+            &serde_json::json!({"directives": "info,sqlx=trace", "ttl_minutes": "5"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/log-filter");
+
+    // Assert - Part 1
+    let html_page = get_log_filter_html(&app).await;
+    assert!(html_page.contains("The log filter has been changed."));
+    assert!(html_page.contains("Active filter: <code>sqlx=trace,info</code>"));
+    assert!(html_page.contains("Reverts to <code>info</code> at"));
+    let changes = sqlx::query!(
+        "SELECT changes FROM audit_log WHERE action = 'log_filter.change' AND actor_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .changes;
+    assert_eq!(changes["directives"]["new"], "sqlx=trace,info");
+    assert_eq!(changes["ttl_minutes"], 5);
+
+    // Act - Part 2 - Invalid directives are rejected
+    app.post_form(
+        "/admin/log-filter",
+        &serde_json::json!({"directives": "sqlx=loud", "ttl_minutes": ""}),
+    )
+    .await;
+
+    // Assert - Part 2
+    let html_page = get_log_filter_html(&app).await;
+    assert!(html_page.contains("Invalid filter directives."));
+    assert!(html_page.contains("Active filter: <code>sqlx=trace,info</code>"));
+
+    // Act - Part 3 - Reset
+    let response = app
+        .post_form("/admin/log-filter/reset", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/log-filter");
+
+    // Assert - Part 3
+    let html_page = get_log_filter_html(&app).await;
+    assert!(html_page.contains("The log filter has been reset."));
+    assert!(html_page.contains("Active filter: <code>info</code>"));
+    assert!(!html_page.contains("Reverts to"));
+}
@@ -1,4 +1,4 @@
-use crate::utils::{assert_is_redirect_to, spawn_app};
+use crate::utils::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -26,3 +26,92 @@ async fn an_error_flash_message_is_set_on_failure() {
     let html_page = app.get_login_html().await;
     assert!(!html_page.contains(r#"<p><i>Authentication failed</i><p>"#));
 }
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+#[tokio::test]
+async fn an_outdated_password_hash_is_upgraded_on_login() {
+    // Arrange - the test user is stored with m=15000
+    let app = spawn_app_with(|c| c.authentication.password_hashing.memory_kib = 19456).await;
+
+    // Act - Part 1 - Login
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+
+    // Act - Part 2 - The password still works with the new hash
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_up_to_date_password_hash_is_left_alone() {
+    // Arrange
+    let app = spawn_app().await;
+    let password_hash = stored_password_hash(&app).await;
+
+    // Act
+    app.test_user.login(&app).await;
+
+    // Assert
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
+
+#[tokio::test]
+async fn a_failed_login_does_not_upgrade_the_password_hash() {
+    // Arrange
+    let app = spawn_app_with(|c| c.authentication.password_hashing.iterations = 3).await;
+    let password_hash = stored_password_hash(&app).await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "not-the-password"
+    }))
+    .await;
+
+    // Assert
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
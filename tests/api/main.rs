@@ -1,6 +1,23 @@
+mod admin_dashboard;
+mod api_tokens;
+mod audit_log;
+mod cli;
+mod csrf;
+mod dev_mailbox;
+mod email_failover;
 mod health_check;
+mod log_filter;
 mod login;
+mod metrics;
+mod migrations;
 mod newsletter;
+mod password_reset;
+mod roles;
 mod subscriptions;
 mod subscriptions_confirm;
+mod suppressions;
+mod telemetry;
+mod two_factor;
+mod users;
 mod utils;
+mod webhooks;
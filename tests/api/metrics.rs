@@ -0,0 +1,101 @@
+use crate::utils::{spawn_app, spawn_app_with, TestApp};
+use secrecy::Secret;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_metrics(address: &str, token: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new().get(format!("{}/metrics", address));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+async fn spawn_app_with_metrics_token() -> TestApp {
+    spawn_app_with(|c| c.metrics.bearer_token = Some(Secret::new("metrics-token".into()))).await
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_without_a_token_or_listener() {
+    let app = spawn_app().await;
+
+    let response = get_metrics(&app.address, None).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn metrics_on_the_main_listener_require_the_token() {
+    let app = spawn_app_with_metrics_token().await;
+
+    let missing = get_metrics(&app.address, None).await;
+    let wrong = get_metrics(&app.address, Some("not-the-token")).await;
+    let right = get_metrics(&app.address, Some("metrics-token")).await;
+
+    assert_eq!(missing.status().as_u16(), 401);
+    assert_eq!(wrong.status().as_u16(), 401);
+    assert_eq!(right.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_private_listener() {
+    let app = spawn_app_with(|c| c.metrics.listen_address = Some("127.0.0.1:0".into())).await;
+    let metrics_address = format!("http://127.0.0.1:{}", app.metrics_port.unwrap());
+
+    let private = get_metrics(&metrics_address, None).await;
+    let public = get_metrics(&app.address, None).await;
+
+    assert_eq!(private.status().as_u16(), 200);
+    assert!(private
+        .text()
+        .await
+        .unwrap()
+        .contains("db_pool_connections{state=\"idle\"}"));
+    assert_eq!(public.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_route_pattern() {
+    let app = spawn_app_with_metrics_token().await;
+    reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    let body = get_metrics(&app.address, Some("metrics-token"))
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(body.contains(
+        r#"http_requests_total{method="GET",route="/subscriptions/confirm",status="401"} 1"#
+    ));
+    assert!(body.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/subscriptions/confirm"} 1"#
+    ));
+}
+
+#[tokio::test]
+async fn subscriptions_and_email_sends_are_counted() {
+    let app = spawn_app_with_metrics_token().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let body = get_metrics(&app.address, Some("metrics-token"))
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(body.contains("subscriptions_total 1"));
+    assert!(body.contains("subscription_confirmations_total 0"));
+    assert!(body.contains(r#"email_sends_total{outcome="success",status="200"} 1"#));
+}
@@ -0,0 +1,107 @@
+use crate::utils::{create_database, log_filter, spawn_app};
+use claim::assert_ok;
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::migrations::{migration_status, run_migrations, MigrationError};
+use zero2prod::startup::{get_connection_pool, Application};
+
+// settings pointing at a new, empty database
+async fn empty_database_configuration() -> Settings {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.application.port = 0;
+    configuration.application.run_migrations_on_startup = true;
+    create_database(&configuration.database).await;
+    configuration
+}
+
+#[tokio::test]
+async fn the_application_migrates_an_empty_database_on_startup() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let pool = get_connection_pool(&configuration.database);
+    assert!(!migration_status(&pool).await.unwrap().pending.is_empty());
+
+    // Act
+    assert_ok!(Application::build(configuration, log_filter()).await);
+
+    // Assert
+    let status = migration_status(&pool).await.unwrap();
+    assert!(status.pending.is_empty());
+    assert!(status.applied_version.is_some());
+    assert_eq!(status.applied_version, status.latest_version);
+}
+
+#[tokio::test]
+async fn concurrent_migrations_wait_for_each_other() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let first = get_connection_pool(&configuration.database);
+    let second = get_connection_pool(&configuration.database);
+
+    // Act
+    let (a, b) = tokio::join!(run_migrations(&first), run_migrations(&second));
+
+    // Assert
+    assert_ok!(a);
+    assert_ok!(b);
+    assert!(migration_status(&first).await.unwrap().pending.is_empty());
+}
+
+#[tokio::test]
+async fn startup_fails_if_an_applied_migration_was_modified() {
+    // Arrange
+    let configuration = empty_database_configuration().await;
+    let pool = get_connection_pool(&configuration.database);
+    run_migrations(&pool).await.unwrap();
+    let version: i64 = sqlx::query_scalar("SELECT MIN(version) FROM _sqlx_migrations")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE _sqlx_migrations SET checksum = '\\x00' WHERE version = $1")
+        .bind(version)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Act
+    let result = Application::build(configuration, log_filter()).await;
+
+    // Assert
+    let error = match result {
+        Ok(_) => panic!("The application started with a modified migration."),
+        Err(e) => e,
+    };
+    assert!(matches!(
+        error.downcast_ref::<MigrationError>(),
+        Some(MigrationError::ChecksumMismatch(v)) if *v == version
+    ));
+    assert_eq!(
+        migration_status(&pool).await.unwrap().modified,
+        vec![version]
+    );
+}
+
+#[tokio::test]
+async fn diagnostics_show_the_applied_migration_version() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/diagnostics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let migrations = &body["migrations"];
+    assert!(migrations["applied_version"].is_i64());
+    assert_eq!(migrations["applied_version"], migrations["latest_version"]);
+    assert_eq!(migrations["pending"], serde_json::json!([]));
+    assert_eq!(migrations["modified"], serde_json::json!([]));
+}
@@ -37,9 +37,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(batch_response(&[0]))
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -61,6 +61,191 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // mock verifies that we have sent the email on drop
 }
 
+#[tokio::test]
+async fn newsletters_are_sent_in_a_single_batch() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in [
+        "ursula@example.com",
+        "octavia@example.com",
+        "iain@example.com",
+    ] {
+        insert_confirmed_subscriber(&app, email).await;
+    }
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(&[0, 0, 0]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let delivered = sqlx::query!(
+        "SELECT count(*) AS \"count!\" FROM email_deliveries
+        WHERE newsletter_issue_id = $1 AND status = 'sent' AND provider_message_id IS NOT NULL",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivered.count, 3);
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let mut recipients: Vec<_> = messages.iter().map(|m| m["To"].as_str().unwrap()).collect();
+    recipients.sort_unstable();
+    assert_eq!(
+        recipients,
+        [
+            "iain@example.com",
+            "octavia@example.com",
+            "ursula@example.com"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn partial_batch_failures_are_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in ["ursula@example.com", "octavia@example.com"] {
+        insert_confirmed_subscriber(&app, email).await;
+    }
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(&[0, 406]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 207);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["delivered"], 1);
+    let failed = body["failed"].as_array().unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0]["error_code"], 406);
+    assert_eq!(failed[0]["retryable"], false);
+    let recipient = sqlx::query!(
+        "SELECT s.id FROM email_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.status = 'failed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(failed[0]["subscriber_id"], recipient.id.to_string());
+    // the subscriber id is enough, the address stays out of the response
+    assert!(failed[0].get("email").is_none());
+    let deliveries = sqlx::query!(
+        "SELECT s.email, d.kind, d.status, d.newsletter_issue_id, d.provider_message_id, d.error_code
+        FROM email_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries.len(), 2);
+    assert!(deliveries
+        .iter()
+        .all(|d| d.kind == "newsletter_issue" && d.newsletter_issue_id.is_some()));
+    let sent = deliveries.iter().filter(|d| d.status == "sent").count();
+    assert_eq!(sent, 1);
+    let failed = deliveries.iter().find(|d| d.status == "failed").unwrap();
+    assert_eq!(failed.error_code, Some(406));
+    assert_eq!(failed.provider_message_id, None);
+    let event = sqlx::query!("SELECT changes FROM audit_log WHERE action = 'newsletter.publish'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.changes["recipients"], 1);
+    assert_eq!(event.changes["failures"], 1);
+}
+
+#[tokio::test]
+async fn attachments_are_forwarded_to_the_email_provider() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_confirmed_subscriber(&app, "ursula@example.com").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(batch_response(&[0]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // larger than the default limit on JSON payloads
+    let pdf = base64::encode(vec![b'%'; 100 * 1024]);
+    let mut body = newsletter_request_body();
+    body["attachments"] = serde_json::json!([
+        {"name": "issue.pdf", "content_type": "application/pdf", "content": pdf},
+        {"name": "logo.png", "content_type": "image/png", "content": "iVBORw==", "content_id": "logo"},
+    ]);
+
+    // Act
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let attachments = &messages[0]["Attachments"];
+    assert_eq!(attachments[0]["Name"], "issue.pdf");
+    assert_eq!(attachments[0]["Content"], pdf);
+    assert_eq!(attachments[1]["ContentID"], "cid:logo");
+}
+
+#[tokio::test]
+async fn invalid_attachments_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_confirmed_subscriber(&app, "ursula@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = [
+        (
+            serde_json::json!({"name": "setup.exe", "content_type": "application/x-msdownload", "content": "TVo="}),
+            "a forbidden content type",
+        ),
+        (
+            serde_json::json!({"name": "issue.pdf", "content_type": "application/pdf", "content": "not base64!"}),
+            "content that is not base64",
+        ),
+        (
+            serde_json::json!({"name": "issue.pdf", "content_type": "application/pdf", "content": "JQ==", "content_id": "issue"}),
+            "an inline PDF",
+        ),
+    ];
+
+    for (attachment, description) in test_cases {
+        let mut body = newsletter_request_body();
+        body["attachments"] = serde_json::json!([attachment]);
+
+        // Act
+        let response = app.post_newsletters(body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 for {}.",
+            description
+        );
+    }
+}
+
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     // Arrange
@@ -102,7 +287,7 @@ async fn requests_missing_authorization_are_rejected() {
     let app = spawn_app().await;
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&serde_json::json!({
            "title": "Newsletter title",
            "content": {
@@ -131,7 +316,7 @@ async fn non_existing_user_is_rejected() {
     let password = Uuid::new_v4().to_string();
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -162,7 +347,7 @@ async fn invalid_password_is_rejected() {
     assert_ne!(app.test_user.password, password);
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -208,11 +393,48 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
+}
+
+async fn insert_confirmed_subscriber(app: &TestApp, email: &str) {
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'reader', now(), 'confirmed')",
+        Uuid::new_v4(),
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+// Postmark's answer to a batch, one entry per message with the given error code
+fn batch_response(error_codes: &[i64]) -> ResponseTemplate {
+    let results: Vec<_> = error_codes
+        .iter()
+        .map(|&error_code| {
+            serde_json::json!({
+                "ErrorCode": error_code,
+                "Message": if error_code == 0 { "OK" } else { "Recipient is inactive." },
+                "MessageID": Uuid::new_v4(),
+            })
+        })
+        .collect();
+    ResponseTemplate::new(200).set_body_json(results)
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
 }
 
 async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
     reqwest::get(confirmation_link.html)
         .await
         .unwrap()
@@ -0,0 +1,220 @@
+use crate::utils::{anonymous_client, assert_is_redirect_to, csrf_token_from, spawn_app, TestApp};
+use std::time::Duration;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const NEW_PASSWORD: &str = "a-brand-new-password";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'locked.out@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn post_password_reset(app: &TestApp, email: &str) -> reqwest::Response {
+    app.post_form("/password-reset", &serde_json::json!({ "email": email }))
+        .await
+}
+
+async fn post_new_password(app: &TestApp, token: &str, password: &str) -> reqwest::Response {
+    post_new_password_with_check(app, token, password, password).await
+}
+
+// the reset link is opened in a browser that is not logged in
+async fn post_new_password_with_check(
+    app: &TestApp,
+    token: &str,
+    password: &str,
+    password_check: &str,
+) -> reqwest::Response {
+    let client = anonymous_client();
+    let csrf_token = csrf_token_from(&client, &format!("{}/password-reset", &app.address)).await;
+    client
+        .post(format!("{}/password-reset/confirm", &app.address))
+        .form(&serde_json::json!({
+            "token": token,
+            "password": password,
+            "password_check": password_check,
+            "csrf_token": csrf_token
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+// reset links are sent in the background, after the response
+async fn wait_for_emails(app: &TestApp, count: usize) -> Vec<wiremock::Request> {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Expected {} emails to be sent.", count);
+}
+
+// request a reset for the test user and return the token from the email
+async fn request_reset_token(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    set_test_user_email(app).await;
+    post_password_reset(app, "locked.out@example.com").await;
+    let email_request = &wait_for_emails(app, 1).await[0];
+    let link = app.get_confirmation_links(email_request).html;
+    link.query_pairs()
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_response_does_not_reveal_whether_the_user_exists() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    set_test_user_email(&app).await;
+
+    // Act
+    let known = post_password_reset(&app, "locked.out@example.com").await;
+    let unknown = post_password_reset(&app, "nobody@example.com").await;
+
+    // Assert
+    assert_eq!(known.status(), unknown.status());
+    assert_eq!(
+        known.headers().get("Location"),
+        unknown.headers().get("Location")
+    );
+    let flash = |r: &reqwest::Response| {
+        r.cookies()
+            .find(|c| c.name() == "_flash")
+            .map(|c| c.value().to_string())
+    };
+    assert_eq!(flash(&known), flash(&unknown));
+    // only the known user gets an email
+    wait_for_emails(&app, 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn a_user_can_reset_their_password_with_the_emailed_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+
+    // Act - Part 1 - Open the link
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/password-reset/confirm?token={}",
+            &app.address, token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Choose a new password
+    let response = post_new_password(&app, &token, NEW_PASSWORD).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert - the old password no longer works
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert - the new one does
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": NEW_PASSWORD
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+    post_new_password(&app, &token, NEW_PASSWORD).await;
+
+    // Act
+    let response = post_new_password(&app, &token, "yet-another-password").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password-reset");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": NEW_PASSWORD
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = post_new_password(&app, &token, NEW_PASSWORD).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password-reset");
+}
+
+#[tokio::test]
+async fn mismatched_passwords_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+
+    // Act
+    let response =
+        post_new_password_with_check(&app, &token, NEW_PASSWORD, "something-else-entirely").await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/password-reset/confirm?token={}", token),
+    );
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_every_session() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+    let token = request_reset_token(&app).await;
+
+    // Act
+    post_new_password(&app, &token, NEW_PASSWORD).await;
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
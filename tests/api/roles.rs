@@ -0,0 +1,195 @@
+use crate::utils::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn set_test_user_role(app: &TestApp, role: &str) {
+    sqlx::query!(
+        "UPDATE users SET role = $1 WHERE user_id = $2",
+        role,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to update the test user's role.");
+}
+
+async fn get_admin_page(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn only_owners_can_access_settings() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for (role, expected_status) in [
+        ("owner", 200),
+        ("editor", 403),
+        ("analyst", 403),
+        ("support", 403),
+    ] {
+        // Act
+        set_test_user_role(&app, role).await;
+        let response = get_admin_page(&app, "/admin/settings").await;
+
+        // Assert
+        assert_eq!(
+            expected_status,
+            response.status().as_u16(),
+            "Unexpected status for the {} role.",
+            role
+        );
+    }
+}
+
+#[tokio::test]
+async fn analysts_can_view_stats_but_not_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_role(&app, "analyst").await;
+    app.test_user.login(&app).await;
+
+    // Act & Assert
+    assert_eq!(get_admin_page(&app, "/admin/stats").await.status(), 200);
+    assert_eq!(
+        get_admin_page(&app, "/admin/subscribers").await.status(),
+        403
+    );
+}
+
+#[tokio::test]
+async fn the_dashboard_only_links_to_permitted_pages() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_role(&app, "support").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"href="/admin/subscribers""#));
+    assert!(!html_page.contains(r#"href="/admin/settings""#));
+    assert!(!html_page.contains(r#"href="/admin/stats""#));
+}
+
+#[tokio::test]
+async fn support_can_unsubscribe_a_subscriber_but_editors_cannot() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'ursula_le_guin@gmail.com', 'le guin', now(), 'confirmed')",
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    let unsubscribe_path = format!("/admin/subscribers/{}/unsubscribe", subscriber_id);
+
+    // Act - Part 1 - Editor
+    set_test_user_role(&app, "editor").await;
+    let response = app
+        .post_form(&unsubscribe_path, &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    // Act - Part 2 - Support
+    set_test_user_role(&app, "support").await;
+    let response = app
+        .post_form(&unsubscribe_path, &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn analysts_cannot_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_role(&app, "analyst").await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_role(&app, "editor").await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn analysts_cannot_create_publishing_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    set_test_user_role(&app, "analyst").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_api_tokens(&[("name", "CI"), ("scope", "newsletters:publish")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("Your role does not allow creating tokens"));
+}
+
+#[tokio::test]
+async fn a_token_loses_its_rights_when_the_owner_is_demoted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_api_tokens(&[("name", "CI"), ("scope", "newsletters:publish")])
+        .await;
+    let html_page = response.text().await.unwrap();
+    let token = html_page
+        .split(r#"<code id="api-token">"#)
+        .nth(1)
+        .and_then(|s| s.split("</code>").next())
+        .unwrap()
+        .to_string();
+
+    // Act
+    set_test_user_role(&app, "analyst").await;
+    let response = app
+        .post_newsletters_with_token(newsletter_request_body(), &token)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
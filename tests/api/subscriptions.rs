@@ -126,7 +126,7 @@ async fn subscribe_sends_a_confirmation_email_with_link() {
 
     //assert
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
@@ -153,9 +153,7 @@ async fn single_user_multi_subscription_sends_multiple_emails() {
     let email_requests = &app.email_server.received_requests().await.unwrap();
     let confirmation_links: Vec<ConfirmationLinks> = email_requests
         .iter()
-        .map(|req| {
-            return app.get_confirmation_links(req);
-        })
+        .map(|req| app.get_confirmation_links(req))
         .collect();
 
     // check that more than one request was received
@@ -189,3 +187,72 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     //assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn the_confirmation_email_is_recorded_as_a_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "ursula_le_guin@gmail.com",
+            "SubmittedAt": "2023-02-21T10:00:00.0000000Z",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let delivery = sqlx::query!(
+        "SELECT d.kind, d.status, d.provider_message_id, d.submitted_at
+        FROM email_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE s.email = 'ursula_le_guin@gmail.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.kind, "confirmation");
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+    assert!(delivery.submitted_at.is_some());
+}
+
+#[tokio::test]
+async fn a_failed_confirmation_email_is_recorded_with_its_error() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        })))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let delivery =
+        sqlx::query!("SELECT status, provider_message_id, error_code, error FROM email_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(delivery.status, "failed");
+    assert_eq!(delivery.provider_message_id, None);
+    assert_eq!(delivery.error_code, Some(406));
+    assert!(delivery.error.unwrap().contains("inactive"));
+}
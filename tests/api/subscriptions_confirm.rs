@@ -30,7 +30,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
     let response = reqwest::get(confirmation_links.html).await.unwrap();
@@ -53,7 +53,7 @@ async fn clicking_the_confirmation_link_confirms_a_subscriber() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // act
     reqwest::get(confirmation_links.html)
@@ -88,7 +88,7 @@ async fn confirm_fails_if_there_is_a_fatal_database_error() {
     // insert the user
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // sabatoge the database
     sqlx::query!("ALTER TABLE subscriptions_tokens DROP COLUMN subscription_token;")
@@ -0,0 +1,382 @@
+use crate::utils::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::{EmailClient, EmailMessage};
+
+async fn get_suppressions(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/suppressions", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_suppression(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/suppressions", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn delete_suppression(app: &TestApp, suppression_id: &str) -> reqwest::Response {
+    app.api_client
+        .delete(format!("{}/suppressions/{}", &app.address, suppression_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn suppress(app: &TestApp, email: &str) -> String {
+    let response = post_suppression(
+        app,
+        serde_json::json!({"email": email, "reason": "erasure_request"}),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["suppression_id"].as_str().unwrap().to_string()
+}
+
+async fn insert_confirmed_subscriber(app: &TestApp, email: &str) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'reader', now(), 'confirmed')",
+        subscriber_id,
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+#[tokio::test]
+async fn the_suppression_api_requires_credentials() {
+    let app = spawn_app().await;
+
+    for request in [
+        app.api_client.get(format!("{}/suppressions", &app.address)),
+        app.api_client
+            .post(format!("{}/suppressions", &app.address))
+            .json(&serde_json::json!({"email": "ursula@example.com", "reason": "manual"})),
+        app.api_client
+            .delete(format!("{}/suppressions/{}", &app.address, Uuid::new_v4())),
+    ] {
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401);
+    }
+}
+
+#[tokio::test]
+async fn analysts_cannot_manage_suppressions() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET role = 'analyst' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = post_suppression(
+        &app,
+        serde_json::json!({"email": "ursula@example.com", "reason": "manual"}),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn suppressions_can_be_added_listed_and_removed() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Add
+    let response = post_suppression(
+        &app,
+        serde_json::json!({
+            "email": "ursula@example.com",
+            "reason": "erasure_request",
+            "note": "Asked by email on the 3rd"
+        }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let added: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(added["email"], "ursula@example.com");
+    assert_eq!(added["reason"], "erasure_request");
+    assert_eq!(added["source"], "admin");
+    assert_eq!(added["note"], "Asked by email on the 3rd");
+    assert_eq!(added["created_by"], app.test_user.user_id.to_string());
+
+    // Act - Part 2 - List
+    let listed: serde_json::Value = get_suppressions(&app).await.json().await.unwrap();
+    assert_eq!(listed["suppressions"], serde_json::json!([added]));
+
+    // Act - Part 3 - Remove
+    let suppression_id = added["suppression_id"].as_str().unwrap();
+    let response = delete_suppression(&app, suppression_id).await;
+    assert_eq!(response.status().as_u16(), 204);
+    let listed: serde_json::Value = get_suppressions(&app).await.json().await.unwrap();
+    assert_eq!(listed["suppressions"], serde_json::json!([]));
+
+    // Assert
+    let events = sqlx::query!(
+        "SELECT action, actor_id, target_id, changes FROM audit_log
+        WHERE target_type = 'suppression' ORDER BY id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let actions: Vec<_> = events.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, ["suppression.add", "suppression.remove"]);
+    for event in &events {
+        assert_eq!(event.actor_id, Some(app.test_user.user_id));
+        assert_eq!(event.target_id.as_deref(), Some(suppression_id));
+        assert_eq!(event.changes["reason"], "erasure_request");
+        assert_eq!(event.changes["note"], "Asked by email on the 3rd");
+    }
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({"email": "not-an-email", "reason": "manual"}),
+            "an invalid email",
+        ),
+        (
+            serde_json::json!({"email": "ursula@example.com", "reason": "bored"}),
+            "an unknown reason",
+        ),
+        (
+            serde_json::json!({"email": "ursula@example.com"}),
+            "a missing reason",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = post_suppression(&app, body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn an_address_is_suppressed_once_whatever_its_case() {
+    let app = spawn_app().await;
+    suppress(&app, "ursula@example.com").await;
+
+    let response = post_suppression(
+        &app,
+        serde_json::json!({"email": "Ursula@Example.com", "reason": "manual"}),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn removing_an_unknown_suppression_returns_404() {
+    let app = spawn_app().await;
+
+    let response = delete_suppression(&app, &Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+// the client the application and the CLI send through, calling the app's
+// email server
+fn email_client(app: &TestApp) -> EmailClient {
+    let mut configuration = get_configuration().unwrap();
+    configuration.email_client.base_url = app.email_server.uri();
+    configuration.email_client.mailbox = None;
+    configuration.email_client(app.db_pool.clone()).unwrap()
+}
+
+fn address(email: &str) -> SubscriberEmail {
+    SubscriberEmail::parse(email.into()).unwrap()
+}
+
+#[tokio::test]
+async fn suppressed_copies_are_left_out_of_every_message() {
+    // Arrange
+    let app = spawn_app().await;
+    suppress(&app, "cc@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let email_client = email_client(&app);
+    let message = EmailMessage::new("Subject", "<p>Body</p>", "Body")
+        .cc(address("cc@example.com"))
+        .bcc(address("bcc@example.com"));
+    let recipients = [address("ursula@example.com")];
+
+    // Act
+    let sent = email_client.send_message(&recipients[0], &message).await;
+    email_client.send_batch(&recipients, &message).await;
+
+    // Assert
+    assert!(sent.is_ok());
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let single: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let batch: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+    for body in [&single, &batch[0]] {
+        assert_eq!(body["To"], "ursula@example.com");
+        assert_eq!(body.get("Cc"), None);
+        assert_eq!(body["Bcc"], "bcc@example.com");
+    }
+}
+
+#[tokio::test]
+async fn the_cli_does_not_send_test_emails_to_suppressed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    suppress(&app, "ops@example.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let result =
+        zero2prod::cli::send_test_email(&email_client(&app), &address("Ops@example.com")).await;
+
+    // Assert
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    // the response does not tell the address is suppressed
+    assert_eq!(response.status().as_u16(), 200);
+    let delivery = sqlx::query!("SELECT status FROM email_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "suppressed");
+}
+
+#[tokio::test]
+async fn newsletters_are_not_sent_to_suppressed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_confirmed_subscriber(&app, "ursula@example.com").await;
+    insert_confirmed_subscriber(&app, "octavia@example.com").await;
+    suppress(&app, "octavia@example.com").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4()}
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let recipients: Vec<_> = messages.iter().map(|m| m["To"].as_str().unwrap()).collect();
+    assert_eq!(recipients, ["ursula@example.com"]);
+    let statuses = sqlx::query!(
+        "SELECT s.email, d.status FROM email_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id ORDER BY s.email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let statuses: Vec<_> = statuses
+        .iter()
+        .map(|r| (r.email.as_str(), r.status.as_str()))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("octavia@example.com", "suppressed"),
+            ("ursula@example.com", "sent")
+        ]
+    );
+    let event = sqlx::query!("SELECT changes FROM audit_log WHERE action = 'newsletter.publish'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.changes["recipients"], 1);
+    assert_eq!(event.changes["suppressed"], 1);
+}
+
+#[tokio::test]
+async fn unsubscribing_a_subscriber_suppresses_their_address() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_confirmed_subscriber(&app, "ursula@example.com").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/subscribers/{}/unsubscribe", subscriber_id),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let suppression = sqlx::query!("SELECT email, reason, source, created_by FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.email, "ursula@example.com");
+    assert_eq!(suppression.reason, "unsubscribed");
+    assert_eq!(suppression.source, "admin");
+    assert_eq!(suppression.created_by, Some(app.test_user.user_id));
+}
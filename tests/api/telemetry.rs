@@ -0,0 +1,136 @@
+use crate::utils::spawn_app;
+use opentelemetry::trace::TracerProvider;
+use secrecy::Secret;
+use tracing::instrument::WithSubscriber;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::EmailClient;
+use zero2prod::telemetry::{get_subscriber, get_tracer_provider};
+
+const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+
+#[tokio::test]
+async fn the_trace_of_an_incoming_request_continues_in_outgoing_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header(
+            "traceparent",
+            format!("00-{}-b7ad6b7169203331-01", TRACE_ID),
+        )
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request
+        .headers
+        .get(&"traceparent".into())
+        .expect("No traceparent on the email request")
+        .as_str();
+    assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+    // the parent is a span of ours, not the caller's
+    assert!(!traceparent.contains("b7ad6b7169203331"));
+}
+
+#[tokio::test]
+async fn an_outgoing_email_starts_a_trace_if_there_is_none() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request
+        .headers
+        .get(&"traceparent".into())
+        .expect("No traceparent on the email request")
+        .as_str();
+    assert!(traceparent.starts_with("00-"));
+    assert!(!traceparent.contains(TRACE_ID));
+}
+
+// the batch exporter needs a worker thread while the test thread waits for
+// the final flush
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_to_the_configured_collector() {
+    // Arrange
+    let app = spawn_app().await;
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&collector)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let mut settings = get_configuration().unwrap().telemetry;
+    settings.otlp_endpoint = Some(format!("{}/v1/traces", collector.uri()));
+    let tracer_provider = get_tracer_provider("zero2prod-under-test", &settings)
+        .unwrap()
+        .expect("No tracer provider for a configured endpoint");
+    let (subscriber, _) = get_subscriber(
+        "test".into(),
+        "info".into(),
+        std::io::sink,
+        Some(tracer_provider.tracer("test")),
+        &settings.pii,
+    );
+    let email_client = EmailClient::new(
+        app.email_server.uri(),
+        SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        std::time::Duration::from_secs(1),
+    );
+    let recipient = SubscriberEmail::parse("ops@example.com".into()).unwrap();
+
+    // Act
+    email_client
+        .send_email(&recipient, "Subject", "<p>Body</p>", "Body")
+        .with_subscriber(subscriber)
+        .await
+        .unwrap();
+    drop(tracer_provider);
+
+    // Assert
+    let exports = collector.received_requests().await.unwrap();
+    assert_eq!(exports.len(), 1);
+    let body = &exports[0].body;
+    let contains = |needle: &[u8]| body.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"zero2prod-under-test"));
+    assert!(contains(b"Sending an email"));
+}
+
+#[test]
+fn nothing_is_exported_without_an_endpoint() {
+    let mut settings = get_configuration().unwrap().telemetry;
+    settings.otlp_endpoint = None;
+    assert!(get_tracer_provider("zero2prod", &settings)
+        .unwrap()
+        .is_none());
+}
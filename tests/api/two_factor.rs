@@ -0,0 +1,364 @@
+use crate::utils::{assert_is_redirect_to, spawn_app, TestApp};
+use zero2prod::authentication::totp::{self, TotpSecret};
+
+// Enroll the logged in test user, returning their secret and recovery codes
+async fn enroll_test_user(app: &TestApp) -> (TotpSecret, Vec<String>) {
+    let html_page = app.get_two_factor_html().await;
+    let secret = extract_between(&html_page, r#"<code id="totp-secret">"#, "</code>")
+        .pop()
+        .expect("The enrollment page did not contain a secret");
+    let secret = TotpSecret::parse(secret).unwrap();
+
+    let code = secret.code_at(totp::step_at(totp::now()));
+    let response = app.post_enable_two_factor(&code).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let recovery_codes = extract_between(
+        &response.text().await.unwrap(),
+        r#"<code class="recovery-code">"#,
+        "</code>",
+    );
+    (secret, recovery_codes)
+}
+
+// a code from the next time step: the current one was spent during enrollment
+fn next_code(secret: &TotpSecret) -> String {
+    secret.code_at(totp::step_at(totp::now()) + 1)
+}
+
+fn extract_between(haystack: &str, start: &str, end: &str) -> Vec<String> {
+    haystack
+        .split(start)
+        .skip(1)
+        .filter_map(|s| s.split(end).next())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn enrolling_returns_ten_recovery_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let (_, recovery_codes) = enroll_test_user(&app).await;
+
+    // Assert
+    assert_eq!(recovery_codes.len(), 10);
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+}
+
+#[tokio::test]
+async fn an_invalid_code_does_not_enable_two_factor() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.get_two_factor_html().await;
+
+    // Act
+    let response = app.post_enable_two_factor("000000x").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The authentication code is invalid.</i></p>"));
+    assert!(html_page.contains(r#"<code id="totp-secret">"#));
+}
+
+#[tokio::test]
+async fn enrolling_again_is_rejected_once_two_factor_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+    let other_secret = TotpSecret::generate();
+
+    // Act
+    let response = app
+        .post_enable_two_factor(&other_secret.code_at(totp::step_at(totp::now())))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication is already enabled.</i></p>"));
+    // the enrolled secret is still the one asked for at login
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+    let response = app.post_login_two_factor(&next_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn enrolled_users_must_provide_a_code_after_their_password() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - Password
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+
+    // Act - Part 2 - The password alone does not grant access
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Second factor
+    let response = app.post_login_two_factor(&next_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn an_invalid_code_is_rejected_at_login() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enroll_test_user(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_login_two_factor("123456").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let html_page = app.get_login_two_factor_html().await;
+    assert!(html_page.contains("<p><i>The authentication code is invalid.</i></p>"));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_login_is_abandoned_after_too_many_invalid_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    for _ in 0..5 {
+        app.post_login_two_factor("123456").await;
+    }
+    let response = app.post_login_two_factor(&next_code(&secret)).await;
+
+    // Assert - a valid code no longer helps, the password is needed again
+    assert_is_redirect_to(&response, "/login");
+    // and the second factor stays locked for a while
+    app.test_user.login(&app).await;
+    let response = app.post_login_two_factor(&next_code(&secret)).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+// the session cookie `response` sets, as a `Cookie` header value
+fn session_cookie(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find(|cookie| cookie.starts_with("id="))
+        .and_then(|cookie| cookie.split(';').next())
+        .expect("No session cookie was set.")
+        .to_string()
+}
+
+#[tokio::test]
+async fn replaying_the_session_cookie_does_not_reset_the_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+    app.post_logout().await;
+    // a client that only sends the cookies it is told to
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let page = |path: &str, cookie: &str| {
+        client
+            .get(format!("{}{}", &app.address, path))
+            .header("Cookie", cookie)
+            .send()
+    };
+    let login_page = page("/login", "").await.unwrap();
+    let cookie = session_cookie(&login_page);
+    let csrf_token = extract_between(
+        &login_page.text().await.unwrap(),
+        r#"name="csrf_token" value=""#,
+        "\"",
+    )
+    .remove(0);
+    let response = client
+        .post(format!("{}/login", &app.address))
+        .header("Cookie", &cookie)
+        .form(&[
+            ("username", app.test_user.username.as_str()),
+            ("password", app.test_user.password.as_str()),
+            ("csrf_token", &csrf_token),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let two_factor_page = page("/login/two-factor", &session_cookie(&response))
+        .await
+        .unwrap();
+    // the cookie right after the password step
+    let cookie = session_cookie(&two_factor_page);
+    let csrf_token = extract_between(
+        &two_factor_page.text().await.unwrap(),
+        r#"name="csrf_token" value=""#,
+        "\"",
+    )
+    .remove(0);
+    let post_code = |code: String| {
+        client
+            .post(format!("{}/login/two-factor", &app.address))
+            .header("Cookie", &cookie)
+            .form(&[("code", code), ("csrf_token", csrf_token.clone())])
+            .send()
+    };
+
+    // Act
+    for _ in 0..5 {
+        let response = post_code("123456".into()).await.unwrap();
+        assert_eq!(response.status().as_u16(), 303);
+    }
+    let response = post_code(next_code(&secret)).await.unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_recovery_code_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (_, recovery_codes) = enroll_test_user(&app).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - First use
+    app.test_user.login(&app).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act - Part 2 - Second use
+    app.test_user.login(&app).await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn users_must_enroll_when_two_factor_is_required() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_admin_settings(&serde_json::json!({ "require_two_factor": "on" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/settings");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is required."));
+}
+
+#[tokio::test]
+async fn two_factor_cannot_be_disabled_while_required() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+    app.post_admin_settings(&serde_json::json!({ "require_two_factor": "on" }))
+        .await;
+
+    // Act
+    let response = app.post_disable_two_factor(&next_code(&secret)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+}
+
+#[tokio::test]
+async fn two_factor_cannot_be_disabled_after_too_many_invalid_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+
+    // Act
+    for _ in 0..5 {
+        app.post_disable_two_factor("123456").await;
+    }
+    let response = app.post_disable_two_factor(&next_code(&secret)).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Too many invalid authentication codes."));
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+}
+
+#[tokio::test]
+async fn recovery_codes_cannot_be_regenerated_after_too_many_invalid_codes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll_test_user(&app).await;
+
+    // Act
+    for _ in 0..5 {
+        app.post_regenerate_recovery_codes("123456").await;
+    }
+    let response = app
+        .post_regenerate_recovery_codes(&next_code(&secret))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("Too many invalid authentication codes."));
+}
+
+#[tokio::test]
+async fn basic_auth_is_rejected_for_users_with_two_factor_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enroll_test_user(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,309 @@
+use crate::utils::{
+    anonymous_client, assert_is_redirect_to, csrf_token_from, spawn_app, TestApp, TestUser,
+};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// invite someone as an editor, returning the link from the invitation email
+async fn invite_editor(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(app).await;
+    let response = app
+        .post_invitation(&serde_json::json!({
+            "email": "new.editor@example.com",
+            "role": "editor"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+#[tokio::test]
+async fn an_invitee_can_create_an_account_and_log_in_with_the_invited_role() {
+    // Arrange
+    let app = spawn_app().await;
+    let invitation_link = invite_editor(&app).await;
+
+    // Act - Part 1 - Open the invitation
+    let response = reqwest::get(invitation_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("new.editor@example.com"));
+
+    // Act - Part 2 - Accept it
+    let response = app
+        .accept_invitation(&invitation_link, "new-editor", "a-long-enough-password")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Log in as the new user
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "new-editor",
+            "password": "a-long-enough-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("You are signed in as editor."));
+}
+
+#[tokio::test]
+async fn an_invitation_can_only_be_accepted_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let invitation_link = invite_editor(&app).await;
+    app.accept_invitation(&invitation_link, "new-editor", "a-long-enough-password")
+        .await;
+
+    // Act
+    let response = app
+        .accept_invitation(&invitation_link, "another-editor", "a-long-enough-password")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let row = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.count, 2);
+}
+
+#[tokio::test]
+async fn a_tampered_invitation_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut invitation_link = invite_editor(&app).await;
+    let pairs: Vec<(String, String)> = invitation_link
+        .query_pairs()
+        .into_owned()
+        .map(|(k, v)| {
+            if k == "expires" {
+                (k, (v.parse::<i64>().unwrap() + 3600).to_string())
+            } else {
+                (k, v)
+            }
+        })
+        .collect();
+    invitation_link
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs);
+
+    // Act
+    let response = app
+        .accept_invitation(&invitation_link, "new-editor", "a-long-enough-password")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let row = sqlx::query!("SELECT username FROM users WHERE username = 'new-editor'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(row.is_none());
+}
+
+#[tokio::test]
+async fn a_taken_username_is_reported_on_the_invitation_form() {
+    // Arrange
+    let app = spawn_app().await;
+    let invitation_link = invite_editor(&app).await;
+
+    // Act
+    let response = app
+        .accept_invitation(
+            &invitation_link,
+            &app.test_user.username,
+            "a-long-enough-password",
+        )
+        .await;
+
+    // Assert
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(location.starts_with("/invitations/accept?"));
+    let flash = response
+        .cookies()
+        .find(|c| c.name() == "_flash")
+        .unwrap()
+        .value()
+        .to_string();
+    assert_eq!(flash, "That username is already taken.");
+}
+
+#[tokio::test]
+async fn only_owners_can_invite_users() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE users SET role = 'editor'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_invitation(&serde_json::json!({
+            "email": "someone@example.com",
+            "role": "owner"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn inviting_an_invalid_email_address_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_invitation(&serde_json::json!({
+            "email": "definitely-not-an-email",
+            "role": "editor"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("is not a valid subscriber email"));
+}
+
+#[tokio::test]
+async fn owners_can_change_the_role_of_other_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/users/{}/role", other_user.user_id),
+            &serde_json::json!({ "role": "analyst" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let row = sqlx::query!(
+        "SELECT role FROM users WHERE user_id = $1",
+        other_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(row.role, "analyst");
+}
+
+#[tokio::test]
+async fn owners_cannot_deactivate_themselves() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_form(
+            &format!("/admin/users/{}/deactivate", app.test_user.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("You cannot change the status of your own account."));
+}
+
+#[tokio::test]
+async fn deactivated_users_are_logged_out_and_cannot_log_back_in() {
+    // Arrange - another admin is logged in with their own browser
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    let other_client = anonymous_client();
+    let credentials = serde_json::json!({
+        "username": &other_user.username,
+        "password": &other_user.password
+    });
+    let response = post_login_with(&app, &other_client, &credentials).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - the test user deactivates them
+    app.test_user.login(&app).await;
+    let response = app
+        .post_form(
+            &format!("/admin/users/{}/deactivate", other_user.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Assert - the existing session is gone
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert - logging in again fails
+    let response = post_login_with(&app, &other_client, &credentials).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert - the publishing API is closed too
+    let response = other_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&other_user.username, Some(&other_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+async fn post_login_with(
+    app: &TestApp,
+    client: &reqwest::Client,
+    credentials: &serde_json::Value,
+) -> reqwest::Response {
+    let login_url = format!("{}/login", &app.address);
+    let mut form = credentials.clone();
+    form["csrf_token"] = csrf_token_from(client, &login_url).await.into();
+    client.post(&login_url).form(&form).send().await.unwrap()
+}
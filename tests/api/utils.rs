@@ -1,35 +1,67 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use opentelemetry::sdk::trace::TracerProvider;
+use opentelemetry::trace::TracerProvider as _;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 use zero2prod::startup::get_connection_pool;
 use zero2prod::startup::Application;
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
-    telemetry::{get_subscriber, init_subscriber},
+    configuration::{get_configuration, DatabaseSettings, Settings},
+    telemetry::{get_subscriber, init_subscriber, LogFilter},
 };
 
+// spans get a trace context, so that propagation can be tested, but are
+// not exported anywhere
+static TRACER_PROVIDER: Lazy<TracerProvider> = Lazy::new(TracerProvider::default);
+
 // ensuring the tracing stack is only init'd once
-static TRACING: Lazy<()> = Lazy::new(|| {
+static TRACING: Lazy<LogFilter> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let tracer = Some(TRACER_PROVIDER.tracer("test"));
+    let pii = get_configuration()
+        .expect("Failed to read configuration")
+        .telemetry
+        .pii;
 
     // choosing sink dynamically based on environmental var
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            tracer,
+            &pii,
+        );
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            tracer,
+            &pii,
+        );
         init_subscriber(subscriber);
+        log_filter
     }
     // adding tracing to the testing suite
 });
 
+/// The filter of the test suite's subscriber, shared by every application
+/// spawned by the tests.
+pub fn log_filter() -> LogFilter {
+    TRACING.clone()
+}
+
 pub struct TestApp {
     pub address: String,
     pub port: u16,
+    pub metrics_port: Option<u16>,
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub test_user: TestUser,
@@ -39,7 +71,7 @@ pub struct TestApp {
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -49,7 +81,7 @@ impl TestApp {
 
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
@@ -57,13 +89,178 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_newsletters_with_token(
+        &self,
+        body: serde_json::Value,
+        token: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_tokens<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_form("/admin/api-tokens", body).await
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/api-tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
+        self.post_form("/login", body).await
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.post_form("/admin/logout", &serde_json::json!({}))
+            .await
+    }
+
+    pub async fn get_two_factor(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/login", &self.address))
-            .form(body)
+            .get(format!("{}/admin/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.get_two_factor().await.text().await.unwrap()
+    }
+
+    pub async fn post_enable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.post_form(
+            "/admin/two-factor/enable",
+            &serde_json::json!({ "code": code }),
+        )
+        .await
+    }
+
+    pub async fn post_disable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.post_form(
+            "/admin/two-factor/disable",
+            &serde_json::json!({ "code": code }),
+        )
+        .await
+    }
+
+    pub async fn post_regenerate_recovery_codes(&self, code: &str) -> reqwest::Response {
+        self.post_form(
+            "/admin/two-factor/recovery-codes",
+            &serde_json::json!({ "code": code }),
+        )
+        .await
+    }
+
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.post_form("/login/two-factor", &serde_json::json!({ "code": code }))
+            .await
+    }
+
+    pub async fn get_login_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_settings<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_form("/admin/settings", body).await
+    }
+
+    pub async fn get_admin_users_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_invitation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_form("/admin/users/invitations", body).await
+    }
+
+    /// Submit the invitation form behind `invitation_link`, with a fresh client
+    /// since the invitee is not logged in.
+    pub async fn accept_invitation(
+        &self,
+        invitation_link: &reqwest::Url,
+        username: &str,
+        password: &str,
+    ) -> reqwest::Response {
+        let client = anonymous_client();
+        let csrf_token = csrf_token_from(&client, &format!("{}/login", &self.address)).await;
+        let mut form: Vec<(String, String)> = invitation_link.query_pairs().into_owned().collect();
+        form.push(("username".into(), username.into()));
+        form.push(("password".into(), password.into()));
+        form.push(("password_check".into(), password.into()));
+        form.push(("csrf_token".into(), csrf_token));
+        client
+            .post(format!("{}/invitations/accept", &self.address))
+            .form(&form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// POST an HTML form as the logged in user, with the session's CSRF token.
+    pub async fn post_form<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let csrf_token =
+            csrf_token_from(&self.api_client, &format!("{}/login", &self.address)).await;
+        let mut form = serde_urlencoded::to_string(body).expect("Failed to encode the form.");
+        if !form.is_empty() {
+            form.push('&');
+        }
+        form.push_str(&serde_urlencoded::to_string([("csrf_token", csrf_token)]).unwrap());
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(form)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -71,7 +268,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -95,7 +292,15 @@ impl TestUser {
             password: Uuid::new_v4().to_string(),
         }
     }
-    async fn store(&self, pool: &PgPool) {
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         // match parameters to the default password
         let password_hash = Argon2::new(
@@ -108,8 +313,8 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-        VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, 'owner')",
             self.user_id,
             self.username,
             password_hash,
@@ -149,16 +354,24 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// spawn the application after tweaking its configuration
+pub async fn spawn_app_with<F>(customise: F) -> TestApp
+where
+    F: FnOnce(&mut Settings),
+{
     //setup tracing
-    Lazy::force(&TRACING);
+    let log_filter = log_filter();
 
     // mock server
     let email_server = MockServer::start().await;
@@ -169,18 +382,22 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // emails go to the mock server, not the local mail catcher
+        c.email_client.mailbox = None;
+        customise(&mut c);
         c
     };
     configure_database(&configuration.database).await;
 
     // App/server setup
-    let application = Application::build(configuration.clone())
+    let application = Application::build(configuration.clone(), log_filter)
         .await
         .expect("Failed to build application");
     // launching the server as a background task
     // using tokio spawn to return a handle of a future
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    let metrics_port = application.metrics_port();
+    tokio::spawn(application.run_until_stopped());
 
     // creating reqwest client
     let client = reqwest::Client::builder()
@@ -193,6 +410,7 @@ pub async fn spawn_app() -> TestApp {
     let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
         port: application_port,
+        metrics_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         test_user: TestUser::generate(),
@@ -204,15 +422,7 @@ pub async fn spawn_app() -> TestApp {
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    // create database
-    let mut connection = PgConnection::connect_with(&config.without_db())
-        .await
-        .expect("Failed to connect to Postgres");
-
-    connection
-        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database");
+    create_database(config).await;
 
     // migrate database
     let connection_pool = PgPool::connect_with(config.with_db())
@@ -227,6 +437,45 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
     connection_pool
 }
 
+/// Create the database, without running the migrations.
+pub async fn create_database(config: &DatabaseSettings) {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+        .await
+        .expect("Failed to create database");
+}
+
+/// A client with its own cookie jar, as used by someone who is not logged in.
+pub fn anonymous_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+/// Load a page with a form and read the CSRF token it embeds.
+pub async fn csrf_token_from(client: &reqwest::Client, url: &str) -> String {
+    let html_page = client
+        .get(url)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    html_page
+        .split(r#"name="csrf_token" value=""#)
+        .nth(1)
+        .and_then(|s| s.split('"').next())
+        .expect("The page has no CSRF token.")
+        .to_string()
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
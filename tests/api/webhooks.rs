@@ -0,0 +1,328 @@
+use crate::utils::{spawn_app_with, TestApp};
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::WebhookCredentials;
+
+// the message id of the bounce and subscription change payloads
+const BOUNCED_MESSAGE_ID: &str = "883953f4-6105-42a2-a16a-77a8eac79483";
+
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|c| {
+        c.webhooks.postmark = Some(WebhookCredentials {
+            username: "postmark".into(),
+            password: Secret::new("webhook-password".into()),
+        })
+    })
+    .await
+}
+
+// a payload recorded from Postmark, sent about `email`
+fn payload(name: &str, email: &str) -> serde_json::Value {
+    let recorded = match name {
+        "hard_bounce" => include_str!("fixtures/postmark/hard_bounce.json"),
+        "soft_bounce" => include_str!("fixtures/postmark/soft_bounce.json"),
+        "spam_complaint" => include_str!("fixtures/postmark/spam_complaint.json"),
+        "subscription_change" => include_str!("fixtures/postmark/subscription_change.json"),
+        "delivery" => include_str!("fixtures/postmark/delivery.json"),
+        _ => panic!("No recorded payload named {}", name),
+    };
+    let mut payload: serde_json::Value = serde_json::from_str(recorded).unwrap();
+    let field = match payload["RecordType"].as_str() {
+        Some("SubscriptionChange") | Some("Delivery") => "Recipient",
+        _ => "Email",
+    };
+    payload[field] = email.into();
+    payload
+}
+
+async fn post_webhook(app: &TestApp, payload: &serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/webhooks/postmark", app.address))
+        .basic_auth("postmark", Some("webhook-password"))
+        .json(payload)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn insert_subscriber(app: &TestApp, email: &str, status: &str) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'reader', now(), $3)",
+        subscriber_id,
+        email,
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn insert_delivery(app: &TestApp, subscriber_id: Uuid, message_id: &str) {
+    sqlx::query!(
+        "INSERT INTO email_deliveries (subscriber_id, kind, status, provider_message_id, created_at)
+        VALUES ($1, 'confirmation', 'sent', $2, now())",
+        subscriber_id,
+        message_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn subscriber_status(app: &TestApp, subscriber_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "john@example.com", "confirmed").await;
+    let body = payload("hard_bounce", "john@example.com");
+
+    for password in [None, Some("not-the-password")] {
+        // Act
+        let mut request = app
+            .api_client
+            .post(format!("{}/webhooks/postmark", app.address))
+            .json(&body);
+        if let Some(password) = password {
+            request = request.basic_auth("postmark", Some(password));
+        }
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="webhooks""#
+        );
+    }
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+}
+
+#[tokio::test]
+async fn webhooks_are_not_served_unless_configured() {
+    let app = spawn_app_with(|_| {}).await;
+
+    let response = post_webhook(&app, &payload("hard_bounce", "john@example.com")).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_marks_the_subscriber_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    // found through the message that bounced, whatever the payload's address
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+    insert_delivery(&app, subscriber_id, BOUNCED_MESSAGE_ID).await;
+
+    // Act
+    let response = post_webhook(&app, &payload("hard_bounce", "john@example.com")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "bounced");
+    let delivery = sqlx::query!("SELECT status FROM email_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "bounced");
+    let suppression = sqlx::query!("SELECT email, reason, source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.email, "john@example.com");
+    assert_eq!(suppression.reason, "bounced");
+    assert_eq!(suppression.source, "postmark");
+}
+
+#[tokio::test]
+async fn the_recipient_of_the_message_is_preferred_to_the_address() {
+    // Arrange
+    let app = spawn_app().await;
+    let by_address = insert_subscriber(&app, "john@example.com", "confirmed").await;
+    let by_message = insert_subscriber(&app, "ursula@example.com", "confirmed").await;
+    insert_delivery(&app, by_message, BOUNCED_MESSAGE_ID).await;
+
+    // Act
+    let response = post_webhook(&app, &payload("hard_bounce", "john@example.com")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, by_message).await, "bounced");
+    assert_eq!(subscriber_status(&app, by_address).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_soft_bounce_only_marks_the_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "john@example.com", "confirmed").await;
+    insert_delivery(&app, subscriber_id, BOUNCED_MESSAGE_ID).await;
+
+    // Act
+    let response = post_webhook(&app, &payload("soft_bounce", "john@example.com")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+    let delivery = sqlx::query!("SELECT status FROM email_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "bounced");
+}
+
+#[tokio::test]
+async fn a_spam_complaint_marks_the_subscriber_as_complained() {
+    // Arrange
+    let app = spawn_app().await;
+    // no delivery on record, found by address
+    let subscriber_id = insert_subscriber(&app, "john@example.com", "confirmed").await;
+
+    // Act
+    let response = post_webhook(&app, &payload("spam_complaint", "John@Example.com")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "complained");
+}
+
+#[tokio::test]
+async fn a_manual_suppression_unsubscribes_the_subscriber_for_good() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "john@example.com", "confirmed").await;
+    let mut suppression = payload("subscription_change", "john@example.com");
+    suppression["MessageID"] = serde_json::Value::Null;
+
+    // Act - Part 1 - Suppress
+    let response = post_webhook(&app, &suppression).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "unsubscribed");
+
+    // Act - Part 2 - Reactivate
+    suppression["SuppressSending"] = false.into();
+    let response = post_webhook(&app, &suppression).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn reactivating_a_bounced_address_confirms_the_subscriber_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "john@example.com", "confirmed").await;
+    post_webhook(&app, &payload("hard_bounce", "john@example.com")).await;
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "bounced");
+    let mut reactivation = payload("subscription_change", "john@example.com");
+    reactivation["MessageID"] = serde_json::Value::Null;
+    reactivation["SuppressSending"] = false.into();
+    reactivation["SuppressionReason"] = serde_json::Value::Null;
+
+    // Act
+    let response = post_webhook(&app, &reactivation).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+    let suppressions = sqlx::query!("SELECT count(*) AS \"count!\" FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressions.count, 0);
+}
+
+#[tokio::test]
+async fn reactivating_a_bounced_address_does_not_confirm_a_pending_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "john@example.com", "pending_confirmation").await;
+    post_webhook(&app, &payload("hard_bounce", "john@example.com")).await;
+    assert_eq!(
+        subscriber_status(&app, subscriber_id).await,
+        "pending_confirmation"
+    );
+    let mut reactivation = payload("subscription_change", "john@example.com");
+    reactivation["MessageID"] = serde_json::Value::Null;
+    reactivation["SuppressSending"] = false.into();
+    reactivation["SuppressionReason"] = serde_json::Value::Null;
+
+    // Act
+    let response = post_webhook(&app, &reactivation).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        subscriber_status(&app, subscriber_id).await,
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn webhooks_about_unknown_addresses_and_other_events_are_acknowledged() {
+    let app = spawn_app().await;
+
+    for body in [
+        payload("hard_bounce", "stranger@example.com"),
+        payload("delivery", "john@example.com"),
+    ] {
+        let response = post_webhook(&app, &body).await;
+
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn malformed_webhooks_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = post_webhook(&app, &serde_json::json!({"RecordType": "Bounce"})).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_sent_to_bounced_or_complained_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "john@example.com", "confirmed").await;
+    insert_subscriber(&app, "jane@example.com", "confirmed").await;
+    post_webhook(&app, &payload("hard_bounce", "john@example.com")).await;
+    post_webhook(&app, &payload("spam_complaint", "jane@example.com")).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}